@@ -0,0 +1,189 @@
+//! Derive macros for [r2r-msg-ext](https://github.com/jerry73204/r2r-msg-ext).
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, spanned::Spanned, Data, DeriveInput, Error, Fields, LitStr};
+
+/// Derives `PointCloud2Point` for a `#[repr(C)]` struct with named
+/// fields. Each struct field becomes a point field with the same name
+/// at the same offset. The field types must implement
+/// `PointFieldValue`, which covers numbers and arrays of numbers.
+///
+/// A point field can be renamed by `#[point_field(name = "...")]`.
+/// Packed structs are rejected, since their fields can be unaligned.
+///
+/// The generated code refers to the crate as `::r2r_msg_ext`. If the
+/// crate is renamed or re-exported, give its path by
+/// `#[point(crate = "...")]`.
+///
+/// ```ignore
+/// #[derive(Clone, Copy, PointCloud2Point)]
+/// #[repr(C)]
+/// struct XyzRgb {
+///     x: f32,
+///     y: f32,
+///     z: f32,
+///     #[point_field(name = "rgb")]
+///     color: f32,
+/// }
+/// ```
+#[proc_macro_derive(PointCloud2Point, attributes(point, point_field))]
+pub fn derive_point_cloud2_point(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match impl_point_cloud2_point(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn impl_point_cloud2_point(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let Data::Struct(data) = &input.data else {
+        return Err(Error::new(
+            input.span(),
+            "PointCloud2Point can only be derived for structs",
+        ));
+    };
+    let Fields::Named(fields) = &data.fields else {
+        return Err(Error::new(
+            data.fields.span(),
+            "PointCloud2Point requires a struct with named fields",
+        ));
+    };
+    if !is_repr_c(input)? {
+        return Err(Error::new(
+            input.ident.span(),
+            "PointCloud2Point requires #[repr(C)] to have a stable field layout",
+        ));
+    }
+
+    let idents: Vec<_> = fields
+        .named
+        .iter()
+        .map(|field| field.ident.clone().unwrap())
+        .collect();
+    let types: Vec<_> = fields.named.iter().map(|field| &field.ty).collect();
+    let names: Vec<_> = fields
+        .named
+        .iter()
+        .map(|field| {
+            let name = point_field_name(field)?.unwrap_or_else(|| {
+                LitStr::new(&field.ident.as_ref().unwrap().to_string(), field.span())
+            });
+            syn::Result::Ok(name)
+        })
+        .collect::<syn::Result<_>>()?;
+    let indices = 0..idents.len();
+
+    let krate = crate_path(input)?;
+    let value_trait = quote! { #krate::sensor_msgs::msg::PointFieldValue };
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let tokens = quote! {
+        impl #impl_generics #krate::sensor_msgs::msg::PointCloud2Point for #ident #ty_generics #where_clause {
+            fn point_fields() -> ::std::vec::Vec<#krate::__private::PointField> {
+                ::std::vec![
+                    #(
+                        <#types as #value_trait>::point_field(
+                            #names,
+                            ::core::mem::offset_of!(Self, #idents),
+                        ),
+                    )*
+                ]
+            }
+
+            fn point_step() -> u32 {
+                ::core::mem::size_of::<Self>() as u32
+            }
+
+            fn read_point(point: &[u8], offsets: &[usize], is_bigendian: bool) -> Self {
+                Self {
+                    #(
+                        #idents: <#types as #value_trait>::read_field(
+                            &point[offsets[#indices]..],
+                            is_bigendian,
+                        ),
+                    )*
+                }
+            }
+
+            fn write_point(&self, point: &mut [u8], is_bigendian: bool) {
+                #(
+                    <#types as #value_trait>::write_field(
+                        &self.#idents,
+                        &mut point[::core::mem::offset_of!(Self, #idents)..],
+                        is_bigendian,
+                    );
+                )*
+            }
+        }
+    };
+
+    Ok(tokens)
+}
+
+fn is_repr_c(input: &DeriveInput) -> syn::Result<bool> {
+    let mut is_repr_c = false;
+
+    for attr in &input.attrs {
+        if !attr.path().is_ident("repr") {
+            continue;
+        }
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("C") {
+                is_repr_c = true;
+            } else if meta.path.is_ident("packed") {
+                return Err(meta.error("PointCloud2Point does not support packed structs"));
+            } else if meta.input.peek(syn::token::Paren) {
+                // Skip arguments like align(8).
+                let _content;
+                syn::parenthesized!(_content in meta.input);
+            }
+            Ok(())
+        })?;
+    }
+
+    Ok(is_repr_c)
+}
+
+fn crate_path(input: &DeriveInput) -> syn::Result<syn::Path> {
+    let mut krate = None;
+
+    for attr in &input.attrs {
+        if !attr.path().is_ident("point") {
+            continue;
+        }
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("crate") {
+                let path: LitStr = meta.value()?.parse()?;
+                krate = Some(path.parse()?);
+                Ok(())
+            } else {
+                Err(meta.error("unsupported point attribute"))
+            }
+        })?;
+    }
+
+    Ok(krate.unwrap_or_else(|| syn::parse_quote! { ::r2r_msg_ext }))
+}
+
+fn point_field_name(field: &syn::Field) -> syn::Result<Option<LitStr>> {
+    let mut name = None;
+
+    for attr in &field.attrs {
+        if !attr.path().is_ident("point_field") {
+            continue;
+        }
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                name = Some(meta.value()?.parse()?);
+                Ok(())
+            } else {
+                Err(meta.error("unsupported point_field attribute"))
+            }
+        })?;
+    }
+
+    Ok(name)
+}
@@ -7,10 +7,22 @@
 //! - [nalgebra](https://docs.rs/nalgebra/)
 //! - [opencv](https://docs.rs/opencv/)
 //! - [arrow](https://docs.rs/arrow/)
+//! - [image](https://docs.rs/image/)
+//! - [ndarray](https://docs.rs/ndarray/)
+//! - [candle](https://docs.rs/candle-core/)
+//!
+//! The `derive` feature provides `#[derive(PointCloud2Point)]` to
+//! decode and encode point clouds with user-defined point types.
 
 pub mod geometry_msgs;
 pub mod sensor_msgs;
 
+#[cfg(feature = "derive")]
+#[doc(hidden)]
+pub mod __private {
+    pub use r2r::sensor_msgs::msg::PointField;
+}
+
 pub mod prelude {
     pub use crate::geometry_msgs::msg::*;
     pub use crate::sensor_msgs::msg::*;
@@ -13,5 +13,20 @@ pub use with_arrow::*;
 #[cfg(feature = "with-arrow")]
 mod with_arrow;
 
+#[cfg(feature = "with-image")]
+pub use with_image::*;
+#[cfg(feature = "with-image")]
+mod with_image;
+
+#[cfg(feature = "with-ndarray")]
+pub use with_ndarray::*;
+#[cfg(feature = "with-ndarray")]
+mod with_ndarray;
+
+#[cfg(feature = "with-candle")]
+pub use with_candle::*;
+#[cfg(feature = "with-candle")]
+mod with_candle;
+
 pub use with_std::*;
 mod with_std;
@@ -1,3 +1,4 @@
+use super::{PointFieldLayout, PointFieldType};
 use anyhow::{anyhow, bail, ensure, Result};
 use arrow::{
     array::{
@@ -8,8 +9,7 @@ use arrow::{
     datatypes::{DataType, Field},
 };
 use itertools::{izip, Itertools};
-use num_derive::FromPrimitive;
-use num_traits::{FromPrimitive, ToBytes};
+use num_traits::ToBytes;
 use r2r::{
     sensor_msgs::msg::{PointCloud2, PointField},
     std_msgs::msg::Header,
@@ -121,9 +121,9 @@ impl PointCloud2ArrowExt for PointCloud2 {
                     count,
                 } = *field;
 
-                let datatype = RosDataType::from_u8(datatype)
+                let datatype = PointFieldType::from_u8(datatype)
                     .ok_or_else(|| anyhow!("Unsupported datatype {datatype}"))?;
-                let arrow_datatype = datatype.to_arrow_datatype();
+                let arrow_datatype = to_arrow_datatype(datatype);
                 let size = datatype.size();
                 let field = Field::new(name, arrow_datatype, false);
 
@@ -161,7 +161,7 @@ impl PointCloud2ArrowExt for PointCloud2 {
                 });
 
                 use DataType as D;
-                use RosDataType as T;
+                use PointFieldType as T;
 
                 let array: ArrayRef = if count == 1 {
                     match datatype {
@@ -213,45 +213,27 @@ impl PointCloud2ArrowExt for PointCloud2 {
 
     fn from_arrow_array(header: Header, array: &StructArray) -> Result<Self> {
         let is_be = cfg!(target_endian = "big");
-        let mut offset = 0;
-
-        let fields: Vec<_> = izip!(array.column_names(), array.columns())
-            .map(|(name, col): (&str, _)| {
-                let dt = col.data_type();
-
-                let point_field = if let Some(ros_dt) = RosDataType::from_arrow_datatype(dt) {
-                    let field = PointField {
-                        name: name.to_string(),
-                        offset: offset as u32,
-                        datatype: ros_dt as u8,
-                        count: 1,
-                    };
-                    offset += dt.size();
-                    field
-                } else {
-                    let DataType::FixedSizeList(ref field_ty, count) = *dt else {
-                        bail!("Unsupported data type {dt}",);
-                    };
-                    let field_dt = field_ty.data_type();
-                    let Some(ros_dt) = RosDataType::from_arrow_datatype(field_dt) else {
-                        bail!("Unsupported data type {field_dt}",);
-                    };
-
-                    let field = PointField {
-                        name: name.to_string(),
-                        offset: offset as u32,
-                        datatype: ros_dt as u8,
-                        count: count as u32,
-                    };
-                    offset += field_dt.size() * count as usize;
-                    field
-                };
+        let mut layout = PointFieldLayout::new();
 
-                Ok(point_field)
-            })
-            .try_collect()?;
+        for (name, col) in izip!(array.column_names(), array.columns()) {
+            let dt = col.data_type();
 
-        let point_step = offset;
+            if let Some(ros_dt) = from_arrow_datatype(dt) {
+                layout.push(name, ros_dt, 1);
+            } else {
+                let DataType::FixedSizeList(ref field_ty, count) = *dt else {
+                    bail!("Unsupported data type {dt}",);
+                };
+                let field_dt = field_ty.data_type();
+                let Some(ros_dt) = from_arrow_datatype(field_dt) else {
+                    bail!("Unsupported data type {field_dt}",);
+                };
+                layout.push(name, ros_dt, count as u32);
+            }
+        }
+
+        let point_step = layout.point_step() as usize;
+        let fields = layout.into_fields();
         let row_step = point_step;
         let height = array.len();
         let mut data = vec![0u8; height * row_step];
@@ -393,63 +375,99 @@ impl PointCloud2ArrowExt for PointCloud2 {
 #[derive(Debug, Clone, PartialEq, Eq)]
 struct FieldDesc {
     field: Field,
-    datatype: RosDataType,
+    datatype: PointFieldType,
     offset: usize,
     data_size: usize,
     count: usize,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive)]
-#[repr(u8)]
-enum RosDataType {
-    I8 = 1,
-    U8 = 2,
-    I16 = 3,
-    U16 = 4,
-    I32 = 5,
-    U32 = 6,
-    F32 = 7,
-    F64 = 8,
+fn to_arrow_datatype(datatype: PointFieldType) -> DataType {
+    match datatype {
+        PointFieldType::I8 => DataType::Int8,
+        PointFieldType::U8 => DataType::UInt8,
+        PointFieldType::I16 => DataType::Int16,
+        PointFieldType::U16 => DataType::UInt16,
+        PointFieldType::I32 => DataType::Int32,
+        PointFieldType::U32 => DataType::UInt32,
+        PointFieldType::F32 => DataType::Float32,
+        PointFieldType::F64 => DataType::Float64,
+    }
+}
+
+fn from_arrow_datatype(dt: &DataType) -> Option<PointFieldType> {
+    Some(match dt {
+        DataType::Int8 => PointFieldType::I8,
+        DataType::UInt8 => PointFieldType::U8,
+        DataType::Int16 => PointFieldType::I16,
+        DataType::UInt16 => PointFieldType::U16,
+        DataType::Int32 => PointFieldType::I32,
+        DataType::UInt32 => PointFieldType::U32,
+        DataType::Float32 => PointFieldType::F32,
+        DataType::Float64 => PointFieldType::F64,
+        _ => return None,
+    })
 }
 
-impl RosDataType {
-    pub fn size(&self) -> usize {
-        match self {
-            RosDataType::I8 => 1,
-            RosDataType::U8 => 1,
-            RosDataType::I16 => 2,
-            RosDataType::U16 => 2,
-            RosDataType::I32 => 4,
-            RosDataType::U32 => 4,
-            RosDataType::F32 => 4,
-            RosDataType::F64 => 8,
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATATYPES: [(PointFieldType, DataType); 8] = [
+        (PointFieldType::I8, DataType::Int8),
+        (PointFieldType::U8, DataType::UInt8),
+        (PointFieldType::I16, DataType::Int16),
+        (PointFieldType::U16, DataType::UInt16),
+        (PointFieldType::I32, DataType::Int32),
+        (PointFieldType::U32, DataType::UInt32),
+        (PointFieldType::F32, DataType::Float32),
+        (PointFieldType::F64, DataType::Float64),
+    ];
+
+    #[test]
+    fn arrow_datatypes() {
+        for (datatype, arrow_datatype) in DATATYPES {
+            assert_eq!(to_arrow_datatype(datatype), arrow_datatype);
+            assert_eq!(from_arrow_datatype(&arrow_datatype), Some(datatype));
         }
+        assert_eq!(from_arrow_datatype(&DataType::Utf8), None);
     }
 
-    pub fn to_arrow_datatype(&self) -> DataType {
-        match self {
-            RosDataType::I8 => DataType::Int8,
-            RosDataType::U8 => DataType::UInt8,
-            RosDataType::I16 => DataType::Int16,
-            RosDataType::U16 => DataType::UInt8,
-            RosDataType::I32 => DataType::Int32,
-            RosDataType::U32 => DataType::UInt32,
-            RosDataType::F32 => DataType::Float32,
-            RosDataType::F64 => DataType::Float64,
+    #[test]
+    fn arrow_array_columns() {
+        // One field of each datatype.
+        let mut layout = PointFieldLayout::new();
+        for (idx, (datatype, _)) in DATATYPES.into_iter().enumerate() {
+            layout.push(&format!("f{idx}"), datatype, 1);
         }
-    }
+        let point_step = layout.point_step();
+        let width = 2;
 
-    pub fn from_arrow_datatype(dt: &DataType) -> Option<Self> {
-        Some(match dt {
-            DataType::Int8 => Self::I8,
-            DataType::UInt8 => Self::U8,
-            DataType::Int16 => Self::I16,
-            DataType::UInt16 => Self::U16,
-            DataType::Int32 => Self::I32,
-            DataType::UInt32 => Self::U32,
-            DataType::Float32 => Self::F32,
-            DataType::Float64 => Self::F64,
-            _ => return None,
-        })
+        let cloud = PointCloud2 {
+            height: 1,
+            width,
+            fields: layout.into_fields(),
+            is_bigendian: false,
+            point_step,
+            row_step: point_step * width,
+            data: (0..point_step * width).map(|idx| idx as u8).collect(),
+            is_dense: true,
+            ..Default::default()
+        };
+
+        let array = cloud.to_arrow_array().unwrap();
+        assert_eq!(array.len(), width as usize);
+        for (idx, (_, arrow_datatype)) in DATATYPES.into_iter().enumerate() {
+            let column = array.column_by_name(&format!("f{idx}")).unwrap();
+            assert_eq!(*column.data_type(), arrow_datatype);
+        }
+
+        // UINT16 fields keep their values.
+        let u16_offset = cloud.fields[3].offset as usize;
+        let column: &UInt16Array = array.column_by_name("f3").unwrap().as_primitive();
+        let expect = |offset: usize| u16::from_le_bytes([offset as u8, offset as u8 + 1]);
+        assert_eq!(
+            &column.values()[..],
+            [expect(u16_offset), expect(point_step as usize + u16_offset)]
+        );
     }
 }
@@ -0,0 +1,380 @@
+use super::{check_image, image_resize, ImageEncoding, ImageExt, Interpolation};
+use anyhow::{bail, ensure, Result};
+use candle_core::{DType, Device, Tensor};
+use r2r::{sensor_msgs::msg::Image, std_msgs::msg::Header};
+use std::{borrow::Cow, slice};
+
+/// The dimension order of image tensors.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum TensorLayout {
+    /// (batch, channels, height, width)
+    #[default]
+    Nchw,
+    /// (batch, height, width, channels)
+    Nhwc,
+}
+
+/// Resizes images to a fixed size, keeping the aspect ratio and
+/// padding the borders.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Letterbox {
+    pub width: u32,
+    pub height: u32,
+    /// The value of padded pixels in 8-bit scale. The alpha channel,
+    /// if any, is padded opaque instead.
+    pub pad_value: u8,
+}
+
+impl Letterbox {
+    /// Creates a letterbox with the gray padding used by YOLO models.
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pad_value: 114,
+        }
+    }
+
+    /// Computes where an image of the given size is placed in the
+    /// letterbox.
+    pub fn transform(&self, width: u32, height: u32) -> LetterboxTransform {
+        let scale = (self.width as f64 / width as f64).min(self.height as f64 / height as f64);
+        let resized_width = ((width as f64 * scale).round() as u32).min(self.width);
+        let resized_height = ((height as f64 * scale).round() as u32).min(self.height);
+
+        LetterboxTransform {
+            scale,
+            offset_x: (self.width - resized_width) / 2,
+            offset_y: (self.height - resized_height) / 2,
+            width: resized_width,
+            height: resized_height,
+        }
+    }
+}
+
+/// The placement of an image in a letterbox.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LetterboxTransform {
+    /// The resize ratio of the image.
+    pub scale: f64,
+    pub offset_x: u32,
+    pub offset_y: u32,
+    /// The width of the resized image.
+    pub width: u32,
+    /// The height of the resized image.
+    pub height: u32,
+}
+
+impl LetterboxTransform {
+    /// Maps a point in the letterbox, such as a detected box corner,
+    /// back to the original image.
+    pub fn to_source(&self, x: f64, y: f64) -> (f64, f64) {
+        (
+            (x - self.offset_x as f64) / self.scale,
+            (y - self.offset_y as f64) / self.scale,
+        )
+    }
+}
+
+/// Options of image to tensor conversion.
+///
+/// Each sample is computed as `(value * scale - mean[c]) / std[c]`,
+/// where `value` is the 8-bit or 16-bit pixel value in the target
+/// encoding. Empty `mean` and `std` skip the normalization.
+#[derive(Debug, Clone)]
+pub struct TensorOptions {
+    /// The encoding images are converted to before conversion, such as
+    /// "rgb8", "bgr8" or "mono8".
+    pub encoding: String,
+    pub layout: TensorLayout,
+    /// The tensor data type, either `F32` or `F16`.
+    pub dtype: DType,
+    pub scale: f32,
+    pub mean: Vec<f32>,
+    pub std: Vec<f32>,
+    pub letterbox: Option<Letterbox>,
+    pub device: Device,
+}
+
+impl Default for TensorOptions {
+    fn default() -> Self {
+        Self {
+            encoding: "rgb8".to_string(),
+            layout: TensorLayout::Nchw,
+            dtype: DType::F32,
+            scale: 1.0 / 255.0,
+            mean: vec![],
+            std: vec![],
+            letterbox: None,
+            device: Device::Cpu,
+        }
+    }
+}
+
+impl TensorOptions {
+    /// Options with the ImageNet mean and standard deviation of RGB
+    /// channels.
+    pub fn imagenet() -> Self {
+        Self {
+            mean: vec![0.485, 0.456, 0.406],
+            std: vec![0.229, 0.224, 0.225],
+            ..Self::default()
+        }
+    }
+}
+
+pub trait ImageCandleExt
+where
+    Self: Sized,
+{
+    /// Converts the image to a tensor with a batch size of 1.
+    fn to_tensor(&self, options: &TensorOptions) -> Result<Tensor>;
+
+    fn from_mask_tensor(mask: &Tensor, header: Header) -> Result<Self>;
+}
+
+impl ImageCandleExt for Image {
+    fn to_tensor(&self, options: &TensorOptions) -> Result<Tensor> {
+        images_to_tensor(slice::from_ref(self), options)
+    }
+
+    fn from_mask_tensor(mask: &Tensor, header: Header) -> Result<Self> {
+        mask_tensor_to_image(mask, header)
+    }
+}
+
+/// Converts a batch of images to a tensor. All images must have the
+/// same size after the optional letterbox resize.
+pub fn images_to_tensor(images: &[Image], options: &TensorOptions) -> Result<Tensor> {
+    let TensorOptions {
+        layout,
+        dtype,
+        ref device,
+        ..
+    } = *options;
+    ensure!(
+        matches!(dtype, DType::F32 | DType::F16),
+        "unsupported tensor data type {dtype:?}"
+    );
+
+    let mut data = vec![];
+    let mut shape = None;
+
+    for image in images {
+        let (height, width, channels) = image_to_samples(image, options, &mut data)?;
+
+        match shape {
+            None => shape = Some((height, width, channels)),
+            Some(shape) => ensure!(
+                shape == (height, width, channels),
+                "All images must have the same size. Expect {}x{}, but get {width}x{height}.",
+                shape.1,
+                shape.0
+            ),
+        }
+    }
+
+    let batch = images.len();
+    let (height, width, channels) = shape.unwrap_or((0, 0, 0));
+    let tensor = match layout {
+        TensorLayout::Nchw => Tensor::from_vec(data, (batch, channels, height, width), device)?,
+        TensorLayout::Nhwc => Tensor::from_vec(data, (batch, height, width, channels), device)?,
+    };
+
+    Ok(tensor.to_dtype(dtype)?)
+}
+
+/// Converts a segmentation mask to a mono8 image.
+///
+/// The mask is either a tensor of class indices of shape (height,
+/// width), or class scores of shape (classes, height, width), for
+/// which the class with the maximum score is taken. Leading
+/// dimensions of size 1 are removed. Indices above 255 are clamped.
+pub fn mask_tensor_to_image(mask: &Tensor, header: Header) -> Result<Image> {
+    let mut mask = mask.clone();
+    while mask.rank() > 2 && mask.dims()[0] == 1 {
+        mask = mask.squeeze(0)?;
+    }
+
+    let mask = match mask.rank() {
+        2 => mask,
+        3 => mask.argmax(0)?,
+        _ => bail!(
+            "Expect a mask of shape (H, W) or (C, H, W), but get {:?}",
+            mask.dims()
+        ),
+    };
+
+    let indices: Vec<Vec<u32>> = mask.to_dtype(DType::U32)?.to_vec2()?;
+    let height = indices.len();
+    let width = indices.first().map(|row| row.len()).unwrap_or(0);
+    let data: Vec<u8> = indices
+        .into_iter()
+        .flatten()
+        .map(|index| index.min(u8::MAX as u32) as u8)
+        .collect();
+
+    Ok(Image {
+        header,
+        height: height as u32,
+        width: width as u32,
+        encoding: ImageEncoding::Mono8.to_string(),
+        is_bigendian: cfg!(target_endian = "big") as u8,
+        step: width as u32,
+        data,
+    })
+}
+
+/// Appends the normalized samples of an image in the tensor layout
+/// and returns the (height, width, channels) of the image.
+fn image_to_samples(
+    image: &Image,
+    options: &TensorOptions,
+    output: &mut Vec<f32>,
+) -> Result<(usize, usize, usize)> {
+    let TensorOptions {
+        ref encoding,
+        layout,
+        scale,
+        ref mean,
+        ref std,
+        ref letterbox,
+        ..
+    } = *options;
+
+    let target: ImageEncoding = encoding.parse()?;
+    ensure!(
+        target.is_color() || target.is_mono(),
+        "The tensor encoding must be a color or mono encoding, but get '{target}'"
+    );
+    let channels = target.channels() as usize;
+    ensure!(
+        mean.is_empty() || mean.len() == channels,
+        "Expect {channels} mean values, but get {}",
+        mean.len()
+    );
+    ensure!(
+        std.is_empty() || std.len() == channels,
+        "Expect {channels} std values, but get {}",
+        std.len()
+    );
+
+    let image = if check_image(image)? == target {
+        Cow::Borrowed(image)
+    } else {
+        Cow::Owned(image.convert_encoding(encoding)?)
+    };
+    let image = match letterbox {
+        Some(letterbox) => Cow::Owned(image_letterbox(&image, letterbox)?),
+        None => image,
+    };
+
+    let Image {
+        height,
+        width,
+        step: row_step,
+        ref data,
+        is_bigendian,
+        ..
+    } = *image;
+    let height = height as usize;
+    let width = width as usize;
+    let elem_size = target.channel_type().size();
+    let is_bigendian = is_bigendian != 0;
+    let begin = output.len();
+    output.resize(begin + height * width * channels, 0.0);
+    let output = &mut output[begin..];
+
+    for (y, row) in data.chunks(row_step as usize).take(height).enumerate() {
+        let samples = row[0..width * channels * elem_size].chunks_exact(elem_size);
+
+        for (idx, bytes) in samples.enumerate() {
+            let x = idx / channels;
+            let c = idx % channels;
+            let value = match *bytes {
+                [val] => val as f32,
+                [b0, b1] if is_bigendian => u16::from_be_bytes([b0, b1]) as f32,
+                [b0, b1] => u16::from_le_bytes([b0, b1]) as f32,
+                _ => unreachable!(),
+            };
+
+            let mut value = value * scale;
+            if !mean.is_empty() {
+                value -= mean[c];
+            }
+            if !std.is_empty() {
+                value /= std[c];
+            }
+
+            let index = match layout {
+                TensorLayout::Nchw => (c * height + y) * width + x,
+                TensorLayout::Nhwc => (y * width + x) * channels + c,
+            };
+            output[index] = value;
+        }
+    }
+
+    Ok((height, width, channels))
+}
+
+/// Resizes an image into a letterbox with bilinear interpolation.
+fn image_letterbox(image: &Image, letterbox: &Letterbox) -> Result<Image> {
+    ensure!(
+        letterbox.width > 0 && letterbox.height > 0,
+        "The letterbox size must be positive, but get {}x{}",
+        letterbox.width,
+        letterbox.height
+    );
+    ensure!(
+        image.width > 0 && image.height > 0,
+        "Unable to letterbox an empty image"
+    );
+    let transform = letterbox.transform(image.width, image.height);
+    ensure!(
+        transform.width > 0 && transform.height > 0,
+        "The {}x{} image is resized to zero size in the {}x{} letterbox",
+        image.width,
+        image.height,
+        letterbox.width,
+        letterbox.height
+    );
+
+    let resized = image_resize(
+        image,
+        transform.width,
+        transform.height,
+        Interpolation::Bilinear,
+    )?;
+    let encoding = check_image(&resized)?;
+    let pixel_size = encoding.bytes_per_pixel();
+    let row_step = letterbox.width as usize * pixel_size;
+    let row_size = transform.width as usize * pixel_size;
+    let offset = transform.offset_x as usize * pixel_size;
+
+    // Filling every byte with the 8-bit value also scales it to 16
+    // bits, since v * 257 has two bytes of v.
+    let mut pad_pixel = vec![letterbox.pad_value; pixel_size];
+    if encoding.has_alpha() {
+        let alpha_size = encoding.channel_type().size();
+        pad_pixel[pixel_size - alpha_size..].fill(u8::MAX);
+    }
+    let mut data = pad_pixel.repeat(letterbox.width as usize * letterbox.height as usize);
+
+    data.chunks_exact_mut(row_step)
+        .skip(transform.offset_y as usize)
+        .zip(resized.data.chunks(resized.step.max(1) as usize))
+        .take(transform.height as usize)
+        .for_each(|(dst, src)| {
+            dst[offset..offset + row_size].copy_from_slice(&src[0..row_size]);
+        });
+
+    Ok(Image {
+        header: resized.header,
+        height: letterbox.height,
+        width: letterbox.width,
+        encoding: resized.encoding,
+        is_bigendian: resized.is_bigendian,
+        step: row_step as u32,
+        data,
+    })
+}
@@ -0,0 +1,439 @@
+use super::{
+    check_image, restore_encoding, ChannelType, CompressedFormat, CompressionFormat, ImageEncoding,
+    ImageExt,
+};
+use anyhow::{anyhow, bail, Result};
+use image::{
+    codecs::png::{CompressionType, FilterType, PngEncoder},
+    ColorType, DynamicImage, ImageBuffer, ImageEncoder, ImageFormat, ImageOutputFormat, Luma,
+    LumaA, Pixel, Rgb, Rgba,
+};
+use r2r::{
+    sensor_msgs::msg::{CompressedImage, Image},
+    std_msgs::msg::Header,
+};
+use std::{borrow::Cow, io::Cursor, ops::Deref};
+
+/// Pixel types of the image crate that have a ROS image encoding.
+pub trait RosPixel: Pixel {
+    /// The image encoding of the pixel type.
+    const ENCODING: ImageEncoding;
+
+    /// Decodes a subpixel from bytes in the given byte order.
+    fn read_subpixel(bytes: &[u8], is_bigendian: bool) -> Self::Subpixel;
+
+    /// Encodes a subpixel to bytes in the native byte order.
+    fn write_subpixel(value: Self::Subpixel, bytes: &mut [u8]);
+}
+
+macro_rules! impl_ros_pixel {
+    ($pixel:ty, $subpixel:ty, $encoding:expr) => {
+        impl RosPixel for $pixel {
+            const ENCODING: ImageEncoding = $encoding;
+
+            fn read_subpixel(bytes: &[u8], is_bigendian: bool) -> $subpixel {
+                let array = bytes.try_into().unwrap();
+                if is_bigendian {
+                    <$subpixel>::from_be_bytes(array)
+                } else {
+                    <$subpixel>::from_le_bytes(array)
+                }
+            }
+
+            fn write_subpixel(value: $subpixel, bytes: &mut [u8]) {
+                bytes.copy_from_slice(&value.to_ne_bytes());
+            }
+        }
+    };
+}
+
+impl_ros_pixel!(Luma<u8>, u8, ImageEncoding::Mono8);
+impl_ros_pixel!(Luma<u16>, u16, ImageEncoding::Mono16);
+impl_ros_pixel!(Rgb<u8>, u8, ImageEncoding::Rgb8);
+impl_ros_pixel!(Rgb<u16>, u16, ImageEncoding::Rgb16);
+impl_ros_pixel!(Rgba<u8>, u8, ImageEncoding::Rgba8);
+impl_ros_pixel!(Rgba<u16>, u16, ImageEncoding::Rgba16);
+impl_ros_pixel!(
+    LumaA<u8>,
+    u8,
+    ImageEncoding::Generic {
+        channel_type: ChannelType::U8,
+        channels: 2
+    }
+);
+impl_ros_pixel!(
+    LumaA<u16>,
+    u16,
+    ImageEncoding::Generic {
+        channel_type: ChannelType::U16,
+        channels: 2
+    }
+);
+impl_ros_pixel!(
+    Luma<f32>,
+    f32,
+    ImageEncoding::Generic {
+        channel_type: ChannelType::F32,
+        channels: 1
+    }
+);
+impl_ros_pixel!(
+    Rgb<f32>,
+    f32,
+    ImageEncoding::Generic {
+        channel_type: ChannelType::F32,
+        channels: 3
+    }
+);
+impl_ros_pixel!(
+    Rgba<f32>,
+    f32,
+    ImageEncoding::Generic {
+        channel_type: ChannelType::F32,
+        channels: 4
+    }
+);
+
+pub trait ImageImageExt
+where
+    Self: Sized,
+{
+    fn to_image_buffer<P>(&self) -> Result<ImageBuffer<P, Vec<P::Subpixel>>>
+    where
+        P: RosPixel;
+
+    fn from_image_buffer<P, C>(buffer: &ImageBuffer<P, C>, header: Header) -> Result<Self>
+    where
+        P: RosPixel,
+        C: Deref<Target = [P::Subpixel]>;
+
+    fn to_dynamic_image(&self) -> Result<DynamicImage>;
+
+    fn from_dynamic_image(image: &DynamicImage, header: Header) -> Result<Self>;
+}
+
+impl ImageImageExt for Image {
+    fn to_image_buffer<P>(&self) -> Result<ImageBuffer<P, Vec<P::Subpixel>>>
+    where
+        P: RosPixel,
+    {
+        image_to_image_buffer(self)
+    }
+
+    fn from_image_buffer<P, C>(buffer: &ImageBuffer<P, C>, header: Header) -> Result<Self>
+    where
+        P: RosPixel,
+        C: Deref<Target = [P::Subpixel]>,
+    {
+        image_buffer_to_image(buffer, header)
+    }
+
+    fn to_dynamic_image(&self) -> Result<DynamicImage> {
+        image_to_dynamic_image(self)
+    }
+
+    fn from_dynamic_image(image: &DynamicImage, header: Header) -> Result<Self> {
+        dynamic_image_to_image(image, header)
+    }
+}
+
+pub trait CompressedImageImageExt
+where
+    Self: Sized,
+{
+    fn to_dynamic_image(&self) -> Result<DynamicImage>;
+
+    fn from_dynamic_image(
+        image: &DynamicImage,
+        format: CompressionFormat,
+        quality: u8,
+        header: Header,
+    ) -> Result<Self>;
+}
+
+impl CompressedImageImageExt for CompressedImage {
+    fn to_dynamic_image(&self) -> Result<DynamicImage> {
+        compressed_image_to_dynamic_image(self)
+    }
+
+    fn from_dynamic_image(
+        image: &DynamicImage,
+        format: CompressionFormat,
+        quality: u8,
+        header: Header,
+    ) -> Result<Self> {
+        dynamic_image_to_compressed_image(image, None, format, quality, header)
+    }
+}
+
+/// Converts a ROS image to an image buffer of the pixel type `P`.
+///
+/// Images in other color, mono, Bayer or YUV encodings are converted
+/// to the encoding of `P` first. Row padding is dropped.
+fn image_to_image_buffer<P>(image: &Image) -> Result<ImageBuffer<P, Vec<P::Subpixel>>>
+where
+    P: RosPixel,
+{
+    let encoding = check_image(image)?;
+    let image = if encoding == P::ENCODING {
+        Cow::Borrowed(image)
+    } else {
+        Cow::Owned(image.convert_encoding(&P::ENCODING.to_string())?)
+    };
+
+    let Image {
+        height,
+        width,
+        step: row_step,
+        ref data,
+        is_bigendian,
+        ..
+    } = *image;
+    let is_bigendian = is_bigendian != 0;
+    let elem_size = P::ENCODING.channel_type().size();
+    let row_size = P::ENCODING.step(width)? as usize;
+
+    let subpixels: Vec<P::Subpixel> = data
+        .chunks(row_step as usize)
+        .take(height as usize)
+        .flat_map(|row| row[0..row_size].chunks_exact(elem_size))
+        .map(|bytes| P::read_subpixel(bytes, is_bigendian))
+        .collect();
+
+    ImageBuffer::from_raw(width, height, subpixels)
+        .ok_or_else(|| anyhow!("unable to create a {width}x{height} image buffer"))
+}
+
+/// Converts an image buffer to a ROS image in the encoding of the
+/// pixel type.
+fn image_buffer_to_image<P, C>(buffer: &ImageBuffer<P, C>, header: Header) -> Result<Image>
+where
+    P: RosPixel,
+    C: Deref<Target = [P::Subpixel]>,
+{
+    let (width, height) = buffer.dimensions();
+    let elem_size = P::ENCODING.channel_type().size();
+    let row_step = P::ENCODING.step(width)?;
+    let subpixels: &[P::Subpixel] = buffer;
+    let mut data = vec![0u8; row_step as usize * height as usize];
+
+    data.chunks_exact_mut(elem_size)
+        .zip(subpixels)
+        .for_each(|(bytes, &value)| P::write_subpixel(value, bytes));
+
+    Ok(Image {
+        header,
+        height,
+        width,
+        encoding: P::ENCODING.to_string(),
+        is_bigendian: cfg!(target_endian = "big") as u8,
+        step: row_step,
+        data,
+    })
+}
+
+/// Converts a ROS image to the closest [DynamicImage] variant.
+///
+/// BGR images are reordered to RGB. Bayer and YUV images are decoded
+/// to RGB.
+fn image_to_dynamic_image(image: &Image) -> Result<DynamicImage> {
+    use ChannelType as T;
+    use ImageEncoding as E;
+
+    let encoding = check_image(image)?;
+
+    let dynamic_image = match encoding {
+        E::Mono8 => image_to_image_buffer::<Luma<u8>>(image)?.into(),
+        E::Mono16 => image_to_image_buffer::<Luma<u16>>(image)?.into(),
+        E::Rgb8 | E::Bgr8 => image_to_image_buffer::<Rgb<u8>>(image)?.into(),
+        E::Rgb16 | E::Bgr16 => image_to_image_buffer::<Rgb<u16>>(image)?.into(),
+        E::Rgba8 | E::Bgra8 => image_to_image_buffer::<Rgba<u8>>(image)?.into(),
+        E::Rgba16 | E::Bgra16 => image_to_image_buffer::<Rgba<u16>>(image)?.into(),
+        E::Generic {
+            channel_type: T::U8,
+            channels: 2,
+        } => image_to_image_buffer::<LumaA<u8>>(image)?.into(),
+        E::Generic {
+            channel_type: T::U16,
+            channels: 2,
+        } => image_to_image_buffer::<LumaA<u16>>(image)?.into(),
+        E::Generic {
+            channel_type: T::F32,
+            channels: 3,
+        } => image_to_image_buffer::<Rgb<f32>>(image)?.into(),
+        E::Generic {
+            channel_type: T::F32,
+            channels: 4,
+        } => image_to_image_buffer::<Rgba<f32>>(image)?.into(),
+        E::BayerRggb16 | E::BayerBggr16 | E::BayerGbrg16 | E::BayerGrbg16 => {
+            image_to_image_buffer::<Rgb<u16>>(image)?.into()
+        }
+        _ if encoding.is_bayer() || encoding.yuv_format().is_some() => {
+            image_to_image_buffer::<Rgb<u8>>(image)?.into()
+        }
+        _ => bail!("image format '{encoding}' has no DynamicImage counterpart"),
+    };
+
+    Ok(dynamic_image)
+}
+
+fn dynamic_image_to_image(image: &DynamicImage, header: Header) -> Result<Image> {
+    let image = match image {
+        DynamicImage::ImageLuma8(buffer) => image_buffer_to_image(buffer, header)?,
+        DynamicImage::ImageLumaA8(buffer) => image_buffer_to_image(buffer, header)?,
+        DynamicImage::ImageRgb8(buffer) => image_buffer_to_image(buffer, header)?,
+        DynamicImage::ImageRgba8(buffer) => image_buffer_to_image(buffer, header)?,
+        DynamicImage::ImageLuma16(buffer) => image_buffer_to_image(buffer, header)?,
+        DynamicImage::ImageLumaA16(buffer) => image_buffer_to_image(buffer, header)?,
+        DynamicImage::ImageRgb16(buffer) => image_buffer_to_image(buffer, header)?,
+        DynamicImage::ImageRgba16(buffer) => image_buffer_to_image(buffer, header)?,
+        DynamicImage::ImageRgb32F(buffer) => image_buffer_to_image(buffer, header)?,
+        DynamicImage::ImageRgba32F(buffer) => image_buffer_to_image(buffer, header)?,
+        _ => bail!("unsupported DynamicImage variant"),
+    };
+
+    Ok(image)
+}
+
+/// Returns the image encoding of the pixel type of a [DynamicImage].
+fn dynamic_image_encoding(image: &DynamicImage) -> Result<ImageEncoding> {
+    let encoding = match image {
+        DynamicImage::ImageLuma8(_) => Luma::<u8>::ENCODING,
+        DynamicImage::ImageLumaA8(_) => LumaA::<u8>::ENCODING,
+        DynamicImage::ImageRgb8(_) => Rgb::<u8>::ENCODING,
+        DynamicImage::ImageRgba8(_) => Rgba::<u8>::ENCODING,
+        DynamicImage::ImageLuma16(_) => Luma::<u16>::ENCODING,
+        DynamicImage::ImageLumaA16(_) => LumaA::<u16>::ENCODING,
+        DynamicImage::ImageRgb16(_) => Rgb::<u16>::ENCODING,
+        DynamicImage::ImageRgba16(_) => Rgba::<u16>::ENCODING,
+        DynamicImage::ImageRgb32F(_) => Rgb::<f32>::ENCODING,
+        DynamicImage::ImageRgba32F(_) => Rgba::<f32>::ENCODING,
+        _ => bail!("unsupported DynamicImage variant"),
+    };
+    Ok(encoding)
+}
+
+/// Decodes a compressed image using the image crate.
+///
+/// The codec is taken from the format string, or guessed from the
+/// data if the format string cannot be parsed. The decoded image is
+/// converted back to the original encoding.
+pub fn decode_compressed_image_with_image(compressed: &CompressedImage) -> Result<Image> {
+    let format: Option<CompressedFormat> = compressed.format.parse().ok();
+    let dynamic_image = compressed_image_to_dynamic_image(compressed)?;
+    let image = dynamic_image_to_image(&dynamic_image, compressed.header.clone())?;
+    restore_encoding(image, format.as_ref())
+}
+
+/// Compresses an image using the image crate.
+///
+/// Color images are compressed in RGB order. The image crate has no
+/// WebP encoder, so WebP is not supported.
+pub fn encode_compressed_image_with_image(
+    image: &Image,
+    format: CompressionFormat,
+    quality: u8,
+) -> Result<CompressedImage> {
+    let encoding = check_image(image)?;
+    let dynamic_image = image_to_dynamic_image(image)?;
+    dynamic_image_to_compressed_image(
+        &dynamic_image,
+        Some(encoding),
+        format,
+        quality,
+        image.header.clone(),
+    )
+}
+
+fn compressed_image_to_dynamic_image(compressed: &CompressedImage) -> Result<DynamicImage> {
+    let format: Option<CompressedFormat> = compressed.format.parse().ok();
+
+    let image = match format {
+        Some(format) => {
+            let format = match format.format {
+                CompressionFormat::Jpeg => ImageFormat::Jpeg,
+                CompressionFormat::Png => ImageFormat::Png,
+                CompressionFormat::WebP => ImageFormat::WebP,
+                CompressionFormat::Qoi => ImageFormat::Qoi,
+            };
+            image::load_from_memory_with_format(&compressed.data, format)?
+        }
+        None => image::load_from_memory(&compressed.data)?,
+    };
+
+    Ok(image)
+}
+
+/// Compresses a [DynamicImage]. The pixels are converted to a type
+/// the codec supports if necessary. `encoding` is the original image
+/// encoding noted in the format string.
+fn dynamic_image_to_compressed_image(
+    image: &DynamicImage,
+    encoding: Option<ImageEncoding>,
+    format: CompressionFormat,
+    quality: u8,
+    header: Header,
+) -> Result<CompressedImage> {
+    let color = image.color();
+    let image: Cow<'_, DynamicImage> = match format {
+        CompressionFormat::Jpeg => match color {
+            ColorType::L8 | ColorType::Rgb8 => Cow::Borrowed(image),
+            _ if color.has_color() => Cow::Owned(image.to_rgb8().into()),
+            _ => Cow::Owned(image.to_luma8().into()),
+        },
+        CompressionFormat::Png => match color {
+            ColorType::Rgb32F => Cow::Owned(image.to_rgb16().into()),
+            ColorType::Rgba32F => Cow::Owned(image.to_rgba16().into()),
+            _ => Cow::Borrowed(image),
+        },
+        CompressionFormat::Qoi => match color {
+            ColorType::Rgb8 | ColorType::Rgba8 => Cow::Borrowed(image),
+            _ if color.has_alpha() => Cow::Owned(image.to_rgba8().into()),
+            _ => Cow::Owned(image.to_rgb8().into()),
+        },
+        CompressionFormat::WebP => bail!("WebP encoding is not supported by the image crate"),
+    };
+    let image_encoding = dynamic_image_encoding(&image)?;
+
+    // image_transport names the data in color files as BGR, because
+    // OpenCV swaps the channels when writing them. The files written
+    // by the image crate hold the same standard colors.
+    let compressed_encoding = match image_encoding {
+        ImageEncoding::Rgb16 | ImageEncoding::Rgba16 => ImageEncoding::Bgr16,
+        encoding if encoding.is_color() => ImageEncoding::Bgr8,
+        encoding => encoding,
+    };
+
+    let mut data = Cursor::new(vec![]);
+    match format {
+        CompressionFormat::Jpeg => image.write_to(&mut data, ImageOutputFormat::Jpeg(quality))?,
+        CompressionFormat::Png => {
+            let compression = match quality {
+                0..=3 => CompressionType::Fast,
+                4..=6 => CompressionType::Default,
+                _ => CompressionType::Best,
+            };
+            PngEncoder::new_with_quality(&mut data, compression, FilterType::Adaptive)
+                .write_image(
+                    image.as_bytes(),
+                    image.width(),
+                    image.height(),
+                    image.color(),
+                )?;
+        }
+        CompressionFormat::Qoi => image.write_to(&mut data, ImageOutputFormat::Qoi)?,
+        CompressionFormat::WebP => unreachable!(),
+    }
+
+    let format = CompressedFormat {
+        encoding: Some(encoding.unwrap_or(image_encoding)),
+        format,
+        compressed_encoding: Some(compressed_encoding),
+    };
+
+    Ok(CompressedImage {
+        header,
+        format: format.to_string(),
+        data: data.into_inner(),
+    })
+}
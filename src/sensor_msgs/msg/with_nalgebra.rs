@@ -1,6 +1,21 @@
-use anyhow::{bail, Result};
+use super::{
+    camera_info_at_full_resolution, point_chunks, FieldReader, PinholeCamera, PointFieldLayout,
+    PointFieldType, PointFieldValue,
+};
+use anyhow::{ensure, Result};
 use nalgebra as na;
-use r2r::sensor_msgs::msg::{PointCloud2, PointField};
+use r2r::{
+    sensor_msgs::msg::{CameraInfo, PointCloud2},
+    std_msgs::msg::Header,
+};
+
+/// Options of [pointcloud2_to_na_point_iter_with].
+#[derive(Debug, Clone, Default)]
+pub struct NaPointOptions {
+    /// Skips points with NaN or infinite coordinates. It only takes
+    /// effect if the point cloud is not dense.
+    pub skip_non_finite: bool,
+}
 
 pub trait PointCloud2NalgebraExt {
     fn na_point_iter(&self)
@@ -9,6 +24,29 @@ pub trait PointCloud2NalgebraExt {
     fn to_na_point_vec(&self) -> Result<Vec<na::Point3<f32>>> {
         Ok(self.na_point_iter()?.collect())
     }
+
+    /// Extracts points in `f32` or `f64`. See
+    /// [pointcloud2_to_na_point_iter_with].
+    fn na_point_iter_with<T>(
+        &self,
+        options: &NaPointOptions,
+    ) -> Result<Box<dyn Iterator<Item = na::Point3<T>> + Sync + Send + '_>>
+    where
+        T: na::RealField + Copy;
+
+    fn to_na_point_vec_with<T>(&self, options: &NaPointOptions) -> Result<Vec<na::Point3<T>>>
+    where
+        T: na::RealField + Copy,
+    {
+        Ok(self.na_point_iter_with(options)?.collect())
+    }
+
+    /// Builds an unorganized point cloud with the fields "x", "y" and
+    /// "z". Use [PointCloud2Builder] to add attributes.
+    fn from_na_points<T>(header: Header, points: &[na::Point3<T>]) -> Self
+    where
+        Self: Sized,
+        T: na::RealField + PointFieldValue;
 }
 
 impl PointCloud2NalgebraExt for PointCloud2 {
@@ -18,81 +56,590 @@ impl PointCloud2NalgebraExt for PointCloud2 {
         let iter = pointcloud2_to_na_point_iter(self)?;
         Ok(Box::new(iter))
     }
+
+    fn na_point_iter_with<T>(
+        &self,
+        options: &NaPointOptions,
+    ) -> Result<Box<dyn Iterator<Item = na::Point3<T>> + Sync + Send + '_>>
+    where
+        T: na::RealField + Copy,
+    {
+        let iter = pointcloud2_to_na_point_iter_with(self, options)?;
+        Ok(Box::new(iter))
+    }
+
+    fn from_na_points<T>(header: Header, points: &[na::Point3<T>]) -> Self
+    where
+        T: na::RealField + PointFieldValue,
+    {
+        PointCloud2Builder::new(header, points).encode()
+    }
 }
 
-/// Converts a ROS point cloud to an iterator of nalgebra points.
+/// Camera models of camera infos.
+///
+/// Pixels are in the coordinates of the published images. That is,
+/// the binning and the region of interest are folded into the matrices
+/// and the projections, so that `k_matrix() * point` and
+/// `project_point(point)` agree for undistorted cameras.
+pub trait CameraInfoNalgebraExt {
+    /// The intrinsic matrix of the raw images, adjusted for the binning
+    /// and the region of interest.
+    fn k_matrix(&self) -> Result<na::Matrix3<f64>>;
+
+    /// The rotation from the camera frame to the rectified frame.
+    fn r_matrix(&self) -> Result<na::Matrix3<f64>>;
+
+    /// The projection matrix of the rectified images, adjusted for the
+    /// binning and the region of interest.
+    fn p_matrix(&self) -> Result<na::Matrix3x4<f64>>;
+
+    /// The distortion coefficients.
+    fn d_vector(&self) -> na::DVector<f64>;
+
+    /// Projects a point in the camera optical frame to a pixel on the
+    /// raw image, taking the distortion, binning and region of
+    /// interest into account. It returns `None` if the point is not
+    /// in front of the camera.
+    ///
+    /// The camera model is rebuilt on every call. Use
+    /// [PinholeCamera] to project many points.
+    fn project_point(&self, point: &na::Point3<f64>) -> Result<Option<na::Point2<f64>>>;
+
+    /// Computes the ray through a pixel on the raw image. The ray is
+    /// in the camera optical frame and scaled to z = 1.
+    fn unproject_pixel(&self, pixel: &na::Point2<f64>) -> Result<na::Vector3<f64>>;
+}
+
+impl CameraInfoNalgebraExt for CameraInfo {
+    fn k_matrix(&self) -> Result<na::Matrix3<f64>> {
+        let info = camera_info_at_full_resolution(self)?;
+        Ok(na::Matrix3::from_row_slice(&info.k))
+    }
+
+    fn r_matrix(&self) -> Result<na::Matrix3<f64>> {
+        ensure!(
+            self.r.len() == 9,
+            "Invalid camera info. Expect 9 elements in R, but get {}.",
+            self.r.len()
+        );
+        Ok(na::Matrix3::from_row_slice(&self.r))
+    }
+
+    fn p_matrix(&self) -> Result<na::Matrix3x4<f64>> {
+        let info = camera_info_at_full_resolution(self)?;
+        Ok(na::Matrix3x4::from_row_slice(&info.p))
+    }
+
+    fn d_vector(&self) -> na::DVector<f64> {
+        na::DVector::from_column_slice(&self.d)
+    }
+
+    fn project_point(&self, point: &na::Point3<f64>) -> Result<Option<na::Point2<f64>>> {
+        let camera = PinholeCamera::from_camera_info(self)?;
+        let pixel = camera
+            .project([point.x, point.y, point.z])
+            .map(|[u, v]| na::Point2::new(u, v));
+        Ok(pixel)
+    }
+
+    fn unproject_pixel(&self, pixel: &na::Point2<f64>) -> Result<na::Vector3<f64>> {
+        let camera = PinholeCamera::from_camera_info(self)?;
+        let [x, y, z] = camera.unproject([pixel.x, pixel.y]);
+        Ok(na::Vector3::new(x, y, z))
+    }
+}
+
+/// Converts a ROS point cloud to an iterator of nalgebra points. See
+/// [pointcloud2_to_na_point_iter_with].
 pub fn pointcloud2_to_na_point_iter(
     pcd: &PointCloud2,
 ) -> Result<impl Iterator<Item = na::Point3<f32>> + Sync + Send + '_> {
-    let is_big_endian = pcd.is_bigendian;
-
-    // Assert the point cloud has at least 4 fields. Otherwise return error.
-    let [fx, fy, fz] = match pcd.fields.get(0..3) {
-        Some([fx, fy, fz]) => [fx, fy, fz],
-        Some(_) => unreachable!(),
-        None => {
-            bail!("Ignore a point cloud message with less then 3 fields");
+    pointcloud2_to_na_point_iter_with(pcd, &NaPointOptions::default())
+}
+
+/// Converts a ROS point cloud to an iterator of nalgebra points.
+///
+/// The coordinates are read from the fields named "x", "y" and "z"
+/// at any offsets. They can be of any datatype and are converted to
+/// `T`.
+pub fn pointcloud2_to_na_point_iter_with<'a, T>(
+    pcd: &'a PointCloud2,
+    options: &NaPointOptions,
+) -> Result<impl Iterator<Item = na::Point3<T>> + Sync + Send + 'a>
+where
+    T: na::RealField + Copy,
+{
+    let x = FieldReader::new(pcd, "x")?;
+    let y = FieldReader::new(pcd, "y")?;
+    let z = FieldReader::new(pcd, "z")?;
+    let skip_non_finite = options.skip_non_finite && !pcd.is_dense;
+
+    let iter = point_chunks(pcd)?.filter_map(move |point_bytes| {
+        let xyz = [
+            x.read(point_bytes),
+            y.read(point_bytes),
+            z.read(point_bytes),
+        ];
+        if skip_non_finite && !xyz.iter().all(|value| value.is_finite()) {
+            return None;
         }
-    };
+        let [x, y, z] = xyz.map(na::convert::<f64, T>);
+        Some(na::Point3::new(x, y, z))
+    });
 
-    // Assert the fields are named x, y, z and intensity. Otherwise return error.
-    match (fx.name.as_str(), fy.name.as_str(), fz.name.as_str()) {
-        ("x", "y", "z") => {}
-        _ => {
-            bail!("Ignore a point cloud message with incorrect field name");
+    Ok(iter)
+}
+
+/// Builds a point cloud from nalgebra points and per-point
+/// attributes.
+///
+/// The fields "x", "y" and "z" come first, followed by the attributes
+/// in the order they are added. Fields are packed without padding in
+/// native endianness.
+///
+/// ```ignore
+/// let pcd = PointCloud2Builder::new(header, &points)
+///     .intensity(&intensities)
+///     .field("ring", &rings)
+///     .organized(16, 1024)
+///     .build()?;
+/// ```
+pub struct PointCloud2Builder<'a, T> {
+    header: Header,
+    points: &'a [na::Point3<T>],
+    attributes: Vec<Attribute<'a>>,
+    shape: Option<(u32, u32)>,
+}
+
+type AttributeWriter<'a> = Box<dyn Fn(usize, &mut [u8], bool) + 'a>;
+
+struct Attribute<'a> {
+    name: String,
+    datatype: PointFieldType,
+    count: u32,
+    len: usize,
+    write: AttributeWriter<'a>,
+}
+
+impl<'a, T> PointCloud2Builder<'a, T>
+where
+    T: na::RealField + PointFieldValue,
+{
+    pub fn new(header: Header, points: &'a [na::Point3<T>]) -> Self {
+        Self {
+            header,
+            points,
+            attributes: vec![],
+            shape: None,
         }
     }
 
-    // Assert each field has f32 type and contains a single
-    // value. Otherwise it returns an error.
-    let check_field = |field: &PointField| {
-        match field {
-            PointField {
-                datatype: 7,
+    /// Makes an organized point cloud with `height` rows and `width`
+    /// columns. The points are in row-major order.
+    pub fn organized(mut self, height: u32, width: u32) -> Self {
+        self.shape = Some((height, width));
+        self
+    }
+
+    /// Appends a named field with one value per point. Array values
+    /// such as `[f32; 4]` become fields with `count > 1`.
+    pub fn field<V>(mut self, name: &str, values: &'a [V]) -> Self
+    where
+        V: PointFieldValue,
+    {
+        self.attributes.push(Attribute {
+            name: name.to_string(),
+            datatype: V::DATATYPE,
+            count: V::COUNT,
+            len: values.len(),
+            write: Box::new(move |idx, bytes, is_bigendian| {
+                values[idx].write_field(bytes, is_bigendian)
+            }),
+        });
+        self
+    }
+
+    /// Appends the "intensity" field.
+    pub fn intensity(self, values: &'a [f32]) -> Self {
+        self.field("intensity", values)
+    }
+
+    /// Appends a packed "rgb" field from colors in RGB order.
+    pub fn rgb(mut self, colors: &'a [[u8; 3]]) -> Self {
+        self.attributes.push(Attribute {
+            name: "rgb".to_string(),
+            datatype: PointFieldType::F32,
+            count: 1,
+            len: colors.len(),
+            write: Box::new(move |idx, bytes, is_bigendian| {
+                let [r, g, b] = colors[idx].map(u32::from);
+                let packed = f32::from_bits((r << 16) | (g << 8) | b);
+                packed.write_field(bytes, is_bigendian)
+            }),
+        });
+        self
+    }
+
+    /// Appends the "normal_x", "normal_y" and "normal_z" fields.
+    pub fn normals(mut self, normals: &'a [na::Vector3<f32>]) -> Self {
+        for (axis, name) in ["normal_x", "normal_y", "normal_z"].into_iter().enumerate() {
+            self.attributes.push(Attribute {
+                name: name.to_string(),
+                datatype: PointFieldType::F32,
                 count: 1,
-                ..
-            } => {}
-            _ => {
-                bail!("Ignore a point cloud message with non-f64 or non-single-value values");
-            }
+                len: normals.len(),
+                write: Box::new(move |idx, bytes, is_bigendian| {
+                    let normal = &normals[idx];
+                    [normal.x, normal.y, normal.z][axis].write_field(bytes, is_bigendian)
+                }),
+            });
         }
+        self
+    }
 
-        anyhow::Ok(())
-    };
+    /// Builds the point cloud. It fails if an attribute does not have
+    /// one value per point, if field names collide, or if the
+    /// organized shape does not match the number of points.
+    pub fn build(self) -> Result<PointCloud2> {
+        let num_points = self.points.len();
 
-    check_field(fx)?;
-    check_field(fy)?;
-    check_field(fz)?;
+        for (idx, attr) in self.attributes.iter().enumerate() {
+            ensure!(
+                attr.len == num_points,
+                "The attribute '{}' has {} values, but there are {num_points} points",
+                attr.name,
+                attr.len
+            );
+
+            let is_duplicated = ["x", "y", "z"].contains(&attr.name.as_str())
+                || self.attributes[0..idx]
+                    .iter()
+                    .any(|other| other.name == attr.name);
+            ensure!(!is_duplicated, "Duplicated field name '{}'", attr.name);
+        }
 
-    // Assert a point is 12 bytes (3 * f32 values). Otherwise, return error.
-    if pcd.point_step < 12 {
-        bail!("Ignore a point cloud message with incorrect point_step (expect 16)");
+        if let Some((height, width)) = self.shape {
+            ensure!(
+                height as usize * width as usize == num_points,
+                "Cannot organize {num_points} points into {height}x{width}"
+            );
+        }
+
+        Ok(self.encode())
     }
 
-    let parse_f32 = move |slice: &[u8]| {
-        let array: [u8; 4] = slice.try_into().unwrap();
+    fn encode(self) -> PointCloud2 {
+        let Self {
+            header,
+            points,
+            attributes,
+            shape,
+        } = self;
+
+        let mut layout = PointFieldLayout::new();
+        let xyz_offsets: Vec<usize> = ["x", "y", "z"]
+            .into_iter()
+            .map(|name| layout.push(name, T::DATATYPE, T::COUNT) as usize)
+            .collect();
+        let attr_offsets: Vec<usize> = attributes
+            .iter()
+            .map(|attr| layout.push(&attr.name, attr.datatype, attr.count) as usize)
+            .collect();
+        let point_step = layout.point_step() as usize;
 
-        if is_big_endian {
-            f32::from_be_bytes(array)
-        } else {
-            f32::from_le_bytes(array)
+        let is_bigendian = cfg!(target_endian = "big");
+        let mut data = vec![0u8; point_step * points.len()];
+
+        data.chunks_exact_mut(point_step)
+            .zip(points)
+            .enumerate()
+            .for_each(|(idx, (bytes, point))| {
+                for (&offset, value) in xyz_offsets.iter().zip([point.x, point.y, point.z]) {
+                    value.write_field(&mut bytes[offset..], is_bigendian);
+                }
+                for (&offset, attr) in attr_offsets.iter().zip(&attributes) {
+                    (attr.write)(idx, &mut bytes[offset..], is_bigendian);
+                }
+            });
+
+        let is_dense = points
+            .iter()
+            .all(|point| point.x.is_finite() && point.y.is_finite() && point.z.is_finite());
+        let (height, width) = shape.unwrap_or((1, points.len() as u32));
+
+        PointCloud2 {
+            header,
+            height,
+            width,
+            fields: layout.into_fields(),
+            is_bigendian,
+            point_step: point_step as u32,
+            row_step: point_step as u32 * width,
+            data,
+            is_dense,
         }
-    };
-
-    // Transform the data byte to a vec of points.
-    let iter = pcd
-        .data
-        .chunks(pcd.point_step as usize)
-        .map(move |point_bytes| {
-            let xbytes = &point_bytes[0..4];
-            let ybytes = &point_bytes[4..8];
-            let zbytes = &point_bytes[8..12];
-            let x = parse_f32(xbytes);
-            let y = parse_f32(ybytes);
-            let z = parse_f32(zbytes);
-            let position = na::Point3::new(x, y, z);
-            position
-        });
+    }
+}
 
-    Ok(iter)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sensor_msgs::msg::PointCloud2Ext;
+    use r2r::sensor_msgs::msg::RegionOfInterest;
+
+    #[test]
+    fn camera_matrices_match_projection() {
+        let info = CameraInfo {
+            width: 640,
+            height: 480,
+            distortion_model: "plumb_bob".to_string(),
+            d: vec![0.0; 5],
+            k: vec![500.0, 0.0, 320.0, 0.0, 510.0, 240.0, 0.0, 0.0, 1.0],
+            r: vec![1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0],
+            p: vec![
+                500.0, 0.0, 320.0, 0.0, 0.0, 510.0, 240.0, 0.0, 0.0, 0.0, 1.0, 0.0,
+            ],
+            binning_x: 2,
+            binning_y: 2,
+            roi: RegionOfInterest {
+                x_offset: 100,
+                y_offset: 40,
+                width: 400,
+                height: 300,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let point = na::Point3::new(0.2, 0.1, 1.0);
+        let expect = na::Point2::new((420.0 - 100.0) / 2.0, (291.0 - 40.0) / 2.0);
+
+        assert_eq!(info.project_point(&point).unwrap(), Some(expect));
+
+        let pixel = info.k_matrix().unwrap() * point.coords;
+        assert_eq!(na::Point2::new(pixel.x, pixel.y) / pixel.z, expect);
+
+        let pixel = info.p_matrix().unwrap() * point.to_homogeneous();
+        assert_eq!(na::Point2::new(pixel.x, pixel.y) / pixel.z, expect);
+
+        let ray = info.unproject_pixel(&expect).unwrap();
+        assert!((ray - point.coords).norm() < 1e-12);
+    }
+
+    #[test]
+    fn invalid_camera_matrices() {
+        let info = CameraInfo {
+            k: vec![1.0; 8],
+            p: vec![1.0; 12],
+            ..Default::default()
+        };
+        assert!(info.k_matrix().is_err());
+        assert!(info.p_matrix().is_err());
+    }
+
+    /// A point cloud like those of Ouster drivers, where the
+    /// coordinates are not the first fields and have mixed datatypes.
+    fn shuffled_pointcloud(xyzs: &[[f64; 3]], is_dense: bool) -> PointCloud2 {
+        let mut layout = PointFieldLayout::new();
+        layout.push("intensity", PointFieldType::F32, 1);
+        let z_offset = layout.push("z", PointFieldType::F64, 1) as usize;
+        layout.push("ring", PointFieldType::U16, 1);
+        let x_offset = layout.push("x", PointFieldType::I16, 1) as usize;
+        let y_offset = layout.push("y", PointFieldType::F32, 1) as usize;
+        let point_step = layout.point_step() as usize;
+
+        let mut data = vec![0u8; point_step * xyzs.len()];
+        for (bytes, &[x, y, z]) in data.chunks_exact_mut(point_step).zip(xyzs) {
+            (x as i16).write_field(&mut bytes[x_offset..], false);
+            (y as f32).write_field(&mut bytes[y_offset..], false);
+            z.write_field(&mut bytes[z_offset..], false);
+        }
+
+        PointCloud2 {
+            height: 1,
+            width: xyzs.len() as u32,
+            fields: layout.into_fields(),
+            is_bigendian: false,
+            point_step: point_step as u32,
+            row_step: (point_step * xyzs.len()) as u32,
+            data,
+            is_dense,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn na_points_by_field_name() {
+        let xyzs = [[1.0, 0.5, 0.25], [-3.0, 2.5, 1e-9], [100.0, -0.75, 7.0]];
+        let pcd = shuffled_pointcloud(&xyzs, true);
+
+        let points: Vec<na::Point3<f64>> = pcd
+            .to_na_point_vec_with(&NaPointOptions::default())
+            .unwrap();
+        let expect: Vec<_> = xyzs
+            .iter()
+            .map(|&[x, y, z]| na::Point3::new(x, y, z))
+            .collect();
+        assert_eq!(points, expect);
+
+        let points = pcd.to_na_point_vec().unwrap();
+        let expect: Vec<_> = xyzs
+            .iter()
+            .map(|&[x, y, z]| na::Point3::new(x as f32, y as f32, z as f32))
+            .collect();
+        assert_eq!(points, expect);
+    }
+
+    #[test]
+    fn na_points_skip_non_finite() {
+        let xyzs = [
+            [1.0, 2.0, 3.0],
+            [4.0, f64::NAN, 6.0],
+            [7.0, 8.0, f64::INFINITY],
+        ];
+        let skip = NaPointOptions {
+            skip_non_finite: true,
+        };
+
+        let pcd = shuffled_pointcloud(&xyzs, false);
+        let points: Vec<na::Point3<f64>> = pcd.to_na_point_vec_with(&skip).unwrap();
+        assert_eq!(points, [na::Point3::new(1.0, 2.0, 3.0)]);
+
+        let points: Vec<na::Point3<f64>> = pcd
+            .to_na_point_vec_with(&NaPointOptions::default())
+            .unwrap();
+        assert_eq!(points.len(), 3);
+        assert!(points[1].y.is_nan());
+        assert_eq!(points[2].z, f64::INFINITY);
+
+        // Dense point clouds are trusted.
+        let pcd = shuffled_pointcloud(&xyzs, true);
+        let points: Vec<na::Point3<f64>> = pcd.to_na_point_vec_with(&skip).unwrap();
+        assert_eq!(points.len(), 3);
+    }
+
+    #[test]
+    fn na_points_missing_field() {
+        let mut pcd = shuffled_pointcloud(&[[1.0, 2.0, 3.0]], true);
+        pcd.fields
+            .iter_mut()
+            .find(|field| field.name == "z")
+            .unwrap()
+            .name = "Z".to_string();
+
+        assert!(pcd.to_na_point_vec().is_err());
+        assert!(pcd
+            .to_na_point_vec_with::<f64>(&NaPointOptions::default())
+            .is_err());
+    }
+
+    #[test]
+    fn builder_roundtrip() {
+        let points = [
+            na::Point3::new(1.0f32, 2.0, 3.0),
+            na::Point3::new(-1.0, 0.5, 2.0),
+            na::Point3::new(0.0, 0.0, 10.0),
+            na::Point3::new(4.0, -4.0, 0.25),
+        ];
+        let intensities = [0.1f32, 0.2, 0.3, 0.4];
+        let colors = [[255, 0, 0], [0, 255, 0], [0, 0, 255], [1, 2, 3]];
+        let normals = [na::Vector3::new(0.0f32, 0.0, 1.0); 4];
+        let rings = [0u16, 1, 2, 3];
+
+        let pcd = PointCloud2Builder::new(Header::default(), &points)
+            .intensity(&intensities)
+            .rgb(&colors)
+            .normals(&normals)
+            .field("ring", &rings)
+            .organized(2, 2)
+            .build()
+            .unwrap();
+
+        let fields: Vec<_> = pcd
+            .fields
+            .iter()
+            .map(|field| (field.name.as_str(), field.offset, field.datatype))
+            .collect();
+        let f32_type = PointFieldType::F32 as u8;
+        assert_eq!(
+            fields,
+            [
+                ("x", 0, f32_type),
+                ("y", 4, f32_type),
+                ("z", 8, f32_type),
+                ("intensity", 12, f32_type),
+                ("rgb", 16, f32_type),
+                ("normal_x", 20, f32_type),
+                ("normal_y", 24, f32_type),
+                ("normal_z", 28, f32_type),
+                ("ring", 32, PointFieldType::U16 as u8),
+            ]
+        );
+        assert_eq!((pcd.height, pcd.width), (2, 2));
+        assert_eq!(pcd.point_step, 34);
+        assert_eq!(pcd.row_step, 68);
+        assert_eq!(pcd.data.len(), 136);
+        assert!(pcd.is_dense);
+
+        assert_eq!(pcd.to_na_point_vec().unwrap(), points);
+        let values: Vec<f32> = pcd.field_iter("intensity").unwrap().collect();
+        assert_eq!(values, intensities);
+        let values: Vec<u16> = pcd.field_iter("ring").unwrap().collect();
+        assert_eq!(values, rings);
+        let values: Vec<f32> = pcd.field_iter("normal_z").unwrap().collect();
+        assert_eq!(values, [1.0; 4]);
+        let values: Vec<u32> = pcd
+            .field_iter::<f32>("rgb")
+            .unwrap()
+            .map(f32::to_bits)
+            .collect();
+        assert_eq!(values, [0xff0000, 0x00ff00, 0x0000ff, 0x010203]);
+    }
+
+    #[test]
+    fn builder_unorganized() {
+        let points = [
+            na::Point3::new(1.0f64, 2.0, 3.0),
+            na::Point3::new(f64::NAN, 0.0, 1.0),
+        ];
+        let pcd = PointCloud2::from_na_points(Header::default(), &points);
+
+        assert_eq!((pcd.height, pcd.width), (1, 2));
+        assert_eq!(pcd.point_step, 24);
+        assert_eq!(pcd.row_step, 48);
+        assert!(!pcd.is_dense);
+        assert!(pcd
+            .fields
+            .iter()
+            .all(|field| field.datatype == PointFieldType::F64 as u8));
+
+        let decoded: Vec<na::Point3<f64>> = pcd
+            .to_na_point_vec_with(&NaPointOptions::default())
+            .unwrap();
+        assert_eq!(decoded[0], points[0]);
+        assert!(decoded[1].x.is_nan());
+    }
+
+    #[test]
+    fn builder_invalid_attributes() {
+        let points = [na::Point3::new(1.0f32, 2.0, 3.0); 3];
+
+        // The number of values does not match.
+        let intensities = [1.0f32; 2];
+        let builder = PointCloud2Builder::new(Header::default(), &points).intensity(&intensities);
+        assert!(builder.build().is_err());
+
+        // Duplicated field names.
+        let values = [0u8; 3];
+        let builder = PointCloud2Builder::new(Header::default(), &points).field("x", &values);
+        assert!(builder.build().is_err());
+        let builder = PointCloud2Builder::new(Header::default(), &points)
+            .field("label", &values)
+            .field("label", &values);
+        assert!(builder.build().is_err());
+
+        // The shape does not match.
+        let builder = PointCloud2Builder::new(Header::default(), &points).organized(2, 2);
+        assert!(builder.build().is_err());
+    }
 }
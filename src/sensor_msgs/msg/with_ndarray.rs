@@ -0,0 +1,200 @@
+use super::{check_image, ChannelType, ImageEncoding};
+use anyhow::{ensure, Result};
+use ndarray::{Array3, ArrayBase, ArrayView3, CowArray, Data, Ix3};
+use r2r::{sensor_msgs::msg::Image, std_msgs::msg::Header};
+use std::mem;
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// Element types of image arrays, one for each channel type of the
+/// image encodings.
+///
+/// The trait is sealed. Any bit pattern must be a valid value of the
+/// implementors in order to view the image data without copying.
+pub trait NdarrayElement: sealed::Sealed + Copy + 'static {
+    const CHANNEL_TYPE: ChannelType;
+
+    /// Decodes an element from bytes in the given byte order.
+    fn read(bytes: &[u8], is_bigendian: bool) -> Self;
+
+    /// Appends the element in the native byte order.
+    fn write_ne(self, output: &mut Vec<u8>);
+}
+
+macro_rules! impl_ndarray_element {
+    ($ty:ty, $channel_type:expr) => {
+        impl sealed::Sealed for $ty {}
+
+        impl NdarrayElement for $ty {
+            const CHANNEL_TYPE: ChannelType = $channel_type;
+
+            fn read(bytes: &[u8], is_bigendian: bool) -> Self {
+                let array = bytes.try_into().unwrap();
+                if is_bigendian {
+                    <$ty>::from_be_bytes(array)
+                } else {
+                    <$ty>::from_le_bytes(array)
+                }
+            }
+
+            fn write_ne(self, output: &mut Vec<u8>) {
+                output.extend_from_slice(&self.to_ne_bytes());
+            }
+        }
+    };
+}
+
+impl_ndarray_element!(u8, ChannelType::U8);
+impl_ndarray_element!(i8, ChannelType::I8);
+impl_ndarray_element!(u16, ChannelType::U16);
+impl_ndarray_element!(i16, ChannelType::I16);
+impl_ndarray_element!(i32, ChannelType::I32);
+impl_ndarray_element!(f32, ChannelType::F32);
+impl_ndarray_element!(f64, ChannelType::F64);
+
+pub trait ImageNdarrayExt
+where
+    Self: Sized,
+{
+    fn to_ndarray<T>(&self) -> Result<CowArray<'_, T, Ix3>>
+    where
+        T: NdarrayElement;
+
+    fn from_ndarray<S, T>(
+        array: &ArrayBase<S, Ix3>,
+        encoding: &str,
+        header: Header,
+    ) -> Result<Self>
+    where
+        S: Data<Elem = T>,
+        T: NdarrayElement;
+}
+
+impl ImageNdarrayExt for Image {
+    fn to_ndarray<T>(&self) -> Result<CowArray<'_, T, Ix3>>
+    where
+        T: NdarrayElement,
+    {
+        image_to_ndarray(self)
+    }
+
+    fn from_ndarray<S, T>(array: &ArrayBase<S, Ix3>, encoding: &str, header: Header) -> Result<Self>
+    where
+        S: Data<Elem = T>,
+        T: NdarrayElement,
+    {
+        ndarray_to_image(array, encoding, header)
+    }
+}
+
+/// Converts an image to an array of shape (height, width, channels)
+/// in the raw channel order of the encoding.
+///
+/// The array borrows the image data if the rows have no padding, the
+/// data is in the native byte order and properly aligned for `T`.
+/// Otherwise, the data is copied.
+fn image_to_ndarray<T>(image: &Image) -> Result<CowArray<'_, T, Ix3>>
+where
+    T: NdarrayElement,
+{
+    let Image {
+        height,
+        width,
+        step: row_step,
+        ref data,
+        is_bigendian,
+        ..
+    } = *image;
+
+    let encoding = check_image(image)?;
+    check_encoding::<T>(encoding)?;
+
+    let height = height as usize;
+    let width = width as usize;
+    let channels = encoding.channels() as usize;
+    let shape = (height, width, channels);
+    let elem_size = mem::size_of::<T>();
+    let row_size = width * channels * elem_size;
+    let is_bigendian = is_bigendian != 0;
+    let is_native = elem_size == 1 || is_bigendian == cfg!(target_endian = "big");
+
+    if row_step as usize == row_size && is_native {
+        let data = &data[0..row_size * height];
+
+        // Safety: The element types are plain numbers, for which any
+        // bit pattern is valid.
+        let (prefix, elems, _) = unsafe { data.align_to::<T>() };
+
+        if prefix.is_empty() {
+            let view = ArrayView3::from_shape(shape, &elems[0..height * width * channels])?;
+            return Ok(view.into());
+        }
+    }
+
+    let elems: Vec<T> = data
+        .chunks(row_step as usize)
+        .take(height)
+        .flat_map(|row| row[0..row_size].chunks_exact(elem_size))
+        .map(|bytes| T::read(bytes, is_bigendian))
+        .collect();
+    let array = Array3::from_shape_vec(shape, elems)?;
+
+    Ok(array.into())
+}
+
+/// Converts an array of shape (height, width, channels) to an image.
+/// The channels must be in the raw order of the encoding.
+fn ndarray_to_image<S, T>(
+    array: &ArrayBase<S, Ix3>,
+    encoding: &str,
+    header: Header,
+) -> Result<Image>
+where
+    S: Data<Elem = T>,
+    T: NdarrayElement,
+{
+    let encoding: ImageEncoding = encoding.parse()?;
+    check_encoding::<T>(encoding)?;
+
+    let (height, width, channels) = array.dim();
+    ensure!(
+        channels == encoding.channels() as usize,
+        "The image format '{encoding}' has {} channels, but the array has {channels}",
+        encoding.channels()
+    );
+
+    let mut data = Vec::with_capacity(array.len() * mem::size_of::<T>());
+    array.iter().for_each(|&elem| elem.write_ne(&mut data));
+
+    Ok(Image {
+        header,
+        height: height as u32,
+        width: width as u32,
+        encoding: encoding.to_string(),
+        is_bigendian: cfg!(target_endian = "big") as u8,
+        step: encoding.step(width as u32)?,
+        data,
+    })
+}
+
+/// Checks whether the encoding can be represented by an array of `T`.
+fn check_encoding<T>(encoding: ImageEncoding) -> Result<()>
+where
+    T: NdarrayElement,
+{
+    use ImageEncoding as E;
+
+    ensure!(
+        !matches!(encoding, E::Nv12 | E::Nv21 | E::Nv24 | E::I420),
+        "planar image format '{encoding}' is not supported"
+    );
+    ensure!(
+        encoding.channel_type() == T::CHANNEL_TYPE,
+        "The image format '{encoding}' has {:?} channels, but the array element is {:?}",
+        encoding.channel_type(),
+        T::CHANNEL_TYPE
+    );
+    Ok(())
+}
@@ -1,62 +1,914 @@
+use super::{
+    camera_info_at_full_resolution, check_image, image_demosaic, restore_encoding, ChannelType,
+    CompressedFormat, CompressionFormat, DemosaicMethod, Distortion, ImageEncoding, Interpolation,
+    RgbOrder,
+};
 use anyhow::{bail, ensure, Result};
 use opencv::{
-    core::{Scalar, Vec3b, VecN, CV_8UC3},
+    calib3d,
+    core::{
+        _InputArray, Point2d, Point3d, Scalar, Size, ToInputArray, Vector, BORDER_CONSTANT, CV_16S,
+        CV_16SC2, CV_16U, CV_32F, CV_32S, CV_64F, CV_8S, CV_8U, CV_8UC3,
+    },
+    imgcodecs, imgproc,
     prelude::*,
 };
-use r2r::sensor_msgs::msg::Image;
+use r2r::{
+    geometry_msgs::msg::Point,
+    sensor_msgs::msg::{CameraInfo, CompressedImage, Image},
+    std_msgs::msg::Header,
+};
+use std::{ffi::c_void, marker::PhantomData};
 
-pub trait ImageOpenCvExt {
+pub trait ImageOpenCvExt
+where
+    Self: Sized,
+{
     fn to_mat(&self) -> Result<Mat>;
+
+    /// Converts the image to a Mat like [to_mat](Self::to_mat), except
+    /// that Bayer images are demosaiced by the pure-Rust
+    /// [image_demosaic] with the given method instead of OpenCV.
+    fn to_mat_with_demosaic(&self, method: DemosaicMethod) -> Result<Mat>;
+
+    /// Creates a Mat view over the image data without copying.
+    ///
+    /// # Safety
+    ///
+    /// OpenCV does not track the lifetime of the borrowed data. The
+    /// input array of the view gives access to Mat headers over the
+    /// image data, such as by `_InputArray::get_mat()`. The caller must
+    /// not use such a Mat after the view is dropped, and must not write
+    /// to the image data through it.
+    unsafe fn as_mat_view(&self) -> Result<MatView<'_>>;
+
+    fn from_mat(mat: &Mat, encoding: &str, header: Header) -> Result<Self>;
 }
 
 impl ImageOpenCvExt for Image {
     fn to_mat(&self) -> Result<Mat> {
         image_to_mat(self)
     }
+
+    fn to_mat_with_demosaic(&self, method: DemosaicMethod) -> Result<Mat> {
+        image_to_mat_with_demosaic(self, method)
+    }
+
+    unsafe fn as_mat_view(&self) -> Result<MatView<'_>> {
+        image_as_mat_view(self)
+    }
+
+    fn from_mat(mat: &Mat, encoding: &str, header: Header) -> Result<Self> {
+        mat_to_image(mat, encoding, header)
+    }
+}
+
+pub trait CompressedImageOpenCvExt
+where
+    Self: Sized,
+{
+    fn to_mat(&self) -> Result<Mat>;
+    fn from_mat(mat: &Mat, format: CompressionFormat, quality: u8, header: Header) -> Result<Self>;
+}
+
+impl CompressedImageOpenCvExt for CompressedImage {
+    fn to_mat(&self) -> Result<Mat> {
+        compressed_image_to_mat(self)
+    }
+
+    fn from_mat(mat: &Mat, format: CompressionFormat, quality: u8, header: Header) -> Result<Self> {
+        mat_to_compressed_image(mat, None, format, quality, header)
+    }
+}
+
+/// Conversions between camera infos and the camera model Mats of
+/// OpenCV's calib3d module.
+///
+/// The Mats are of type CV_64F and hold the values of the message as
+/// is, without applying the binning and the region of interest.
+pub trait CameraInfoOpenCvExt
+where
+    Self: Sized,
+{
+    /// The 3x3 camera matrix K.
+    fn camera_matrix(&self) -> Result<Mat>;
+
+    /// The 1xN distortion coefficients D. It is empty if D is empty.
+    fn dist_coeffs(&self) -> Result<Mat>;
+
+    /// The 3x3 rectification rotation R.
+    fn rectification_matrix(&self) -> Result<Mat>;
+
+    /// The 3x4 projection matrix P.
+    fn projection_matrix(&self) -> Result<Mat>;
+
+    /// Creates a camera info from the output of calibrateCamera(). The
+    /// distortion model is "plumb_bob" for up to 5 coefficients and
+    /// "rational_polynomial" for 8 coefficients.
+    fn from_calibration(
+        width: u32,
+        height: u32,
+        camera_matrix: &Mat,
+        dist_coeffs: &Mat,
+        header: Header,
+    ) -> Result<Self>;
+
+    /// Creates a camera info with the "equidistant" distortion model
+    /// from the output of fisheye::calibrate().
+    fn from_fisheye_calibration(
+        width: u32,
+        height: u32,
+        camera_matrix: &Mat,
+        dist_coeffs: &Mat,
+        header: Header,
+    ) -> Result<Self>;
+}
+
+impl CameraInfoOpenCvExt for CameraInfo {
+    fn camera_matrix(&self) -> Result<Mat> {
+        matrix_to_mat(&self.k, 3, 3, "K")
+    }
+
+    fn dist_coeffs(&self) -> Result<Mat> {
+        if self.d.is_empty() {
+            return Ok(Mat::default());
+        }
+        Ok(Mat::from_slice_2d(&[&self.d[..]])?)
+    }
+
+    fn rectification_matrix(&self) -> Result<Mat> {
+        matrix_to_mat(&self.r, 3, 3, "R")
+    }
+
+    fn projection_matrix(&self) -> Result<Mat> {
+        matrix_to_mat(&self.p, 3, 4, "P")
+    }
+
+    fn from_calibration(
+        width: u32,
+        height: u32,
+        camera_matrix: &Mat,
+        dist_coeffs: &Mat,
+        header: Header,
+    ) -> Result<Self> {
+        let mut d = mat_to_vec(dist_coeffs)?;
+        let distortion_model = match d.len() {
+            0..=5 => {
+                d.resize(5, 0.0);
+                "plumb_bob"
+            }
+            8 => "rational_polynomial",
+            len => bail!("{len} distortion coefficients are not supported by ROS"),
+        };
+        calibration_to_camera_info(width, height, camera_matrix, distortion_model, d, header)
+    }
+
+    fn from_fisheye_calibration(
+        width: u32,
+        height: u32,
+        camera_matrix: &Mat,
+        dist_coeffs: &Mat,
+        header: Header,
+    ) -> Result<Self> {
+        let d = mat_to_vec(dist_coeffs)?;
+        ensure!(
+            d.len() == 4,
+            "Expect 4 fisheye distortion coefficients, but get {}",
+            d.len()
+        );
+        calibration_to_camera_info(width, height, camera_matrix, "equidistant", d, header)
+    }
+}
+
+/// Undistorts or rectifies raw images using OpenCV's
+/// initUndistortRectifyMap() and remap().
+///
+/// It is the OpenCV counterpart of [Rectifier](super::Rectifier).
+/// The maps are computed once on creation. Images are converted by
+/// [ImageOpenCvExt::to_mat], so that Bayer and YUV images are
+/// rectified to BGR images.
+pub struct OpenCvRectifier {
+    width: u32,
+    height: u32,
+    interpolation: Interpolation,
+    map1: Mat,
+    map2: Mat,
+}
+
+impl OpenCvRectifier {
+    /// Creates a rectifier that produces images for the projection
+    /// matrix P, applying the rectification rotation R.
+    pub fn new(camera_info: &CameraInfo, interpolation: Interpolation) -> Result<Self> {
+        let model = CvCameraModel::new(camera_info)?;
+        Self::with_model(&model, &model.r, &model.p_left, interpolation)
+    }
+
+    /// Creates a rectifier that only removes the distortion, keeping
+    /// the intrinsic matrix K.
+    pub fn new_undistort(camera_info: &CameraInfo, interpolation: Interpolation) -> Result<Self> {
+        let model = CvCameraModel::new(camera_info)?;
+        Self::with_model(&model, &Mat::default(), &model.k, interpolation)
+    }
+
+    fn with_model(
+        model: &CvCameraModel,
+        r: &Mat,
+        new_camera_matrix: &Mat,
+        interpolation: Interpolation,
+    ) -> Result<Self> {
+        let size = Size::new(model.width as i32, model.height as i32);
+        let mut map1 = Mat::default();
+        let mut map2 = Mat::default();
+
+        if model.is_fisheye {
+            calib3d::fisheye_init_undistort_rectify_map(
+                &model.k,
+                &model.d,
+                r,
+                new_camera_matrix,
+                size,
+                CV_16SC2,
+                &mut map1,
+                &mut map2,
+            )?;
+        } else {
+            calib3d::init_undistort_rectify_map(
+                &model.k,
+                &model.d,
+                r,
+                new_camera_matrix,
+                size,
+                CV_16SC2,
+                &mut map1,
+                &mut map2,
+            )?;
+        }
+
+        Ok(Self {
+            width: model.width,
+            height: model.height,
+            interpolation,
+            map1,
+            map2,
+        })
+    }
+
+    pub fn rectify(&self, image: &Image) -> Result<Image> {
+        ensure!(
+            image.width == self.width && image.height == self.height,
+            "The rectifier is for {}x{} images, but the image is {}x{}",
+            self.width,
+            self.height,
+            image.width,
+            image.height
+        );
+
+        let src = image_to_mat(image)?;
+        let mut dst = Mat::default();
+        imgproc::remap(
+            &src,
+            &mut dst,
+            &self.map1,
+            &self.map2,
+            cv_interpolation(self.interpolation),
+            BORDER_CONSTANT,
+            Scalar::all(0.0),
+        )?;
+
+        mat_to_image_like(&dst, image)
+    }
+}
+
+/// Undistorts an image using OpenCV's undistort(), keeping the
+/// intrinsic matrix K.
+pub fn undistort_image_with_opencv(image: &Image, camera_info: &CameraInfo) -> Result<Image> {
+    let model = CvCameraModel::new(camera_info)?;
+    ensure!(
+        image.width == model.width && image.height == model.height,
+        "The camera info is for {}x{} images, but the image is {}x{}",
+        model.width,
+        model.height,
+        image.width,
+        image.height
+    );
+
+    let src = image_to_mat(image)?;
+    let mut dst = Mat::default();
+
+    if model.is_fisheye {
+        let size = Size::new(model.width as i32, model.height as i32);
+        calib3d::fisheye_undistort_image(&src, &mut dst, &model.k, &model.d, &model.k, size)?;
+    } else {
+        calib3d::undistort(&src, &mut dst, &model.k, &model.d, &model.k)?;
+    }
+
+    mat_to_image_like(&dst, image)
+}
+
+/// Projects points in the camera optical frame to pixels on the raw
+/// image using OpenCV's projectPoints().
+pub fn project_points_with_opencv(
+    points: &[Point],
+    camera_info: &CameraInfo,
+) -> Result<Vec<[f64; 2]>> {
+    let model = CvCameraModel::new(camera_info)?;
+    let object_points: Vector<Point3d> = points
+        .iter()
+        .map(|&Point { x, y, z }| Point3d::new(x, y, z))
+        .collect();
+    let rvec = Vector::<f64>::from_slice(&[0.0; 3]);
+    let tvec = Vector::<f64>::from_slice(&[0.0; 3]);
+    let mut image_points = Vector::<Point2d>::new();
+    let mut jacobian = Mat::default();
+
+    if model.is_fisheye {
+        calib3d::fisheye_project_points_vec(
+            &object_points,
+            &mut image_points,
+            &rvec,
+            &tvec,
+            &model.k,
+            &model.d,
+            0.0,
+            &mut jacobian,
+        )?;
+    } else {
+        calib3d::project_points(
+            &object_points,
+            &rvec,
+            &tvec,
+            &model.k,
+            &model.d,
+            &mut image_points,
+            &mut jacobian,
+            0.0,
+        )?;
+    }
+
+    Ok(image_points.iter().map(|pt| [pt.x, pt.y]).collect())
+}
+
+/// The camera model Mats of a camera info, with the binning and the
+/// region of interest folded into the intrinsics.
+struct CvCameraModel {
+    width: u32,
+    height: u32,
+    k: Mat,
+    d: Mat,
+    r: Mat,
+    /// The left 3x3 block of P.
+    p_left: Mat,
+    is_fisheye: bool,
+}
+
+impl CvCameraModel {
+    fn new(camera_info: &CameraInfo) -> Result<Self> {
+        let distortion = Distortion::from_camera_info(camera_info)?;
+        let info = camera_info_at_full_resolution(camera_info)?;
+
+        let (d, is_fisheye) = match distortion {
+            Distortion::None => (Mat::default(), false),
+            Distortion::PlumbBob(d) => (Mat::from_slice_2d(&[d])?, false),
+            Distortion::RationalPolynomial(d) => (Mat::from_slice_2d(&[d])?, false),
+            Distortion::Equidistant(d) => (Mat::from_slice_2d(&[d])?, true),
+        };
+
+        // An empty R is treated as identity by OpenCV.
+        let r = if info.r.is_empty() {
+            Mat::default()
+        } else {
+            info.rectification_matrix()?
+        };
+
+        let p = &info.p;
+        let p_left = [p[0], p[1], p[2], p[4], p[5], p[6], p[8], p[9], p[10]];
+
+        Ok(Self {
+            width: info.width,
+            height: info.height,
+            k: info.camera_matrix()?,
+            d,
+            r,
+            p_left: matrix_to_mat(&p_left, 3, 3, "P")?,
+            is_fisheye,
+        })
+    }
+}
+
+fn calibration_to_camera_info(
+    width: u32,
+    height: u32,
+    camera_matrix: &Mat,
+    distortion_model: &str,
+    d: Vec<f64>,
+    header: Header,
+) -> Result<CameraInfo> {
+    let k = mat_to_vec(camera_matrix)?;
+    ensure!(
+        k.len() == 9,
+        "Expect a 3x3 camera matrix, but get {} elements",
+        k.len()
+    );
+    let p = vec![
+        k[0], k[1], k[2], 0.0, k[3], k[4], k[5], 0.0, k[6], k[7], k[8], 0.0,
+    ];
+
+    Ok(CameraInfo {
+        header,
+        height,
+        width,
+        distortion_model: distortion_model.to_string(),
+        d,
+        k,
+        r: vec![1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0],
+        p,
+        ..Default::default()
+    })
+}
+
+/// Creates a CV_64F Mat from a row-major matrix of a camera info.
+fn matrix_to_mat(values: &[f64], rows: usize, cols: usize, name: &str) -> Result<Mat> {
+    ensure!(
+        values.len() == rows * cols,
+        "Invalid camera info. Expect {} elements in {name}, but get {}.",
+        rows * cols,
+        values.len()
+    );
+    let rows: Vec<&[f64]> = values.chunks(cols).collect();
+    Ok(Mat::from_slice_2d(&rows)?)
+}
+
+/// Reads the elements of a Mat in row-major order as f64 values.
+fn mat_to_vec(mat: &Mat) -> Result<Vec<f64>> {
+    if mat.empty() {
+        return Ok(vec![]);
+    }
+    ensure!(
+        mat.channels() == 1,
+        "Expect a single-channel Mat, but get {} channels",
+        mat.channels()
+    );
+
+    // The converted Mat is newly allocated and thus continuous.
+    let mut converted = Mat::default();
+    mat.convert_to(&mut converted, CV_64F, 1.0, 0.0)?;
+    Ok(converted.data_typed::<f64>()?.to_vec())
+}
+
+/// Converts a Mat produced from the image by [image_to_mat] back to
+/// the encoding of the image. Bayer and YUV images, which are
+/// converted to BGR, stay in BGR.
+fn mat_to_image_like(mat: &Mat, image: &Image) -> Result<Image> {
+    let encoding = check_image(image)?;
+    let encoding = if encoding.is_bayer() || encoding.is_yuv() {
+        mat_encoding(mat)?
+    } else {
+        encoding
+    };
+    mat_to_image(mat, &encoding.to_string(), image.header.clone())
+}
+
+fn cv_interpolation(interpolation: Interpolation) -> i32 {
+    match interpolation {
+        Interpolation::Nearest => imgproc::INTER_NEAREST,
+        Interpolation::Bilinear => imgproc::INTER_LINEAR,
+    }
+}
+
+/// An OpenCV Mat borrowing the pixel data of an [Image].
+///
+/// It is created by [ImageOpenCvExt::as_mat_view] and cannot outlive
+/// the image. The inner Mat is not exposed, because a Mat derived from
+/// it, such as a row or an ROI, would alias the image data without a
+/// lifetime. The view is meant to be passed to OpenCV functions as an
+/// input array. See the safety section of
+/// [as_mat_view](ImageOpenCvExt::as_mat_view) for the rules of
+/// aliasing.
+pub struct MatView<'a> {
+    mat: Mat,
+    _phantom: PhantomData<&'a [u8]>,
+}
+
+impl MatView<'_> {
+    pub fn size(&self) -> Result<Size> {
+        Ok(self.mat.size()?)
+    }
+
+    /// The OpenCV type, such as CV_8UC3.
+    pub fn typ(&self) -> i32 {
+        self.mat.typ()
+    }
+
+    pub fn as_input_array(&self) -> &impl ToInputArray {
+        self
+    }
+
+    /// Copies the data to an owned Mat.
+    pub fn to_mat(&self) -> Result<Mat> {
+        Ok(self.mat.try_clone()?)
+    }
+}
+
+impl ToInputArray for MatView<'_> {
+    fn input_array(&self) -> opencv::Result<_InputArray> {
+        self.mat.input_array()
+    }
+}
+
+/// Creates a Mat header over the image data without copying.
+///
+/// Only encodings with the same memory layout as OpenCV are accepted,
+/// that is, mono, BGR and BGRA images and the generic OpenCV type
+/// encodings. Multi-byte data must be in the native byte order.
+///
+/// # Safety
+///
+/// The same as [ImageOpenCvExt::as_mat_view].
+unsafe fn image_as_mat_view(image: &Image) -> Result<MatView<'_>> {
+    let Image {
+        height,
+        width,
+        step: row_step,
+        ref data,
+        is_bigendian,
+        ..
+    } = *image;
+
+    let encoding = check_image(image)?;
+    ensure!(
+        is_native(encoding),
+        "image format '{encoding}' cannot be viewed as a Mat without conversion"
+    );
+    let elem_size = encoding.channel_type().size();
+    let is_bigendian = is_bigendian != 0;
+
+    ensure!(
+        elem_size == 1 || is_bigendian == cfg!(target_endian = "big"),
+        "image data must be in the native byte order to be viewed as a Mat"
+    );
+    ensure!(
+        (row_step as usize).is_multiple_of(elem_size),
+        "Invalid step {row_step}. It must be a multiple of {elem_size}."
+    );
+
+    // Safety: The Mat header points to the image data, which is not
+    // freed while the MatView borrows the image. Mats obtained through
+    // the input array are not bound to the borrow. The caller takes
+    // care of them as required by ImageOpenCvExt::as_mat_view.
+    let mat = unsafe {
+        Mat::new_rows_cols_with_data(
+            height as i32,
+            width as i32,
+            cv_type(encoding),
+            data.as_ptr() as *mut c_void,
+            row_step as usize,
+        )?
+    };
+
+    Ok(MatView {
+        mat,
+        _phantom: PhantomData,
+    })
 }
 
 /// Converts a ROS image to an OpenCV Mat.
+///
+/// Color images are returned in OpenCV's BGR or BGRA channel order.
+/// YUV and Bayer images are converted to BGR. Bayer images are
+/// demosaiced by OpenCV. Use [image_to_mat_with_demosaic] for the
+/// pure-Rust demosaicing. Other encodings keep their channel layout.
+/// Multi-byte big-endian data is byte-swapped to the native byte
+/// order.
 fn image_to_mat(image: &Image) -> Result<Mat> {
-    let mat = match image.encoding.as_str() {
-        "BGR8" => bgr8_to_mat(image)?,
-        "RGB8" => rgb8_to_mat(image)?,
-        "UYVY" => uyvy_to_mat(image)?,
-        _ => bail!("unsupported image format '{}'", image.encoding),
+    use ImageEncoding as E;
+
+    let encoding = check_image(image)?;
+
+    let mat = match encoding {
+        E::Rgb8 | E::Rgb16 => cvt_color(&raw_to_mat(image, encoding)?, imgproc::COLOR_RGB2BGR)?,
+        E::Rgba8 | E::Rgba16 => cvt_color(&raw_to_mat(image, encoding)?, imgproc::COLOR_RGBA2BGRA)?,
+        E::BayerRggb8 | E::BayerRggb16 => {
+            cvt_color(&raw_to_mat(image, encoding)?, imgproc::COLOR_BayerBG2BGR)?
+        }
+        E::BayerBggr8 | E::BayerBggr16 => {
+            cvt_color(&raw_to_mat(image, encoding)?, imgproc::COLOR_BayerRG2BGR)?
+        }
+        E::BayerGbrg8 | E::BayerGbrg16 => {
+            cvt_color(&raw_to_mat(image, encoding)?, imgproc::COLOR_BayerGR2BGR)?
+        }
+        E::BayerGrbg8 | E::BayerGrbg16 => {
+            cvt_color(&raw_to_mat(image, encoding)?, imgproc::COLOR_BayerGB2BGR)?
+        }
+        E::Yuv422 | E::Uyvy => uyvy_to_mat(image)?,
+        E::Yuv422Yuy2 | E::Yuyv => {
+            cvt_color(&raw_to_mat(image, encoding)?, imgproc::COLOR_YUV2BGR_YUY2)?
+        }
+        E::Nv12 => yuv420_to_mat(image, imgproc::COLOR_YUV2BGR_NV12)?,
+        E::Nv21 => yuv420_to_mat(image, imgproc::COLOR_YUV2BGR_NV21)?,
+        E::I420 => yuv420_to_mat(image, imgproc::COLOR_YUV2BGR_I420)?,
+        E::Nv24 => nv24_to_mat(image)?,
+        E::Mono8 | E::Mono16 | E::Bgr8 | E::Bgra8 | E::Bgr16 | E::Bgra16 | E::Generic { .. } => {
+            raw_to_mat(image, encoding)?
+        }
     };
 
     Ok(mat)
 }
 
-fn bgr8_to_mat(image: &Image) -> Result<Mat> {
+/// Converts a ROS image to an OpenCV Mat, the same as [image_to_mat]
+/// except that Bayer images are demosaiced by [image_demosaic].
+fn image_to_mat_with_demosaic(image: &Image, method: DemosaicMethod) -> Result<Mat> {
+    let encoding = check_image(image)?;
+    if !encoding.is_bayer() {
+        return image_to_mat(image);
+    }
+
+    let bgr = image_demosaic(image, method, RgbOrder::Bgr)?;
+    raw_to_mat(&bgr, check_image(&bgr)?)
+}
+
+/// Converts an OpenCV Mat to a ROS image with the given encoding.
+///
+/// It inverts [image_to_mat] only for mono, color and generic
+/// encodings. Color Mats are expected in BGR or BGRA channel order and
+/// are reordered if an RGB encoding is requested. Bayer and YUV 4:2:2
+/// encodings are not converted from BGR, but take the raw layout, that
+/// is, a single-channel Mat for Bayer and a 2-channel Mat for YUV. The
+/// planar YUV encodings "nv12", "nv21", "nv24" and "i420" are not
+/// supported.
+fn mat_to_image(mat: &Mat, encoding: &str, header: Header) -> Result<Image> {
+    use ImageEncoding as E;
+
+    ensure!(
+        mat.dims() == 2,
+        "Expect a 2-dimensional Mat, but get {} dimensions",
+        mat.dims()
+    );
+
+    let encoding: ImageEncoding = encoding.parse()?;
+    let cvt_code = match encoding {
+        E::Rgb8 | E::Rgb16 => Some(imgproc::COLOR_BGR2RGB),
+        E::Rgba8 | E::Rgba16 => Some(imgproc::COLOR_BGRA2RGBA),
+        E::Nv12 | E::Nv21 | E::Nv24 | E::I420 => {
+            bail!("planar image format '{encoding}' is not supported");
+        }
+        _ => None,
+    };
+
+    ensure!(
+        mat.typ() == cv_type(encoding),
+        "The Mat with depth {} and {} channels does not fit the encoding '{encoding}'",
+        mat.depth(),
+        mat.channels()
+    );
+
+    let converted;
+    let mat = match cvt_code {
+        Some(code) => {
+            converted = cvt_color(mat, code)?;
+            &converted
+        }
+        None => mat,
+    };
+
+    let height = mat.rows() as usize;
+    let width = mat.cols() as usize;
+    let row_step = width * encoding.bytes_per_pixel();
+    let mut data = Vec::with_capacity(row_step * height);
+
+    // A Mat is not continuous if it is a ROI of a larger Mat or has
+    // padded rows. Copy it row by row in that case.
+    if mat.is_continuous() {
+        data.extend_from_slice(mat.data_bytes()?);
+    } else {
+        for row in 0..height {
+            let row = mat.row(row as i32)?;
+            data.extend_from_slice(row.data_bytes()?);
+        }
+    }
+
+    Ok(Image {
+        header,
+        height: height as u32,
+        width: width as u32,
+        encoding: encoding.to_string(),
+        is_bigendian: cfg!(target_endian = "big") as u8,
+        step: row_step as u32,
+        data,
+    })
+}
+
+/// Decodes a compressed image using OpenCV's imdecode.
+///
+/// The decoded image is converted back to the original encoding in
+/// the format string. QOI is not supported.
+pub fn decode_compressed_image_with_opencv(compressed: &CompressedImage) -> Result<Image> {
+    let format: Option<CompressedFormat> = compressed.format.parse().ok();
+    let mat = compressed_image_to_mat(compressed)?;
+    let encoding = mat_encoding(&mat)?;
+    let image = mat_to_image(&mat, &encoding.to_string(), compressed.header.clone())?;
+    restore_encoding(image, format.as_ref())
+}
+
+/// Compresses an image using OpenCV's imencode.
+///
+/// The image is converted by [image_to_mat] first, so color images
+/// are compressed in BGR order. QOI is not supported.
+pub fn encode_compressed_image_with_opencv(
+    image: &Image,
+    format: CompressionFormat,
+    quality: u8,
+) -> Result<CompressedImage> {
+    let encoding = check_image(image)?;
+    let mat = image_to_mat(image)?;
+    mat_to_compressed_image(&mat, Some(encoding), format, quality, image.header.clone())
+}
+
+fn compressed_image_to_mat(compressed: &CompressedImage) -> Result<Mat> {
+    let buf = Vector::<u8>::from_slice(&compressed.data);
+    let mat = imgcodecs::imdecode(&buf, imgcodecs::IMREAD_UNCHANGED)?;
+    ensure!(
+        !mat.empty(),
+        "unable to decode the compressed image in format '{}'",
+        compressed.format
+    );
+    Ok(mat)
+}
+
+/// Compresses a Mat in OpenCV's channel order. `encoding` is the
+/// original image encoding noted in the format string.
+fn mat_to_compressed_image(
+    mat: &Mat,
+    encoding: Option<ImageEncoding>,
+    format: CompressionFormat,
+    quality: u8,
+    header: Header,
+) -> Result<CompressedImage> {
+    let quality = quality as i32;
+    let (ext, params) = match format {
+        CompressionFormat::Jpeg => (".jpg", [imgcodecs::IMWRITE_JPEG_QUALITY, quality]),
+        CompressionFormat::Png => (".png", [imgcodecs::IMWRITE_PNG_COMPRESSION, quality]),
+        CompressionFormat::WebP => (".webp", [imgcodecs::IMWRITE_WEBP_QUALITY, quality]),
+        CompressionFormat::Qoi => bail!("QOI is not supported by OpenCV"),
+    };
+
+    // JPEG and WebP only store 8-bit samples.
+    let converted;
+    let mat = if format != CompressionFormat::Png && mat.depth() == CV_16U {
+        let mut dst = Mat::default();
+        mat.convert_to(&mut dst, CV_8U, 1.0 / 256.0, 0.0)?;
+        converted = dst;
+        &converted
+    } else {
+        mat
+    };
+    let compressed_encoding = mat_encoding(mat)?;
+
+    let mut buf = Vector::<u8>::new();
+    let ok = imgcodecs::imencode(ext, mat, &mut buf, &Vector::from_slice(&params))?;
+    ensure!(ok, "unable to encode the image as {format}");
+
+    let format = CompressedFormat {
+        encoding: Some(encoding.unwrap_or(compressed_encoding)),
+        format,
+        compressed_encoding: Some(compressed_encoding),
+    };
+
+    Ok(CompressedImage {
+        header,
+        format: format.to_string(),
+        data: buf.to_vec(),
+    })
+}
+
+/// Returns the mono, BGR or BGRA encoding of an 8-bit or 16-bit Mat.
+fn mat_encoding(mat: &Mat) -> Result<ImageEncoding> {
+    use ImageEncoding as E;
+
+    let encoding = match (mat.depth(), mat.channels()) {
+        (CV_8U, 1) => E::Mono8,
+        (CV_8U, 3) => E::Bgr8,
+        (CV_8U, 4) => E::Bgra8,
+        (CV_16U, 1) => E::Mono16,
+        (CV_16U, 3) => E::Bgr16,
+        (CV_16U, 4) => E::Bgra16,
+        (depth, channels) => {
+            bail!("unsupported Mat with depth {depth} and {channels} channels")
+        }
+    };
+    Ok(encoding)
+}
+
+/// Checks whether the memory layout of the encoding is identical to
+/// an OpenCV Mat.
+fn is_native(encoding: ImageEncoding) -> bool {
+    use ImageEncoding as E;
+
+    matches!(
+        encoding,
+        E::Mono8 | E::Mono16 | E::Bgr8 | E::Bgra8 | E::Bgr16 | E::Bgra16 | E::Generic { .. }
+    )
+}
+
+/// Returns the OpenCV type of the encoding's pixels as stored in the
+/// image data.
+fn cv_type(encoding: ImageEncoding) -> i32 {
+    let depth = match encoding.channel_type() {
+        ChannelType::U8 => CV_8U,
+        ChannelType::I8 => CV_8S,
+        ChannelType::U16 => CV_16U,
+        ChannelType::I16 => CV_16S,
+        ChannelType::I32 => CV_32S,
+        ChannelType::F32 => CV_32F,
+        ChannelType::F64 => CV_64F,
+    };
+    make_type(depth, encoding.channels() as i32)
+}
+
+fn make_type(depth: i32, channels: i32) -> i32 {
+    (depth & 7) + ((channels - 1) << 3)
+}
+
+/// Copies the image data into a newly allocated Mat, without
+/// converting the channels.
+fn raw_to_mat(image: &Image, encoding: ImageEncoding) -> Result<Mat> {
     let Image {
         height,
         width,
         step: row_step,
         ref data,
+        is_bigendian,
         ..
     } = *image;
 
-    let is_bigendian = image.is_bigendian != 0;
-    ensure!(!is_bigendian);
-    let pixel_step = 3;
-    ensure!(row_step == width * pixel_step);
-    ensure!(data.len() == (row_step * height) as usize);
+    bytes_to_mat(
+        data,
+        height as usize,
+        width as usize,
+        row_step as usize,
+        is_bigendian != 0,
+        encoding,
+    )
+}
 
-    let mut mat =
-        Mat::new_rows_cols_with_default(height as i32, width as i32, CV_8UC3, Scalar::all(0.0))?;
+fn bytes_to_mat(
+    data: &[u8],
+    rows: usize,
+    cols: usize,
+    row_step: usize,
+    is_bigendian: bool,
+    encoding: ImageEncoding,
+) -> Result<Mat> {
+    let elem_size = encoding.channel_type().size();
+    let row_size = cols * encoding.bytes_per_pixel();
+
+    ensure!(
+        row_step >= row_size,
+        "Invalid step {row_step}. Expect at least {row_size} bytes."
+    );
+    ensure!(
+        data.len() >= row_step * rows,
+        "Invalid data size. Expect {} bytes, but get {} bytes.",
+        row_step * rows,
+        data.len()
+    );
+
+    let mut mat = Mat::new_rows_cols_with_default(
+        rows as i32,
+        cols as i32,
+        cv_type(encoding),
+        Scalar::all(0.0),
+    )?;
+
+    if row_size > 0 {
+        let swap_bytes = elem_size > 1 && is_bigendian != cfg!(target_endian = "big");
+        let mat_bytes = mat.data_bytes_mut()?;
+
+        mat_bytes
+            .chunks_exact_mut(row_size)
+            .zip(data.chunks(row_step))
+            .for_each(|(dst, src)| {
+                dst.copy_from_slice(&src[0..row_size]);
 
-    data.chunks_exact(3).enumerate().for_each(|(pidx, bytes)| {
-        let col = pidx % width as usize;
-        let row = pidx / width as usize;
-        let pixel: &mut Vec3b = mat.at_2d_mut(row as i32, col as i32).unwrap();
-        let bytes: [u8; 3] = bytes.try_into().unwrap();
-        *pixel = VecN(bytes);
-    });
+                if swap_bytes {
+                    dst.chunks_exact_mut(elem_size)
+                        .for_each(|elem| elem.reverse());
+                }
+            });
+    }
 
     Ok(mat)
 }
 
-fn rgb8_to_mat(image: &Image) -> Result<Mat> {
+fn cvt_color(src: &Mat, code: i32) -> Result<Mat> {
+    let mut dst = Mat::default();
+    imgproc::cvt_color(src, &mut dst, code, 0)?;
+    Ok(dst)
+}
+
+/// Converts planar YUV 4:2:0 images, which OpenCV expects as a
+/// single-channel Mat with the chroma planes below the Y plane.
+fn yuv420_to_mat(image: &Image, code: i32) -> Result<Mat> {
     let Image {
         height,
         width,
@@ -65,24 +917,59 @@ fn rgb8_to_mat(image: &Image) -> Result<Mat> {
         ..
     } = *image;
 
-    let is_bigendian = image.is_bigendian != 0;
-    ensure!(!is_bigendian);
-    let pixel_step = 3;
-    ensure!(row_step == width * pixel_step);
-    ensure!(data.len() == (row_step * height) as usize);
+    ensure!(
+        height.is_multiple_of(2) && width.is_multiple_of(2),
+        "YUV 4:2:0 image size must be even, but get {width}x{height}"
+    );
+    ensure!(
+        row_step == width,
+        "Invalid step {row_step} for YUV 4:2:0 image"
+    );
 
-    let mut mat =
-        Mat::new_rows_cols_with_default(height as i32, width as i32, CV_8UC3, Scalar::all(0.0))?;
+    let rows = height as usize * 3 / 2;
+    let yuv = bytes_to_mat(
+        data,
+        rows,
+        width as usize,
+        row_step as usize,
+        false,
+        ImageEncoding::Mono8,
+    )?;
+    cvt_color(&yuv, code)
+}
 
-    data.chunks_exact(3).enumerate().for_each(|(pidx, bytes)| {
-        let col = pidx % width as usize;
-        let row = pidx / width as usize;
-        let pixel: &mut Vec3b = mat.at_2d_mut(row as i32, col as i32).unwrap();
-        let [r, g, b]: [u8; 3] = bytes.try_into().unwrap();
-        *pixel = VecN([b, g, r]);
-    });
+fn nv24_to_mat(image: &Image) -> Result<Mat> {
+    let Image {
+        height,
+        width,
+        step: row_step,
+        ref data,
+        ..
+    } = *image;
+    let n_pixels = (width * height) as usize;
 
-    Ok(mat)
+    ensure!(row_step == width, "Invalid step {row_step} for NV24 image");
+    ensure!(
+        data.len() >= n_pixels * 3,
+        "Invalid data size. Expect {} bytes, but get {} bytes.",
+        n_pixels * 3,
+        data.len()
+    );
+
+    // OpenCV has no NV24 conversion. Interleave the Y plane and the
+    // full resolution UV plane into a 3-channel YUV image instead.
+    let (y_plane, uv_plane) = data.split_at(n_pixels);
+    let mut yuv =
+        Mat::new_rows_cols_with_default(height as i32, width as i32, CV_8UC3, Scalar::all(0.0))?;
+    yuv.data_bytes_mut()?
+        .chunks_exact_mut(3)
+        .zip(y_plane)
+        .zip(uv_plane.chunks_exact(2))
+        .for_each(|((pixel, &y), uv)| {
+            pixel.copy_from_slice(&[y, uv[0], uv[1]]);
+        });
+
+    cvt_color(&yuv, imgproc::COLOR_YUV2BGR)
 }
 
 #[cfg(feature = "nightly")]
@@ -115,6 +1002,7 @@ fn uyvy_to_mat(image: &Image) -> Result<Mat> {
 }
 
 #[cfg(not(feature = "nightly"))]
-fn uyvy_to_mat(_image: &Image) -> Result<Mat> {
-    bail!("UYVY image to OpenCV Mat is not implemented for stable version");
+fn uyvy_to_mat(image: &Image) -> Result<Mat> {
+    let yuv = raw_to_mat(image, ImageEncoding::Yuv422)?;
+    cvt_color(&yuv, imgproc::COLOR_YUV2BGR_UYVY)
 }
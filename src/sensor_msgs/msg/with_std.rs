@@ -1,26 +1,267 @@
-use r2r::sensor_msgs::msg::PointCloud2;
-use std::slice::Chunks;
+pub use bayer::*;
+mod bayer;
+
+pub use camera::*;
+mod camera;
+
+pub use cloud_projection::*;
+mod cloud_projection;
+
+pub use compressed::*;
+mod compressed;
+
+pub use compressed_depth::*;
+mod compressed_depth;
+
+pub use convert::*;
+mod convert;
+
+pub use depth_cloud::*;
+mod depth_cloud;
+
+pub use encoding::*;
+mod encoding;
+
+pub use ops::*;
+mod ops;
+
+pub use point_field::*;
+mod point_field;
+
+pub use rectify::*;
+mod rectify;
+
+pub use rvl::*;
+mod rvl;
+
+pub use typed_point::*;
+mod typed_point;
+
+pub use view::*;
+mod view;
+
+pub use yuv::*;
+mod yuv;
+
+use anyhow::Result;
+use r2r::{
+    sensor_msgs::msg::{Image, PointCloud2},
+    std_msgs::msg::Header,
+};
+use std::{
+    iter::{FusedIterator, Take},
+    slice::Chunks,
+};
+
+/// Iterates over the rows of a point cloud. It is created by
+/// [row_bytes_iter](PointCloud2Ext::row_bytes_iter).
+#[derive(Debug, Clone)]
+pub struct RowBytesIter<'a> {
+    rows: Take<Chunks<'a, u8>>,
+    row_size: usize,
+    point_step: usize,
+}
+
+impl<'a> RowBytesIter<'a> {
+    fn trim(&self, row: &'a [u8]) -> &'a [u8] {
+        // Drop the incomplete point if the last row is truncated.
+        let size = self
+            .row_size
+            .min(row.len() / self.point_step * self.point_step);
+        &row[0..size]
+    }
+}
+
+impl<'a> Iterator for RowBytesIter<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let row = self.rows.next()?;
+        Some(self.trim(row))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.rows.size_hint()
+    }
+}
+
+impl<'a> DoubleEndedIterator for RowBytesIter<'a> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let row = self.rows.next_back()?;
+        Some(self.trim(row))
+    }
+}
+
+impl<'a> ExactSizeIterator for RowBytesIter<'a> {}
+
+impl<'a> FusedIterator for RowBytesIter<'a> {}
 
 pub type PointBytesIter<'a> = Box<dyn Iterator<Item = Chunks<'a, u8>> + Sync + Send + 'a>;
+pub type IndexedPointBytesIter<'a> =
+    Box<dyn Iterator<Item = (usize, usize, &'a [u8])> + Sync + Send + 'a>;
 
 pub trait PointCloud2Ext {
-    fn row_bytes_iter(&self) -> Chunks<'_, u8>;
+    /// Iterates over the `height` rows. Each row has `width` points
+    /// without the padding at the end.
+    fn row_bytes_iter(&self) -> RowBytesIter<'_>;
+
+    /// Iterates over the rows, each of which is an iterator of point
+    /// bytes.
     fn point_bytes_iter(&self) -> PointBytesIter<'_>;
+
+    /// Iterates over the points with their (row, column) indices.
+    fn indexed_point_bytes_iter(&self) -> IndexedPointBytesIter<'_>;
+
+    /// Returns the bytes of a row without padding, or `None` if the
+    /// row is out of bounds.
+    fn row_at(&self, row: usize) -> Option<&[u8]>;
+
+    /// Returns the bytes of the point at (row, column) of an
+    /// organized point cloud, or `None` if it is out of bounds.
+    fn point_at(&self, row: usize, col: usize) -> Option<&[u8]>;
+
+    /// Looks up a field by name to decode it across all points.
+    fn field(&self, name: &str) -> Option<FieldAccessor<'_>>;
+
+    /// Decodes a named field across all points, such as "intensity"
+    /// or "ring". See [FieldAccessor::iter].
+    fn field_iter<T>(&self, name: &str) -> Result<Box<dyn Iterator<Item = T> + Sync + Send + '_>>
+    where
+        T: PointFieldValue;
+
+    /// Decodes the points to a user-defined point type. The fields
+    /// of the type are matched by name, so the point cloud may have
+    /// more fields or a different field order.
+    fn typed_iter<T>(&self) -> Result<Box<dyn Iterator<Item = T> + Sync + Send + '_>>
+    where
+        T: PointCloud2Point;
+
+    /// Builds an unorganized point cloud with the field layout of the
+    /// point type. See [points_to_pointcloud2] for the `is_dense` flag.
+    fn from_points<T>(points: &[T], header: Header) -> Self
+    where
+        Self: Sized,
+        T: PointCloud2Point;
 }
 
 impl PointCloud2Ext for PointCloud2 {
-    fn row_bytes_iter(&self) -> Chunks<'_, u8> {
+    fn row_bytes_iter(&self) -> RowBytesIter<'_> {
         let Self {
-            row_step, ref data, ..
+            height,
+            width,
+            point_step,
+            row_step,
+            ref data,
+            ..
         } = *self;
+        let point_step = point_step.max(1) as usize;
 
-        data.chunks(row_step as usize)
+        RowBytesIter {
+            rows: data.chunks(row_step.max(1) as usize).take(height as usize),
+            row_size: width as usize * point_step,
+            point_step,
+        }
     }
 
     fn point_bytes_iter(&self) -> PointBytesIter<'_> {
+        let point_step = self.point_step.max(1) as usize;
+        let iter = self.row_bytes_iter().map(move |row| row.chunks(point_step));
+        Box::new(iter)
+    }
+
+    fn indexed_point_bytes_iter(&self) -> IndexedPointBytesIter<'_> {
+        let point_step = self.point_step.max(1) as usize;
         let iter = self
             .row_bytes_iter()
-            .map(|row| row.chunks(self.point_step as usize));
+            .enumerate()
+            .flat_map(move |(row_idx, row)| {
+                row.chunks(point_step)
+                    .enumerate()
+                    .map(move |(col_idx, point)| (row_idx, col_idx, point))
+            });
         Box::new(iter)
     }
+
+    fn row_at(&self, row: usize) -> Option<&[u8]> {
+        if row >= self.height as usize {
+            return None;
+        }
+        let start = row * self.row_step as usize;
+        let end = start + self.width as usize * self.point_step as usize;
+        self.data.get(start..end)
+    }
+
+    fn point_at(&self, row: usize, col: usize) -> Option<&[u8]> {
+        if col >= self.width as usize {
+            return None;
+        }
+        let point_step = self.point_step as usize;
+        let start = col * point_step;
+        self.row_at(row)?.get(start..start + point_step)
+    }
+
+    fn field(&self, name: &str) -> Option<FieldAccessor<'_>> {
+        FieldAccessor::new(self, name)
+    }
+
+    fn field_iter<T>(&self, name: &str) -> Result<Box<dyn Iterator<Item = T> + Sync + Send + '_>>
+    where
+        T: PointFieldValue,
+    {
+        let iter = pointcloud2_field_iter(self, name)?;
+        Ok(Box::new(iter))
+    }
+
+    fn typed_iter<T>(&self) -> Result<Box<dyn Iterator<Item = T> + Sync + Send + '_>>
+    where
+        T: PointCloud2Point,
+    {
+        let iter = pointcloud2_to_typed_iter(self)?;
+        Ok(Box::new(iter))
+    }
+
+    fn from_points<T>(points: &[T], header: Header) -> Self
+    where
+        T: PointCloud2Point,
+    {
+        points_to_pointcloud2(points, header)
+    }
+}
+
+pub trait ImageExt
+where
+    Self: Sized,
+{
+    fn convert_encoding(&self, target: &str) -> Result<Self>;
+    fn yuv_to_rgb8(&self) -> Result<Self>;
+    fn yuv_to_bgr8(&self) -> Result<Self>;
+    fn demosaic(&self, method: DemosaicMethod, order: RgbOrder) -> Result<Self>;
+    fn view<P: ImagePixel>(&self) -> Result<ImageView<'_, P>>;
+    fn view_mut<P: ImagePixel>(&mut self) -> Result<ImageViewMut<'_, P>>;
+}
+
+impl ImageExt for Image {
+    fn convert_encoding(&self, target: &str) -> Result<Self> {
+        image_convert_encoding(self, target)
+    }
+
+    fn yuv_to_rgb8(&self) -> Result<Self> {
+        image_yuv_to_rgb8(self, RgbOrder::Rgb)
+    }
+
+    fn yuv_to_bgr8(&self) -> Result<Self> {
+        image_yuv_to_rgb8(self, RgbOrder::Bgr)
+    }
+
+    fn demosaic(&self, method: DemosaicMethod, order: RgbOrder) -> Result<Self> {
+        image_demosaic(self, method, order)
+    }
+
+    fn view<P: ImagePixel>(&self) -> Result<ImageView<'_, P>> {
+        ImageView::new(self)
+    }
+
+    fn view_mut<P: ImagePixel>(&mut self) -> Result<ImageViewMut<'_, P>> {
+        ImageViewMut::new(self)
+    }
 }
@@ -0,0 +1,443 @@
+use super::{check_image, RgbOrder};
+use anyhow::{bail, ensure, Result};
+use r2r::sensor_msgs::msg::Image;
+
+/// The color filter arrangement of a Bayer image, named after the
+/// colors of the top-left 2x2 block in row-major order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BayerPattern {
+    Rggb,
+    Bggr,
+    Gbrg,
+    Grbg,
+}
+
+impl BayerPattern {
+    /// Returns the channel index, 0 for red, 1 for green and 2 for
+    /// blue, of the color filter at the pixel.
+    pub fn color_at(&self, x: usize, y: usize) -> usize {
+        let cfa = match self {
+            Self::Rggb => [0, 1, 1, 2],
+            Self::Bggr => [2, 1, 1, 0],
+            Self::Gbrg => [1, 2, 0, 1],
+            Self::Grbg => [1, 0, 2, 1],
+        };
+        cfa[(y % 2) * 2 + x % 2]
+    }
+}
+
+/// The interpolation algorithms of Bayer demosaicing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum DemosaicMethod {
+    /// Bilinear interpolation. It is the same algorithm as OpenCV's
+    /// default, but the borders and the rounding may differ.
+    #[default]
+    Bilinear,
+    /// The gradient-corrected linear interpolation by Malvar, He and
+    /// Cutler, which has fewer color artifacts along edges.
+    MalvarHeCutler,
+}
+
+/// Demosaics a Bayer image to an rgb8/bgr8 image, or an rgb16/bgr16
+/// image for 16-bit Bayer encodings.
+pub fn image_demosaic(image: &Image, method: DemosaicMethod, order: RgbOrder) -> Result<Image> {
+    let Image {
+        ref header,
+        height,
+        width,
+        is_bigendian,
+        step: row_step,
+        ref data,
+        ..
+    } = *image;
+
+    let encoding = check_image(image)?;
+    let Some(pattern) = encoding.bayer_pattern() else {
+        bail!("unsupported Bayer image format '{encoding}'");
+    };
+    let width = width as usize;
+    let height = height as usize;
+    let row_step = row_step as usize;
+    let elem_size = encoding.channel_type().size();
+
+    ensure!(
+        width >= 2 && height >= 2,
+        "Bayer image must be at least 2x2, but get {width}x{height}"
+    );
+
+    let is_bigendian = is_bigendian != 0;
+    let samples: Vec<u16> = data
+        .chunks(row_step)
+        .take(height)
+        .flat_map(|row| row[0..width * elem_size].chunks_exact(elem_size))
+        .map(|bytes| match *bytes {
+            [val] => val as u16,
+            [b0, b1] if is_bigendian => u16::from_be_bytes([b0, b1]),
+            [b0, b1] => u16::from_le_bytes([b0, b1]),
+            _ => unreachable!(),
+        })
+        .collect();
+
+    let mut rgb = match method {
+        DemosaicMethod::Bilinear => demosaic_bilinear(&samples, width, height, pattern),
+        DemosaicMethod::MalvarHeCutler => {
+            let max_value = if elem_size == 1 {
+                u8::MAX as u16
+            } else {
+                u16::MAX
+            };
+            demosaic_malvar_he_cutler(&samples, width, height, pattern, max_value)
+        }
+    };
+
+    if order == RgbOrder::Bgr {
+        rgb.chunks_exact_mut(3).for_each(|pixel| pixel.swap(0, 2));
+    }
+
+    let (encoding, data): (_, Vec<u8>) = match (elem_size, order) {
+        (1, RgbOrder::Rgb) => ("rgb8", rgb.into_iter().map(|val| val as u8).collect()),
+        (1, RgbOrder::Bgr) => ("bgr8", rgb.into_iter().map(|val| val as u8).collect()),
+        (_, RgbOrder::Rgb) => (
+            "rgb16",
+            rgb.into_iter().flat_map(u16::to_ne_bytes).collect(),
+        ),
+        (_, RgbOrder::Bgr) => (
+            "bgr16",
+            rgb.into_iter().flat_map(u16::to_ne_bytes).collect(),
+        ),
+    };
+
+    Ok(Image {
+        header: header.clone(),
+        height: height as u32,
+        width: width as u32,
+        encoding: encoding.to_string(),
+        is_bigendian: cfg!(target_endian = "big") as u8,
+        step: (width * 3 * elem_size) as u32,
+        data,
+    })
+}
+
+/// Interpolates the missing color channels of each pixel from the
+/// average of its nearest neighbors having the color. It returns the
+/// interleaved RGB samples.
+pub fn demosaic_bilinear(
+    samples: &[u16],
+    width: usize,
+    height: usize,
+    pattern: BayerPattern,
+) -> Vec<u16> {
+    let sample = |x: isize, y: isize| -> u32 {
+        let x = reflect(x, width);
+        let y = reflect(y, height);
+        samples[y * width + x] as u32
+    };
+    let cross = |x: isize, y: isize| {
+        (sample(x - 1, y) + sample(x + 1, y) + sample(x, y - 1) + sample(x, y + 1) + 2) / 4
+    };
+    let diagonal = |x: isize, y: isize| {
+        (sample(x - 1, y - 1)
+            + sample(x + 1, y - 1)
+            + sample(x - 1, y + 1)
+            + sample(x + 1, y + 1)
+            + 2)
+            / 4
+    };
+    let horizontal = |x: isize, y: isize| (sample(x - 1, y) + sample(x + 1, y)).div_ceil(2);
+    let vertical = |x: isize, y: isize| (sample(x, y - 1) + sample(x, y + 1)).div_ceil(2);
+
+    let mut rgb = vec![0u16; width * height * 3];
+
+    rgb.chunks_exact_mut(3)
+        .enumerate()
+        .for_each(|(idx, pixel)| {
+            let xu = idx % width;
+            let yu = idx / width;
+            let x = xu as isize;
+            let y = yu as isize;
+            let center = sample(x, y);
+
+            let [r, g, b] = match pattern.color_at(xu, yu) {
+                0 => [center, cross(x, y), diagonal(x, y)],
+                2 => [diagonal(x, y), cross(x, y), center],
+                _ => {
+                    if pattern.color_at(xu + 1, yu) == 0 {
+                        [horizontal(x, y), center, vertical(x, y)]
+                    } else {
+                        [vertical(x, y), center, horizontal(x, y)]
+                    }
+                }
+            };
+
+            pixel[0] = r as u16;
+            pixel[1] = g as u16;
+            pixel[2] = b as u16;
+        });
+
+    rgb
+}
+
+/// Interpolates the missing color channels using the 5x5 kernels of
+/// Malvar, He and Cutler, "High-Quality Linear Interpolation for
+/// Demosaicing of Bayer-Patterned Color Images", 2004. The bilinear
+/// estimate is corrected by the Laplacian of the known channel. It
+/// returns the interleaved RGB samples clamped to `max_value`.
+pub fn demosaic_malvar_he_cutler(
+    samples: &[u16],
+    width: usize,
+    height: usize,
+    pattern: BayerPattern,
+    max_value: u16,
+) -> Vec<u16> {
+    let sample = |x: isize, y: isize| -> i32 {
+        let x = reflect(x, width);
+        let y = reflect(y, height);
+        samples[y * width + x] as i32
+    };
+
+    // The kernels are scaled by 16 so that all weights are integers.
+    let cross = |x: isize, y: isize| {
+        sample(x - 1, y) + sample(x + 1, y) + sample(x, y - 1) + sample(x, y + 1)
+    };
+    let far_cross = |x: isize, y: isize| {
+        sample(x - 2, y) + sample(x + 2, y) + sample(x, y - 2) + sample(x, y + 2)
+    };
+    let diagonal = |x: isize, y: isize| {
+        sample(x - 1, y - 1) + sample(x + 1, y - 1) + sample(x - 1, y + 1) + sample(x + 1, y + 1)
+    };
+
+    // Green at red and blue pixels.
+    let green = |x: isize, y: isize| 8 * sample(x, y) + 4 * cross(x, y) - 2 * far_cross(x, y);
+
+    // Red or blue at green pixels, from the horizontal or the vertical
+    // neighbors.
+    let horizontal = |x: isize, y: isize| {
+        10 * sample(x, y) + 8 * (sample(x - 1, y) + sample(x + 1, y))
+            - 2 * (sample(x - 2, y) + sample(x + 2, y))
+            - 2 * diagonal(x, y)
+            + (sample(x, y - 2) + sample(x, y + 2))
+    };
+    let vertical = |x: isize, y: isize| {
+        10 * sample(x, y) + 8 * (sample(x, y - 1) + sample(x, y + 1))
+            - 2 * (sample(x, y - 2) + sample(x, y + 2))
+            - 2 * diagonal(x, y)
+            + (sample(x - 2, y) + sample(x + 2, y))
+    };
+
+    // Red at blue pixels and blue at red pixels.
+    let opposite =
+        |x: isize, y: isize| 12 * sample(x, y) + 4 * diagonal(x, y) - 3 * far_cross(x, y);
+
+    let normalize = |val: i32| ((val + 8) >> 4).clamp(0, max_value as i32) as u16;
+
+    let mut rgb = vec![0u16; width * height * 3];
+
+    rgb.chunks_exact_mut(3)
+        .enumerate()
+        .for_each(|(idx, pixel)| {
+            let xu = idx % width;
+            let yu = idx / width;
+            let x = xu as isize;
+            let y = yu as isize;
+            let center = sample(x, y) as u16;
+
+            let [r, g, b] = match pattern.color_at(xu, yu) {
+                0 => [center, normalize(green(x, y)), normalize(opposite(x, y))],
+                2 => [normalize(opposite(x, y)), normalize(green(x, y)), center],
+                _ => {
+                    if pattern.color_at(xu + 1, yu) == 0 {
+                        [
+                            normalize(horizontal(x, y)),
+                            center,
+                            normalize(vertical(x, y)),
+                        ]
+                    } else {
+                        [
+                            normalize(vertical(x, y)),
+                            center,
+                            normalize(horizontal(x, y)),
+                        ]
+                    }
+                }
+            };
+
+            pixel[0] = r;
+            pixel[1] = g;
+            pixel[2] = b;
+        });
+
+    rgb
+}
+
+/// Reflects an out-of-range index back into `0..len` without
+/// repeating the border, which preserves the parity of the color
+/// filter array.
+fn reflect(idx: isize, len: usize) -> usize {
+    let len = len as isize;
+    if len == 1 {
+        return 0;
+    }
+
+    let period = 2 * (len - 1);
+    let idx = idx.rem_euclid(period);
+    if idx < len {
+        idx as usize
+    } else {
+        (period - idx) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATTERNS: [(BayerPattern, &str); 4] = [
+        (BayerPattern::Rggb, "rggb"),
+        (BayerPattern::Bggr, "bggr"),
+        (BayerPattern::Gbrg, "gbrg"),
+        (BayerPattern::Grbg, "grbg"),
+    ];
+    const METHODS: [DemosaicMethod; 2] = [DemosaicMethod::Bilinear, DemosaicMethod::MalvarHeCutler];
+
+    /// Samples a flat color through the color filter array.
+    fn mosaic(pattern: BayerPattern, width: usize, height: usize, rgb: [u16; 3]) -> Vec<u16> {
+        (0..height)
+            .flat_map(|y| (0..width).map(move |x| rgb[pattern.color_at(x, y)]))
+            .collect()
+    }
+
+    fn bayer_image(
+        pattern: &str,
+        bits: u32,
+        width: usize,
+        height: usize,
+        samples: &[u16],
+        is_bigendian: bool,
+    ) -> Image {
+        let data: Vec<u8> = match bits {
+            8 => samples.iter().map(|&val| val as u8).collect(),
+            _ if is_bigendian => samples.iter().flat_map(|val| val.to_be_bytes()).collect(),
+            _ => samples.iter().flat_map(|val| val.to_le_bytes()).collect(),
+        };
+        Image {
+            height: height as u32,
+            width: width as u32,
+            encoding: format!("bayer_{pattern}{bits}"),
+            is_bigendian: is_bigendian as u8,
+            step: (width * bits as usize / 8) as u32,
+            data,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn color_filter_arrangement() {
+        let colors = |pattern: BayerPattern| {
+            [(0, 0), (1, 0), (0, 1), (1, 1)].map(|(x, y)| pattern.color_at(x, y))
+        };
+        assert_eq!(colors(BayerPattern::Rggb), [0, 1, 1, 2]);
+        assert_eq!(colors(BayerPattern::Bggr), [2, 1, 1, 0]);
+        assert_eq!(colors(BayerPattern::Gbrg), [1, 2, 0, 1]);
+        assert_eq!(colors(BayerPattern::Grbg), [1, 0, 2, 1]);
+        assert_eq!(BayerPattern::Rggb.color_at(3, 5), 2);
+        assert_eq!(BayerPattern::Rggb.color_at(2, 5), 1);
+    }
+
+    #[test]
+    fn demosaic_flat_color() {
+        // Odd sizes cover the reflection at the borders.
+        let (width, height) = (7, 5);
+        let rgb = [200, 120, 40];
+
+        for (pattern, name) in PATTERNS {
+            let samples = mosaic(pattern, width, height, rgb);
+            let image = bayer_image(name, 8, width, height, &samples, false);
+
+            for method in METHODS {
+                let out = image_demosaic(&image, method, RgbOrder::Rgb).unwrap();
+                assert_eq!(out.encoding, "rgb8");
+                assert_eq!(out.step as usize, width * 3);
+                assert!(
+                    out.data
+                        .chunks_exact(3)
+                        .all(|pixel| pixel == [200, 120, 40]),
+                    "{name} {method:?}"
+                );
+
+                let out = image_demosaic(&image, method, RgbOrder::Bgr).unwrap();
+                assert_eq!(out.encoding, "bgr8");
+                assert!(
+                    out.data
+                        .chunks_exact(3)
+                        .all(|pixel| pixel == [40, 120, 200]),
+                    "{name} {method:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn demosaic_flat_color_16bit() {
+        let (width, height) = (6, 4);
+        let rgb = [60000, 1000, 30000];
+
+        for (pattern, name) in PATTERNS {
+            let samples = mosaic(pattern, width, height, rgb);
+
+            for is_bigendian in [false, true] {
+                let image = bayer_image(name, 16, width, height, &samples, is_bigendian);
+
+                for method in METHODS {
+                    let out = image_demosaic(&image, method, RgbOrder::Rgb).unwrap();
+                    assert_eq!(out.encoding, "rgb16");
+                    assert_eq!(out.step as usize, width * 6);
+
+                    let pixels: Vec<u16> = out
+                        .data
+                        .chunks_exact(2)
+                        .map(|bytes| u16::from_ne_bytes([bytes[0], bytes[1]]))
+                        .collect();
+                    assert!(
+                        pixels.chunks_exact(3).all(|pixel| pixel == rgb),
+                        "{name} {method:?} big-endian {is_bigendian}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn demosaic_known_values() {
+        // An RGGB mosaic where only the red pixel at (2, 2) is lit.
+        let mut samples = vec![0; 25];
+        samples[2 * 5 + 2] = 100;
+
+        let rgb = demosaic_bilinear(&samples, 5, 5, BayerPattern::Rggb);
+        let red = |x: usize, y: usize| rgb[(y * 5 + x) * 3];
+        assert_eq!(red(2, 2), 100);
+        assert_eq!([red(1, 2), red(3, 2), red(2, 1), red(2, 3)], [50; 4]);
+        assert_eq!([red(1, 1), red(3, 3)], [25; 2]);
+        assert_eq!(red(0, 0), 0);
+        assert!(rgb
+            .chunks_exact(3)
+            .all(|pixel| pixel[1] == 0 && pixel[2] == 0));
+
+        // The Laplacian correction is clamped to the sample range.
+        let rgb = demosaic_malvar_he_cutler(&samples, 5, 5, BayerPattern::Rggb, 255);
+        let pixel = |x: usize, y: usize| &rgb[(y * 5 + x) * 3..(y * 5 + x) * 3 + 3];
+        assert_eq!(pixel(2, 2), [100, 50, 75]);
+        assert_eq!(pixel(0, 2), [0, 0, 0]);
+    }
+
+    #[test]
+    fn demosaic_invalid_images() {
+        let image = bayer_image("rggb", 8, 1, 2, &[0, 0], false);
+        assert!(image_demosaic(&image, DemosaicMethod::Bilinear, RgbOrder::Rgb).is_err());
+
+        let image = Image {
+            encoding: "rgb8".to_string(),
+            ..bayer_image("rggb", 8, 2, 2, &[0; 12], false)
+        };
+        assert!(image_demosaic(&image, DemosaicMethod::Bilinear, RgbOrder::Rgb).is_err());
+    }
+}
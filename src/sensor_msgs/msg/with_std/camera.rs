@@ -0,0 +1,310 @@
+use super::camera_info_at_full_resolution;
+use anyhow::{bail, ensure, Result};
+use r2r::sensor_msgs::msg::CameraInfo;
+
+const UNDISTORT_ITERATIONS: usize = 20;
+const UNDISTORT_EPSILON: f64 = 1e-12;
+
+/// The lens distortion of a camera info, with the coefficients in the
+/// order of the D vector.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Distortion {
+    /// No distortion.
+    None,
+    /// The "plumb_bob" model, with the coefficients (k1, k2, p1, p2, k3).
+    PlumbBob([f64; 5]),
+    /// The "rational_polynomial" model, with the coefficients (k1, k2,
+    /// p1, p2, k3, k4, k5, k6).
+    RationalPolynomial([f64; 8]),
+    /// The "equidistant" fisheye model, with the coefficients (k1, k2,
+    /// k3, k4).
+    Equidistant([f64; 4]),
+}
+
+impl Distortion {
+    /// Reads the distortion model and the D vector. Missing trailing
+    /// coefficients are treated as zeros.
+    pub fn from_camera_info(camera_info: &CameraInfo) -> Result<Self> {
+        let CameraInfo {
+            ref distortion_model,
+            ref d,
+            ..
+        } = *camera_info;
+
+        fn coefficients<const N: usize>(model: &str, d: &[f64]) -> Result<[f64; N]> {
+            ensure!(
+                d.len() <= N,
+                "The '{model}' distortion model has {N} coefficients, but get {}",
+                d.len()
+            );
+            let mut coefs = [0.0; N];
+            coefs[0..d.len()].copy_from_slice(d);
+            Ok(coefs)
+        }
+
+        let is_zero = d.iter().all(|&coef| coef == 0.0);
+
+        let distortion = match distortion_model.as_str() {
+            "plumb_bob" if is_zero => Self::None,
+            "plumb_bob" => Self::PlumbBob(coefficients("plumb_bob", d)?),
+            "rational_polynomial" if is_zero => Self::None,
+            "rational_polynomial" => {
+                Self::RationalPolynomial(coefficients("rational_polynomial", d)?)
+            }
+            "equidistant" => Self::Equidistant(coefficients("equidistant", d)?),
+            "" if d.is_empty() => Self::None,
+            model => bail!("unsupported distortion model '{model}'"),
+        };
+        Ok(distortion)
+    }
+
+    /// Applies the distortion to a point on the normalized image plane.
+    pub fn distort(&self, x: f64, y: f64) -> (f64, f64) {
+        match *self {
+            Self::None => (x, y),
+            Self::PlumbBob([k1, k2, p1, p2, k3]) => {
+                distort_rational(x, y, [k1, k2, p1, p2, k3, 0.0, 0.0, 0.0])
+            }
+            Self::RationalPolynomial(coefs) => distort_rational(x, y, coefs),
+            Self::Equidistant(coefs) => {
+                let r = x.hypot(y);
+                if r < f64::EPSILON {
+                    return (x, y);
+                }
+                let theta = r.atan();
+                let scale = equidistant_theta(theta, coefs) / r;
+                (x * scale, y * scale)
+            }
+        }
+    }
+
+    /// Removes the distortion from a point on the normalized image
+    /// plane. It inverts [distort](Self::distort) iteratively.
+    pub fn undistort(&self, x: f64, y: f64) -> (f64, f64) {
+        match *self {
+            Self::None => (x, y),
+            Self::PlumbBob([k1, k2, p1, p2, k3]) => {
+                undistort_rational(x, y, [k1, k2, p1, p2, k3, 0.0, 0.0, 0.0])
+            }
+            Self::RationalPolynomial(coefs) => undistort_rational(x, y, coefs),
+            Self::Equidistant([k1, k2, k3, k4]) => {
+                let theta_d = x.hypot(y);
+                if theta_d < f64::EPSILON {
+                    return (x, y);
+                }
+
+                // Solve theta_d = theta * (1 + k1 theta^2 + ...) by
+                // Newton's method.
+                let mut theta = theta_d;
+                for _ in 0..UNDISTORT_ITERATIONS {
+                    let t2 = theta * theta;
+                    let f = equidistant_theta(theta, [k1, k2, k3, k4]) - theta_d;
+                    let df =
+                        1.0 + t2 * (3.0 * k1 + t2 * (5.0 * k2 + t2 * (7.0 * k3 + t2 * 9.0 * k4)));
+                    let step = f / df;
+                    theta -= step;
+                    if step.abs() < UNDISTORT_EPSILON {
+                        break;
+                    }
+                }
+
+                let scale = theta.tan() / theta_d;
+                (x * scale, y * scale)
+            }
+        }
+    }
+}
+
+/// The pinhole camera model of a camera info.
+///
+/// The binning and the region of interest are folded into the
+/// intrinsics, so that pixels are in the coordinates of the published
+/// images.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PinholeCamera {
+    pub width: u32,
+    pub height: u32,
+    /// The row-major intrinsic matrix.
+    pub k: [f64; 9],
+    /// The row-major rectification rotation.
+    pub r: [f64; 9],
+    /// The row-major projection matrix of rectified images.
+    pub p: [f64; 12],
+    pub distortion: Distortion,
+}
+
+impl PinholeCamera {
+    pub fn from_camera_info(camera_info: &CameraInfo) -> Result<Self> {
+        let distortion = Distortion::from_camera_info(camera_info)?;
+        let info = camera_info_at_full_resolution(camera_info)?;
+
+        // An empty R is treated as identity, as monocular cameras do.
+        let r = match info.r.len() {
+            0 => [1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0],
+            9 => info.r[..].try_into().unwrap(),
+            len => bail!("Invalid camera info. Expect 9 elements in R, but get {len}."),
+        };
+
+        Ok(Self {
+            width: info.width,
+            height: info.height,
+            k: info.k[..].try_into().unwrap(),
+            r,
+            p: info.p[..].try_into().unwrap(),
+            distortion,
+        })
+    }
+
+    /// Projects a point in the camera optical frame to a pixel on the
+    /// raw image. It returns `None` if the point is not in front of
+    /// the camera.
+    pub fn project(&self, point: [f64; 3]) -> Option<[f64; 2]> {
+        let [x, y, z] = point;
+        if z.is_nan() || z <= 0.0 {
+            return None;
+        }
+        let (x, y) = self.distortion.distort(x / z, y / z);
+        Some(self.normalized_to_pixel(x, y))
+    }
+
+    /// Computes the ray through a pixel on the raw image. The ray is
+    /// in the camera optical frame and scaled to z = 1.
+    pub fn unproject(&self, pixel: [f64; 2]) -> [f64; 3] {
+        let (x, y) = self.pixel_to_normalized(pixel);
+        let (x, y) = self.distortion.undistort(x, y);
+        [x, y, 1.0]
+    }
+
+    fn normalized_to_pixel(&self, x: f64, y: f64) -> [f64; 2] {
+        let [fx, skew, cx, _, fy, cy, ..] = self.k;
+        [fx * x + skew * y + cx, fy * y + cy]
+    }
+
+    fn pixel_to_normalized(&self, pixel: [f64; 2]) -> (f64, f64) {
+        let [fx, skew, cx, _, fy, cy, ..] = self.k;
+        let [u, v] = pixel;
+        let y = (v - cy) / fy;
+        let x = (u - cx - skew * y) / fx;
+        (x, y)
+    }
+}
+
+fn distort_rational(x: f64, y: f64, coefs: [f64; 8]) -> (f64, f64) {
+    let [k1, k2, p1, p2, k3, k4, k5, k6] = coefs;
+    let r2 = x * x + y * y;
+    let radial = (1.0 + r2 * (k1 + r2 * (k2 + r2 * k3))) / (1.0 + r2 * (k4 + r2 * (k5 + r2 * k6)));
+    let dx = 2.0 * p1 * x * y + p2 * (r2 + 2.0 * x * x);
+    let dy = p1 * (r2 + 2.0 * y * y) + 2.0 * p2 * x * y;
+    (x * radial + dx, y * radial + dy)
+}
+
+/// Inverts the distortion by fixed-point iteration, the same way as
+/// OpenCV's undistortPoints().
+fn undistort_rational(xd: f64, yd: f64, coefs: [f64; 8]) -> (f64, f64) {
+    let [k1, k2, p1, p2, k3, k4, k5, k6] = coefs;
+    let (mut x, mut y) = (xd, yd);
+
+    for _ in 0..UNDISTORT_ITERATIONS {
+        let r2 = x * x + y * y;
+        let inv_radial =
+            (1.0 + r2 * (k4 + r2 * (k5 + r2 * k6))) / (1.0 + r2 * (k1 + r2 * (k2 + r2 * k3)));
+        let dx = 2.0 * p1 * x * y + p2 * (r2 + 2.0 * x * x);
+        let dy = p1 * (r2 + 2.0 * y * y) + 2.0 * p2 * x * y;
+        let next_x = (xd - dx) * inv_radial;
+        let next_y = (yd - dy) * inv_radial;
+        let delta = (next_x - x).abs() + (next_y - y).abs();
+        (x, y) = (next_x, next_y);
+
+        if delta < UNDISTORT_EPSILON {
+            break;
+        }
+    }
+
+    (x, y)
+}
+
+fn equidistant_theta(theta: f64, coefs: [f64; 4]) -> f64 {
+    let [k1, k2, k3, k4] = coefs;
+    let t2 = theta * theta;
+    theta * (1.0 + t2 * (k1 + t2 * (k2 + t2 * (k3 + t2 * k4))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn camera(distortion: Distortion) -> PinholeCamera {
+        PinholeCamera {
+            width: 1280,
+            height: 720,
+            k: [700.0, 0.0, 640.0, 0.0, 700.0, 360.0, 0.0, 0.0, 1.0],
+            r: [1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0],
+            p: [
+                700.0, 0.0, 640.0, 0.0, 0.0, 700.0, 360.0, 0.0, 0.0, 0.0, 1.0, 0.0,
+            ],
+            distortion,
+        }
+    }
+
+    /// Points at various depths. The corner points are outside the
+    /// view of an undistorted camera, but are projected within 60
+    /// pixels of the image corners by the tested distortions.
+    fn points() -> Vec<[f64; 3]> {
+        let rays = [
+            [0.0, 0.0],
+            [0.4, -0.2],
+            [-1.25, -0.68],
+            [1.25, -0.68],
+            [-1.25, 0.68],
+            [1.25, 0.68],
+        ];
+        rays.into_iter()
+            .zip([1.0, 0.5, 2.5, 5.0, 10.0, 40.0])
+            .map(|([x, y], z)| [x * z, y * z, z])
+            .collect()
+    }
+
+    fn check_round_trip(distortion: Distortion) {
+        let camera = camera(distortion);
+
+        for point @ [x, y, z] in points() {
+            let pixel @ [u, v] = camera.project(point).unwrap();
+            assert!((0.0..1280.0).contains(&u) && (0.0..720.0).contains(&v));
+
+            let [rx, ry, rz] = camera.unproject(pixel);
+
+            // The iterative undistortion stops before reaching machine
+            // precision near the corners. 1e-6 is about a thousandth of
+            // a pixel.
+            assert_eq!(rz, 1.0);
+            assert!(
+                (rx - x / z).abs() < 1e-6 && (ry - y / z).abs() < 1e-6,
+                "{distortion:?}: {point:?} is projected to {pixel:?} and unprojected to [{rx}, {ry}]"
+            );
+        }
+    }
+
+    #[test]
+    fn plumb_bob_round_trip() {
+        check_round_trip(Distortion::PlumbBob([-0.28, 0.07, 0.0002, -0.0001, 0.0]));
+    }
+
+    #[test]
+    fn rational_polynomial_round_trip() {
+        check_round_trip(Distortion::RationalPolynomial([
+            2.1, 0.9, 0.0004, -0.0003, 0.03, 2.5, 1.6, 0.15,
+        ]));
+    }
+
+    #[test]
+    fn equidistant_round_trip() {
+        check_round_trip(Distortion::Equidistant([-0.01, 0.005, -0.002, 0.0005]));
+    }
+
+    #[test]
+    fn project_behind_camera() {
+        let camera = camera(Distortion::None);
+        assert_eq!(camera.project([0.0, 0.0, 0.0]), None);
+        assert_eq!(camera.project([1.0, 1.0, -1.0]), None);
+    }
+}
@@ -0,0 +1,667 @@
+use super::{
+    check_image, point_chunks, ChannelType, FieldReader, ImageEncoding, ImageExt, PinholeCamera,
+    PointFieldLayout, PointFieldType,
+};
+use anyhow::{ensure, Result};
+use r2r::{
+    geometry_msgs::msg::{Quaternion, Transform, Vector3},
+    sensor_msgs::msg::{CameraInfo, Image, PointCloud2},
+};
+use std::borrow::Cow;
+
+/// The value mapped to the colors of painted points.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PointColoring {
+    /// The distance to the camera in meters.
+    Range { min: f64, max: f64 },
+    /// The value of the "intensity" field.
+    Intensity { min: f64, max: f64 },
+}
+
+/// The options of [paint_pointcloud2].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PaintOptions {
+    /// Values between the bounds are mapped to the jet colormap from
+    /// blue to red.
+    pub coloring: PointColoring,
+    /// The radius of painted points in pixels. Zero paints single
+    /// pixels.
+    pub radius: u32,
+}
+
+impl Default for PaintOptions {
+    fn default() -> Self {
+        Self {
+            coloring: PointColoring::Range {
+                min: 0.0,
+                max: 50.0,
+            },
+            radius: 1,
+        }
+    }
+}
+
+/// The treatment of points that are behind the camera, outside the
+/// image or have non-finite coordinates in [colorize_pointcloud2].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum OutOfViewPolicy {
+    /// Removes the points. The point cloud becomes unorganized.
+    Drop,
+    /// Keeps the points with zero rgb values.
+    #[default]
+    KeepUncolored,
+    /// Keeps the points with the given color in RGB order.
+    DefaultColor([u8; 3]),
+}
+
+/// Projections of point clouds into camera images.
+///
+/// The transform maps points from the point cloud frame to the camera
+/// optical frame. Points are projected onto the raw images of the
+/// camera, taking the distortion, binning and region of interest of
+/// the camera info into account.
+pub trait PointCloud2CameraExt {
+    fn to_depth_image(&self, transform: &Transform, camera_info: &CameraInfo) -> Result<Image>;
+
+    fn paint_on_image(
+        &self,
+        image: &Image,
+        transform: &Transform,
+        camera_info: &CameraInfo,
+        options: &PaintOptions,
+    ) -> Result<Image>;
+
+    fn colorize(
+        &self,
+        image: &Image,
+        transform: &Transform,
+        camera_info: &CameraInfo,
+        policy: OutOfViewPolicy,
+    ) -> Result<PointCloud2>;
+}
+
+impl PointCloud2CameraExt for PointCloud2 {
+    fn to_depth_image(&self, transform: &Transform, camera_info: &CameraInfo) -> Result<Image> {
+        pointcloud2_to_depth_image(self, transform, camera_info)
+    }
+
+    fn paint_on_image(
+        &self,
+        image: &Image,
+        transform: &Transform,
+        camera_info: &CameraInfo,
+        options: &PaintOptions,
+    ) -> Result<Image> {
+        paint_pointcloud2(image, self, transform, camera_info, options)
+    }
+
+    fn colorize(
+        &self,
+        image: &Image,
+        transform: &Transform,
+        camera_info: &CameraInfo,
+        policy: OutOfViewPolicy,
+    ) -> Result<PointCloud2> {
+        colorize_pointcloud2(self, image, transform, camera_info, policy)
+    }
+}
+
+/// Renders a point cloud to a 32FC1 depth image in meters. Where
+/// several points fall on the same pixel, the nearest one is kept.
+/// Pixels without points are NaN.
+///
+/// The image has the frame of the camera info and the time stamp of
+/// the point cloud.
+pub fn pointcloud2_to_depth_image(
+    pcd: &PointCloud2,
+    transform: &Transform,
+    camera_info: &CameraInfo,
+) -> Result<Image> {
+    let camera = PinholeCamera::from_camera_info(camera_info)?;
+    let points = project_points(pcd, transform, &camera, None)?;
+    let width = camera.width as usize;
+    let height = camera.height as usize;
+    let mut depths = vec![f32::NAN; width * height];
+
+    for point in points {
+        let depth = &mut depths[point.v * width + point.u];
+        let z = point.depth as f32;
+        if depth.is_nan() || z < *depth {
+            *depth = z;
+        }
+    }
+
+    let encoding = ImageEncoding::Generic {
+        channel_type: ChannelType::F32,
+        channels: 1,
+    };
+    let mut header = camera_info.header.clone();
+    header.stamp = pcd.header.stamp.clone();
+
+    Ok(Image {
+        header,
+        height: height as u32,
+        width: width as u32,
+        encoding: encoding.to_string(),
+        is_bigendian: cfg!(target_endian = "big") as u8,
+        step: encoding.step(width as u32)?,
+        data: depths
+            .iter()
+            .flat_map(|depth| depth.to_ne_bytes())
+            .collect(),
+    })
+}
+
+/// Paints the points of a point cloud onto an image of the camera.
+/// Nearer points are painted over farther ones.
+///
+/// The output is bgr8 for bgr8 images, and rgb8 for other encodings.
+pub fn paint_pointcloud2(
+    image: &Image,
+    pcd: &PointCloud2,
+    transform: &Transform,
+    camera_info: &CameraInfo,
+    options: &PaintOptions,
+) -> Result<Image> {
+    let camera = PinholeCamera::from_camera_info(camera_info)?;
+    ensure!(
+        image.width == camera.width && image.height == camera.height,
+        "The camera info is for {}x{} images, but the image is {}x{}",
+        camera.width,
+        camera.height,
+        image.width,
+        image.height
+    );
+
+    let encoding = check_image(image)?;
+    let image = match encoding {
+        ImageEncoding::Rgb8 | ImageEncoding::Bgr8 => Cow::Borrowed(image),
+        _ => Cow::Owned(image.convert_encoding("rgb8")?),
+    };
+    let is_bgr = encoding == ImageEncoding::Bgr8;
+
+    let (intensity, min, max) = match options.coloring {
+        PointColoring::Range { min, max } => (None, min, max),
+        PointColoring::Intensity { min, max } => {
+            (Some(FieldReader::new(pcd, "intensity")?), min, max)
+        }
+    };
+    let mut points = project_points(pcd, transform, &camera, intensity.as_ref())?;
+    points.sort_by(|lhs, rhs| rhs.depth.total_cmp(&lhs.depth));
+
+    let width = image.width as i64;
+    let height = image.height as i64;
+    let row_step = image.step as usize;
+    let radius = options.radius as i64;
+    let mut image = image.into_owned();
+
+    for point in points {
+        let t = if max > min {
+            ((point.value - min) / (max - min)).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let [r, g, b] = jet_color(t);
+        let color = if is_bgr { [b, g, r] } else { [r, g, b] };
+
+        for dv in -radius..=radius {
+            for du in -radius..=radius {
+                if du * du + dv * dv > radius * radius {
+                    continue;
+                }
+                let u = point.u as i64 + du;
+                let v = point.v as i64 + dv;
+                if !(0..width).contains(&u) || !(0..height).contains(&v) {
+                    continue;
+                }
+
+                let offset = v as usize * row_step + u as usize * 3;
+                image.data[offset..offset + 3].copy_from_slice(&color);
+            }
+        }
+    }
+
+    Ok(image)
+}
+
+/// Appends a packed "rgb" field to a point cloud, taking the colors
+/// from the pixels the points project to. Occlusions are not
+/// considered, so points hidden behind others take the color of the
+/// occluder.
+///
+/// The image is converted to rgb8 if it is not rgb8 or bgr8.
+pub fn colorize_pointcloud2(
+    pcd: &PointCloud2,
+    image: &Image,
+    transform: &Transform,
+    camera_info: &CameraInfo,
+    policy: OutOfViewPolicy,
+) -> Result<PointCloud2> {
+    let camera = PinholeCamera::from_camera_info(camera_info)?;
+    ensure!(
+        image.width == camera.width && image.height == camera.height,
+        "The camera info is for {}x{} images, but the image is {}x{}",
+        camera.width,
+        camera.height,
+        image.width,
+        image.height
+    );
+    ensure!(
+        pcd.fields.iter().all(|field| field.name != "rgb"),
+        "The point cloud already has an rgb field"
+    );
+
+    let encoding = check_image(image)?;
+    let image = match encoding {
+        ImageEncoding::Rgb8 | ImageEncoding::Bgr8 => Cow::Borrowed(image),
+        _ => Cow::Owned(image.convert_encoding("rgb8")?),
+    };
+    let is_bgr = encoding == ImageEncoding::Bgr8;
+    let row_step = image.step as usize;
+
+    let projector = PointProjector::new(pcd, transform, &camera)?;
+    let mut layout = PointFieldLayout::from_fields(pcd.fields.clone(), pcd.point_step);
+    let rgb_offset = layout.push("rgb", PointFieldType::F32, 1) as usize;
+    let point_step = layout.point_step() as usize;
+
+    let pack = |[r, g, b]: [u8; 3]| {
+        let [r, g, b] = [r, g, b].map(u32::from);
+        let rgb = f32::from_bits((r << 16) | (g << 8) | b);
+        if pcd.is_bigendian {
+            rgb.to_be_bytes()
+        } else {
+            rgb.to_le_bytes()
+        }
+    };
+
+    let mut data = Vec::with_capacity(pcd.data.len() / pcd.point_step.max(1) as usize * point_step);
+    let mut num_points = 0;
+
+    for bytes in point_chunks(pcd)? {
+        let color = match projector.project(bytes) {
+            Some(([u, v], _)) => {
+                let offset = v * row_step + u * 3;
+                let [c0, c1, c2]: [u8; 3] = image.data[offset..offset + 3].try_into().unwrap();
+                Some(if is_bgr { [c2, c1, c0] } else { [c0, c1, c2] })
+            }
+            None => match policy {
+                OutOfViewPolicy::Drop => None,
+                OutOfViewPolicy::KeepUncolored => Some([0, 0, 0]),
+                OutOfViewPolicy::DefaultColor(color) => Some(color),
+            },
+        };
+        let Some(color) = color else {
+            continue;
+        };
+
+        data.extend_from_slice(&bytes[0..rgb_offset]);
+        data.extend_from_slice(&pack(color));
+        num_points += 1;
+    }
+
+    let (height, width, is_dense) = match policy {
+        OutOfViewPolicy::Drop => (1, num_points, true),
+        _ => (pcd.height, pcd.width, pcd.is_dense),
+    };
+
+    Ok(PointCloud2 {
+        header: pcd.header.clone(),
+        height,
+        width,
+        fields: layout.into_fields(),
+        is_bigendian: pcd.is_bigendian,
+        point_step: point_step as u32,
+        row_step: point_step as u32 * width,
+        data,
+        is_dense,
+    })
+}
+
+struct ProjectedPoint {
+    u: usize,
+    v: usize,
+    /// The z coordinate in the camera optical frame.
+    depth: f64,
+    /// The range, or the field value if a field reader is given.
+    value: f64,
+}
+
+/// Projects the points to pixels inside the image, dropping points
+/// with non-finite coordinates.
+fn project_points(
+    pcd: &PointCloud2,
+    transform: &Transform,
+    camera: &PinholeCamera,
+    field: Option<&FieldReader>,
+) -> Result<Vec<ProjectedPoint>> {
+    let projector = PointProjector::new(pcd, transform, camera)?;
+
+    let points = point_chunks(pcd)?
+        .filter_map(|bytes| {
+            let ([u, v], [x, y, z]) = projector.project(bytes)?;
+            let value = match field {
+                Some(field) => field.read(bytes),
+                None => (x * x + y * y + z * z).sqrt(),
+            };
+
+            Some(ProjectedPoint {
+                u,
+                v,
+                depth: z,
+                value,
+            })
+        })
+        .collect();
+
+    Ok(points)
+}
+
+/// Projects points in the point cloud frame to the nearest pixels.
+struct PointProjector<'a> {
+    x: FieldReader,
+    y: FieldReader,
+    z: FieldReader,
+    transform: &'a Transform,
+    camera: &'a PinholeCamera,
+}
+
+impl<'a> PointProjector<'a> {
+    fn new(pcd: &PointCloud2, transform: &'a Transform, camera: &'a PinholeCamera) -> Result<Self> {
+        Ok(Self {
+            x: FieldReader::new(pcd, "x")?,
+            y: FieldReader::new(pcd, "y")?,
+            z: FieldReader::new(pcd, "z")?,
+            transform,
+            camera,
+        })
+    }
+
+    /// Returns the pixel and the point in the camera optical frame, or
+    /// `None` if the point has non-finite coordinates or is out of
+    /// view.
+    fn project(&self, bytes: &[u8]) -> Option<([usize; 2], [f64; 3])> {
+        let point = [self.x.read(bytes), self.y.read(bytes), self.z.read(bytes)];
+        if !point.iter().all(|value| value.is_finite()) {
+            return None;
+        }
+
+        let point = transform_point(self.transform, point);
+        let [u, v] = self.camera.project(point)?;
+        let (u, v) = (u.round(), v.round());
+        let width = self.camera.width as f64;
+        let height = self.camera.height as f64;
+        if !(0.0..width).contains(&u) || !(0.0..height).contains(&v) {
+            return None;
+        }
+
+        Some(([u as usize, v as usize], point))
+    }
+}
+
+/// Applies a transform to a point, normalizing the rotation first.
+fn transform_point(transform: &Transform, point: [f64; 3]) -> [f64; 3] {
+    let Transform {
+        translation: Vector3 {
+            x: tx,
+            y: ty,
+            z: tz,
+        },
+        rotation: Quaternion { x, y, z, w },
+    } = *transform;
+
+    let norm = (x * x + y * y + z * z + w * w).sqrt();
+    let [qx, qy, qz, qw] = [x / norm, y / norm, z / norm, w / norm];
+    let [px, py, pz] = point;
+
+    // v' = v + 2 q x (q x v + w v)
+    let cx = qy * pz - qz * py + qw * px;
+    let cy = qz * px - qx * pz + qw * py;
+    let cz = qx * py - qy * px + qw * pz;
+
+    [
+        px + 2.0 * (qy * cz - qz * cy) + tx,
+        py + 2.0 * (qz * cx - qx * cz) + ty,
+        pz + 2.0 * (qx * cy - qy * cx) + tz,
+    ]
+}
+
+/// Maps a value in [0, 1] to the jet colormap in RGB order.
+fn jet_color(t: f64) -> [u8; 3] {
+    let channel = |center: f64| {
+        let value = (1.5 - (4.0 * t - center).abs()).clamp(0.0, 1.0);
+        (value * 255.0).round() as u8
+    };
+    [channel(3.0), channel(2.0), channel(1.0)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: u32 = 4;
+    const HEIGHT: u32 = 3;
+
+    fn camera_info() -> CameraInfo {
+        CameraInfo {
+            width: WIDTH,
+            height: HEIGHT,
+            k: vec![2.0, 0.0, 1.0, 0.0, 2.0, 1.0, 0.0, 0.0, 1.0],
+            r: vec![1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0],
+            p: vec![2.0, 0.0, 1.0, 0.0, 0.0, 2.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0],
+            ..Default::default()
+        }
+    }
+
+    /// Moves the points 1 meter forward along the optical axis.
+    fn transform() -> Transform {
+        Transform {
+            translation: Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: 1.0,
+            },
+            rotation: Quaternion {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+                w: 1.0,
+            },
+        }
+    }
+
+    /// The points in the point cloud frame and the pixels they project
+    /// to after [transform].
+    fn points() -> [([f32; 3], Option<[usize; 2]>); 6] {
+        [
+            // At 2 meters depth.
+            ([0.0, 0.0, 1.0], Some([1, 1])),
+            // The same pixel at 1 meter depth.
+            ([0.0, 0.0, 0.0], Some([1, 1])),
+            // Behind the camera.
+            ([0.0, 0.0, -3.0], None),
+            // Right of the image.
+            ([1.0, 0.0, -0.5], None),
+            ([1.0, -0.5, 0.0], Some([3, 0])),
+            ([f32::NAN, 0.0, 0.0], None),
+        ]
+    }
+
+    fn pointcloud() -> PointCloud2 {
+        let mut layout = PointFieldLayout::new();
+        for name in ["x", "y", "z"] {
+            layout.push(name, PointFieldType::F32, 1);
+        }
+        let points = points();
+        let point_step = layout.point_step();
+
+        PointCloud2 {
+            height: 1,
+            width: points.len() as u32,
+            fields: layout.into_fields(),
+            is_bigendian: false,
+            point_step,
+            row_step: point_step * points.len() as u32,
+            data: points
+                .iter()
+                .flat_map(|(point, _)| point.iter().flat_map(|value| value.to_le_bytes()))
+                .collect(),
+            is_dense: false,
+            ..Default::default()
+        }
+    }
+
+    fn image(encoding: &str, data: Vec<u8>) -> Image {
+        Image {
+            height: HEIGHT,
+            width: WIDTH,
+            encoding: encoding.to_string(),
+            step: WIDTH * 3,
+            data,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn depth_image_culling() {
+        let depth = pointcloud()
+            .to_depth_image(&transform(), &camera_info())
+            .unwrap();
+        assert_eq!((depth.width, depth.height), (WIDTH, HEIGHT));
+        assert_eq!(depth.encoding, "32FC1");
+
+        let depths: Vec<_> = depth
+            .data
+            .chunks_exact(4)
+            .map(|bytes| f32::from_ne_bytes(bytes.try_into().unwrap()))
+            .collect();
+        for (idx, depth) in depths.into_iter().enumerate() {
+            match (idx % WIDTH as usize, idx / WIDTH as usize) {
+                // The nearer point wins.
+                (1, 1) => assert_eq!(depth, 1.0),
+                (3, 0) => assert_eq!(depth, 1.0),
+                _ => assert!(depth.is_nan(), "pixel {idx} has depth {depth}"),
+            }
+        }
+    }
+
+    #[test]
+    fn paint_nearest_on_top() {
+        let options = PaintOptions {
+            coloring: PointColoring::Range { min: 0.0, max: 4.0 },
+            radius: 0,
+        };
+        let pcd = pointcloud();
+
+        for (encoding, is_bgr) in [("rgb8", false), ("bgr8", true)] {
+            let image = image(encoding, vec![0; (WIDTH * HEIGHT * 3) as usize]);
+            let painted = pcd
+                .paint_on_image(&image, &transform(), &camera_info(), &options)
+                .unwrap();
+            assert_eq!(painted.encoding, encoding);
+
+            let color = |range: f64| {
+                let [r, g, b] = jet_color(range / 4.0);
+                if is_bgr {
+                    [b, g, r]
+                } else {
+                    [r, g, b]
+                }
+            };
+            for (idx, pixel) in painted.data.chunks_exact(3).enumerate() {
+                let expect = match (idx % WIDTH as usize, idx / WIDTH as usize) {
+                    (1, 1) => color(1.0),
+                    (3, 0) => color(1.5),
+                    _ => [0, 0, 0],
+                };
+                assert_eq!(pixel, expect, "pixel {idx}");
+            }
+        }
+    }
+
+    #[test]
+    fn colorize_policies() {
+        let pcd = pointcloud();
+        let info = camera_info();
+        // Each pixel has a distinct color.
+        let data: Vec<u8> = (0..WIDTH * HEIGHT)
+            .flat_map(|idx| [idx as u8, 100 + idx as u8, 200 + idx as u8])
+            .collect();
+        let pixel_color = |[u, v]: [usize; 2]| {
+            let idx = (v * WIDTH as usize + u) as u8;
+            [idx, 100 + idx, 200 + idx]
+        };
+        let read_colors = |colorized: &PointCloud2| -> Vec<[u8; 3]> {
+            let field = colorized.fields.last().unwrap();
+            assert_eq!(field.name, "rgb");
+            let offset = field.offset as usize;
+
+            colorized
+                .data
+                .chunks_exact(colorized.point_step as usize)
+                .map(|point| {
+                    let [b, g, r, _] = point[offset..offset + 4].try_into().unwrap();
+                    [r, g, b]
+                })
+                .collect()
+        };
+
+        for encoding in ["rgb8", "bgr8"] {
+            let mut image = image(encoding, data.clone());
+            if encoding == "bgr8" {
+                image
+                    .data
+                    .chunks_exact_mut(3)
+                    .for_each(|pixel| pixel.reverse());
+            }
+
+            let colorized = pcd
+                .colorize(&image, &transform(), &info, OutOfViewPolicy::Drop)
+                .unwrap();
+            let expect: Vec<_> = points()
+                .into_iter()
+                .filter_map(|(_, pixel)| Some(pixel_color(pixel?)))
+                .collect();
+            assert_eq!((colorized.width, colorized.height), (3, 1));
+            assert!(colorized.is_dense);
+            assert_eq!(read_colors(&colorized), expect);
+
+            for (policy, default) in [
+                (OutOfViewPolicy::KeepUncolored, [0, 0, 0]),
+                (OutOfViewPolicy::DefaultColor([1, 2, 3]), [1, 2, 3]),
+            ] {
+                let colorized = pcd.colorize(&image, &transform(), &info, policy).unwrap();
+                let expect: Vec<_> = points()
+                    .into_iter()
+                    .map(|(_, pixel)| pixel.map(pixel_color).unwrap_or(default))
+                    .collect();
+                assert_eq!((colorized.width, colorized.height), (6, 1));
+                assert!(!colorized.is_dense);
+                assert_eq!(read_colors(&colorized), expect);
+                // The xyz fields are kept.
+                assert!(colorized
+                    .data
+                    .chunks_exact(16)
+                    .zip(pcd.data.chunks_exact(12))
+                    .all(|(point, orig)| point[0..12] == *orig));
+            }
+        }
+
+        // An rgb field can only be added once.
+        let colorized = pcd
+            .colorize(
+                &image("rgb8", data),
+                &transform(),
+                &info,
+                Default::default(),
+            )
+            .unwrap();
+        assert!(colorized
+            .colorize(
+                &image("rgb8", vec![0; 36]),
+                &transform(),
+                &info,
+                Default::default()
+            )
+            .is_err());
+    }
+}
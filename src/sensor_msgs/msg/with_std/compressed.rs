@@ -0,0 +1,193 @@
+use super::ImageEncoding;
+#[cfg(any(feature = "with-image", feature = "with-opencv"))]
+use super::{decode_compressed_depth, CompressedDepthFormat};
+use anyhow::{bail, Error, Result};
+#[cfg(any(feature = "with-image", feature = "with-opencv"))]
+use r2r::sensor_msgs::msg::{CompressedImage, Image};
+use std::{fmt, str::FromStr};
+
+/// Image codecs used in `sensor_msgs/CompressedImage`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CompressionFormat {
+    Jpeg,
+    Png,
+    WebP,
+    Qoi,
+}
+
+impl CompressionFormat {
+    /// Returns the file extension of the codec, which is also the name
+    /// used in the image_transport format string.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            CompressionFormat::Jpeg => "jpeg",
+            CompressionFormat::Png => "png",
+            CompressionFormat::WebP => "webp",
+            CompressionFormat::Qoi => "qoi",
+        }
+    }
+}
+
+impl FromStr for CompressionFormat {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let format = match text.to_ascii_lowercase().as_str() {
+            "jpeg" | "jpg" => Self::Jpeg,
+            "png" => Self::Png,
+            "webp" => Self::WebP,
+            "qoi" => Self::Qoi,
+            _ => bail!("unsupported compression format '{text}'"),
+        };
+        Ok(format)
+    }
+}
+
+impl fmt::Display for CompressionFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.extension())
+    }
+}
+
+/// The `format` field of `sensor_msgs/CompressedImage` written by
+/// image_transport, such as "bgr8; jpeg compressed bgr8".
+///
+/// The first encoding is the one of the original image and the last
+/// one is the pixel layout passed to the codec. Both are optional in
+/// order to accept bare formats like "jpeg".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CompressedFormat {
+    pub encoding: Option<ImageEncoding>,
+    pub format: CompressionFormat,
+    pub compressed_encoding: Option<ImageEncoding>,
+}
+
+impl FromStr for CompressedFormat {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let (encoding, rest) = match text.split_once(';') {
+            Some((encoding, rest)) => (Some(encoding.trim().parse()?), rest),
+            None => (None, text),
+        };
+
+        let mut words = rest.split_whitespace();
+        let Some(format) = words.next() else {
+            bail!("invalid compressed image format '{text}'");
+        };
+        let format: CompressionFormat = format.parse()?;
+
+        let compressed_encoding = match (words.next(), words.next(), words.next()) {
+            (None, None, None) => None,
+            (Some("compressed"), None, None) => None,
+            (Some("compressed"), Some(encoding), None) => Some(encoding.parse()?),
+            _ => bail!("invalid compressed image format '{text}'"),
+        };
+
+        Ok(Self {
+            encoding,
+            format,
+            compressed_encoding,
+        })
+    }
+}
+
+impl fmt::Display for CompressedFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self {
+            encoding,
+            format,
+            compressed_encoding,
+        } = *self;
+
+        if let Some(encoding) = encoding {
+            write!(f, "{encoding}; ")?;
+        }
+        write!(f, "{format} compressed")?;
+        if let Some(compressed_encoding) = compressed_encoding {
+            write!(f, " {compressed_encoding}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Converts a decoded image to the original encoding in the format
+/// string. Encodings that cannot be converted back, such as Bayer,
+/// are left as decoded, like image_transport does.
+#[cfg(any(feature = "with-image", feature = "with-opencv"))]
+pub(crate) fn restore_encoding(image: Image, format: Option<&CompressedFormat>) -> Result<Image> {
+    use super::ImageExt;
+
+    let Some(encoding) = format.and_then(|format| format.encoding) else {
+        return Ok(image);
+    };
+    if !(encoding.is_color() || encoding.is_mono()) || image.encoding == encoding.to_string() {
+        return Ok(image);
+    }
+    image.convert_encoding(&encoding.to_string())
+}
+
+/// Decoding and encoding of compressed images, using OpenCV if the
+/// `with-opencv` feature is enabled and the image crate otherwise.
+#[cfg(any(feature = "with-image", feature = "with-opencv"))]
+pub trait CompressedImageExt
+where
+    Self: Sized,
+{
+    /// Decodes the image and converts it back to the original
+    /// encoding noted in the format string. compressedDepth images
+    /// are decoded by [decode_compressed_depth].
+    fn decode(&self) -> Result<Image>;
+
+    /// Compresses an image. The quality ranges from 1 to 100 for JPEG
+    /// and WebP. It is the compression level from 0 to 9 for PNG and
+    /// is ignored by QOI.
+    fn encode(image: &Image, format: CompressionFormat, quality: u8) -> Result<Self>;
+}
+
+#[cfg(any(feature = "with-image", feature = "with-opencv"))]
+impl CompressedImageExt for CompressedImage {
+    fn decode(&self) -> Result<Image> {
+        if self.format.parse::<CompressedDepthFormat>().is_ok() {
+            return decode_compressed_depth(self);
+        }
+
+        // OpenCV cannot read QOI. Leave it to the image crate.
+        #[cfg(all(feature = "with-opencv", feature = "with-image"))]
+        if let Ok(CompressedFormat {
+            format: CompressionFormat::Qoi,
+            ..
+        }) = self.format.parse()
+        {
+            return super::super::decode_compressed_image_with_image(self);
+        }
+
+        #[cfg(feature = "with-opencv")]
+        {
+            super::super::decode_compressed_image_with_opencv(self)
+        }
+
+        #[cfg(not(feature = "with-opencv"))]
+        {
+            super::super::decode_compressed_image_with_image(self)
+        }
+    }
+
+    fn encode(image: &Image, format: CompressionFormat, quality: u8) -> Result<Self> {
+        // OpenCV cannot write QOI. Leave it to the image crate.
+        #[cfg(all(feature = "with-opencv", feature = "with-image"))]
+        if format == CompressionFormat::Qoi {
+            return super::super::encode_compressed_image_with_image(image, format, quality);
+        }
+
+        #[cfg(feature = "with-opencv")]
+        {
+            super::super::encode_compressed_image_with_opencv(image, format, quality)
+        }
+
+        #[cfg(not(feature = "with-opencv"))]
+        {
+            super::super::encode_compressed_image_with_image(image, format, quality)
+        }
+    }
+}
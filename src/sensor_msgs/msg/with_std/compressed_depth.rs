@@ -0,0 +1,526 @@
+use super::{check_image, rvl_decode, rvl_encode, ChannelType, ImageEncoding};
+use anyhow::{bail, ensure, Error, Result};
+use r2r::sensor_msgs::msg::{CompressedImage, Image};
+use std::{fmt, str::FromStr};
+
+/// The codecs of image_transport's compressedDepth plugin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DepthCompression {
+    Png,
+    Rvl,
+}
+
+impl FromStr for DepthCompression {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let compression = match text {
+            "png" => Self::Png,
+            "rvl" => Self::Rvl,
+            _ => bail!("unsupported depth compression '{text}'"),
+        };
+        Ok(compression)
+    }
+}
+
+impl fmt::Display for DepthCompression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            DepthCompression::Png => "png",
+            DepthCompression::Rvl => "rvl",
+        };
+        f.write_str(text)
+    }
+}
+
+/// The `format` field of compressedDepth images, such as
+/// "16UC1; compressedDepth rvl".
+///
+/// The codec is missing in older image_transport versions, which only
+/// support PNG.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CompressedDepthFormat {
+    pub encoding: ImageEncoding,
+    pub compression: DepthCompression,
+}
+
+impl FromStr for CompressedDepthFormat {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let Some((encoding, rest)) = text.split_once(';') else {
+            bail!("invalid compressedDepth format '{text}'");
+        };
+        let encoding: ImageEncoding = encoding.trim().parse()?;
+
+        let mut words = rest.split_whitespace();
+        let compression = match (words.next(), words.next(), words.next()) {
+            (Some("compressedDepth"), None, None) => DepthCompression::Png,
+            (Some("compressedDepth"), Some(compression), None) => compression.parse()?,
+            _ => bail!("invalid compressedDepth format '{text}'"),
+        };
+
+        Ok(Self {
+            encoding,
+            compression,
+        })
+    }
+}
+
+impl fmt::Display for CompressedDepthFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}; compressedDepth {}", self.encoding, self.compression)
+    }
+}
+
+/// The 12-byte `ConfigHeader` preceding the compressed data of
+/// compressedDepth images.
+///
+/// For 32FC1 images, the depth is quantized to 16-bit inverse depth
+/// `depth_param[0] / depth + depth_param[1]` before compression.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CompressedDepthHeader {
+    pub format: i32,
+    pub depth_param: [f32; 2],
+}
+
+impl CompressedDepthHeader {
+    /// The header size in bytes.
+    pub const SIZE: usize = 12;
+
+    /// The only format defined by image_transport, inverse depth.
+    pub const INV_DEPTH: i32 = 0;
+
+    /// Parses the header from the first [Self::SIZE] bytes in
+    /// little-endian order.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        ensure!(
+            bytes.len() >= Self::SIZE,
+            "Invalid data size. Expect {} bytes, but get {} bytes.",
+            Self::SIZE,
+            bytes.len()
+        );
+
+        let word = |idx: usize| -> [u8; 4] { bytes[idx * 4..(idx + 1) * 4].try_into().unwrap() };
+
+        Ok(Self {
+            format: i32::from_le_bytes(word(0)),
+            depth_param: [f32::from_le_bytes(word(1)), f32::from_le_bytes(word(2))],
+        })
+    }
+
+    /// Serializes the header in little-endian order.
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0u8; Self::SIZE];
+        bytes[0..4].copy_from_slice(&self.format.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.depth_param[0].to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.depth_param[1].to_le_bytes());
+        bytes
+    }
+}
+
+/// Parameters of compressedDepth encoding. The defaults are the same
+/// as image_transport's.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CompressedDepthConfig {
+    pub compression: DepthCompression,
+    /// Depth values of 32FC1 images at or beyond this distance in
+    /// meters are dropped.
+    pub depth_max: f32,
+    /// The inverse depth quantization parameter of 32FC1 images.
+    pub depth_quantization: f32,
+    /// The PNG compression level from 0 to 9.
+    pub png_level: u8,
+}
+
+impl Default for CompressedDepthConfig {
+    fn default() -> Self {
+        Self {
+            compression: DepthCompression::Png,
+            depth_max: 10.0,
+            depth_quantization: 100.0,
+            png_level: 9,
+        }
+    }
+}
+
+pub trait CompressedDepthExt
+where
+    Self: Sized,
+{
+    fn is_compressed_depth(&self) -> bool;
+    fn decode_depth(&self) -> Result<Image>;
+    fn encode_depth(image: &Image, config: &CompressedDepthConfig) -> Result<Self>;
+}
+
+impl CompressedDepthExt for CompressedImage {
+    fn is_compressed_depth(&self) -> bool {
+        self.format.parse::<CompressedDepthFormat>().is_ok()
+    }
+
+    fn decode_depth(&self) -> Result<Image> {
+        decode_compressed_depth(self)
+    }
+
+    fn encode_depth(image: &Image, config: &CompressedDepthConfig) -> Result<Self> {
+        encode_compressed_depth(image, config)
+    }
+}
+
+/// Decodes a compressedDepth image to a 16UC1 or 32FC1 image.
+///
+/// Invalid depth values of 32FC1 images are decoded as NaN. PNG data
+/// requires the `with-image` or `with-opencv` feature.
+pub fn decode_compressed_depth(compressed: &CompressedImage) -> Result<Image> {
+    let format: CompressedDepthFormat = compressed.format.parse()?;
+    let header = CompressedDepthHeader::from_bytes(&compressed.data)?;
+    let payload = &compressed.data[CompressedDepthHeader::SIZE..];
+
+    let (width, height, samples) = match format.compression {
+        DepthCompression::Png => decode_png(payload)?,
+        DepthCompression::Rvl => {
+            ensure!(
+                payload.len() >= 8,
+                "Invalid data size. Expect at least {} bytes, but get {} bytes.",
+                CompressedDepthHeader::SIZE + 8,
+                compressed.data.len()
+            );
+            let width = u32::from_le_bytes(payload[0..4].try_into().unwrap());
+            let height = u32::from_le_bytes(payload[4..8].try_into().unwrap());
+            let Some(num_samples) = (width as usize).checked_mul(height as usize) else {
+                bail!("Invalid image size {width}x{height}");
+            };
+            let samples = rvl_decode(&payload[8..], num_samples)?;
+            (width, height, samples)
+        }
+    };
+
+    let data: Vec<u8> = match depth_type(format.encoding)? {
+        ChannelType::U16 => samples.into_iter().flat_map(u16::to_ne_bytes).collect(),
+        ChannelType::F32 => {
+            let [quant_a, quant_b] = header.depth_param;
+            samples
+                .into_iter()
+                .map(|inv_depth| {
+                    if inv_depth != 0 {
+                        quant_a / (inv_depth as f32 - quant_b)
+                    } else {
+                        f32::NAN
+                    }
+                })
+                .flat_map(f32::to_ne_bytes)
+                .collect()
+        }
+        _ => unreachable!(),
+    };
+
+    Ok(Image {
+        header: compressed.header.clone(),
+        height,
+        width,
+        encoding: format.encoding.to_string(),
+        is_bigendian: cfg!(target_endian = "big") as u8,
+        step: format.encoding.step(width)?,
+        data,
+    })
+}
+
+/// Encodes a 16UC1 or 32FC1 depth image the same way as
+/// image_transport's compressedDepth plugin.
+///
+/// PNG compression requires the `with-image` or `with-opencv`
+/// feature.
+pub fn encode_compressed_depth(
+    image: &Image,
+    config: &CompressedDepthConfig,
+) -> Result<CompressedImage> {
+    let Image {
+        height,
+        width,
+        step: row_step,
+        ref data,
+        is_bigendian,
+        ..
+    } = *image;
+
+    let encoding = check_image(image)?;
+    let channel_type = depth_type(encoding)?;
+    let elem_size = channel_type.size();
+    let is_bigendian = is_bigendian != 0;
+    let mut header = CompressedDepthHeader {
+        format: CompressedDepthHeader::INV_DEPTH,
+        depth_param: [0.0, 0.0],
+    };
+
+    let elems = data
+        .chunks(row_step as usize)
+        .take(height as usize)
+        .flat_map(|row| row[0..width as usize * elem_size].chunks_exact(elem_size));
+
+    let samples: Vec<u16> = match channel_type {
+        ChannelType::U16 => elems
+            .map(|bytes| {
+                let bytes = bytes.try_into().unwrap();
+                if is_bigendian {
+                    u16::from_be_bytes(bytes)
+                } else {
+                    u16::from_le_bytes(bytes)
+                }
+            })
+            .collect(),
+        ChannelType::F32 => {
+            let CompressedDepthConfig {
+                depth_max,
+                depth_quantization,
+                ..
+            } = *config;
+            let quant_a = depth_quantization * (depth_quantization + 1.0);
+            let quant_b = 1.0 - quant_a / depth_max;
+            header.depth_param = [quant_a, quant_b];
+
+            elems
+                .map(|bytes| {
+                    let bytes = bytes.try_into().unwrap();
+                    let depth = if is_bigendian {
+                        f32::from_be_bytes(bytes)
+                    } else {
+                        f32::from_le_bytes(bytes)
+                    };
+
+                    if depth > 0.0 && depth < depth_max {
+                        (quant_a / depth + quant_b) as u16
+                    } else {
+                        0
+                    }
+                })
+                .collect()
+        }
+        _ => unreachable!(),
+    };
+
+    let payload = match config.compression {
+        DepthCompression::Png => encode_png(width, height, samples, config.png_level)?,
+        DepthCompression::Rvl => {
+            let mut payload = Vec::with_capacity(8 + samples.len());
+            payload.extend(width.to_le_bytes());
+            payload.extend(height.to_le_bytes());
+            payload.extend(rvl_encode(&samples));
+            payload
+        }
+    };
+
+    let format = CompressedDepthFormat {
+        encoding,
+        compression: config.compression,
+    };
+    let mut data = header.to_bytes().to_vec();
+    data.extend(payload);
+
+    Ok(CompressedImage {
+        header: image.header.clone(),
+        format: format.to_string(),
+        data,
+    })
+}
+
+/// Returns the channel type of a depth encoding, which is either 16UC1
+/// or 32FC1.
+fn depth_type(encoding: ImageEncoding) -> Result<ChannelType> {
+    match (encoding.channel_type(), encoding.channels()) {
+        (ty @ (ChannelType::U16 | ChannelType::F32), 1) if !encoding.is_bayer() => Ok(ty),
+        _ => bail!("compressedDepth only supports 16UC1 and 32FC1 images, but get '{encoding}'"),
+    }
+}
+
+#[cfg(any(feature = "with-image", feature = "with-opencv"))]
+fn decode_png(data: &[u8]) -> Result<(u32, u32, Vec<u16>)> {
+    use super::{CompressedImageExt, ImageEncoding as E};
+
+    let compressed = CompressedImage {
+        format: "png".to_string(),
+        data: data.to_vec(),
+        ..Default::default()
+    };
+    let image = compressed.decode()?;
+    let encoding = check_image(&image)?;
+    ensure!(
+        matches!(encoding, E::Mono16),
+        "Expect a 16-bit grayscale PNG, but get '{encoding}'"
+    );
+
+    let Image {
+        height,
+        width,
+        step: row_step,
+        ref data,
+        is_bigendian,
+        ..
+    } = image;
+    let samples = data
+        .chunks(row_step as usize)
+        .take(height as usize)
+        .flat_map(|row| row[0..width as usize * 2].chunks_exact(2))
+        .map(|bytes| {
+            let bytes = [bytes[0], bytes[1]];
+            if is_bigendian != 0 {
+                u16::from_be_bytes(bytes)
+            } else {
+                u16::from_le_bytes(bytes)
+            }
+        })
+        .collect();
+
+    Ok((width, height, samples))
+}
+
+#[cfg(not(any(feature = "with-image", feature = "with-opencv")))]
+fn decode_png(_data: &[u8]) -> Result<(u32, u32, Vec<u16>)> {
+    bail!("PNG compressedDepth images require the with-image or with-opencv feature")
+}
+
+#[cfg(any(feature = "with-image", feature = "with-opencv"))]
+fn encode_png(width: u32, height: u32, samples: Vec<u16>, level: u8) -> Result<Vec<u8>> {
+    use super::{CompressedImageExt, CompressionFormat};
+
+    let image = Image {
+        height,
+        width,
+        encoding: ImageEncoding::Mono16.to_string(),
+        is_bigendian: cfg!(target_endian = "big") as u8,
+        step: width * 2,
+        data: samples.into_iter().flat_map(u16::to_ne_bytes).collect(),
+        ..Default::default()
+    };
+    let compressed = CompressedImage::encode(&image, CompressionFormat::Png, level)?;
+    Ok(compressed.data)
+}
+
+#[cfg(not(any(feature = "with-image", feature = "with-opencv")))]
+fn encode_png(_width: u32, _height: u32, _samples: Vec<u16>, _level: u8) -> Result<Vec<u8>> {
+    bail!("PNG compressedDepth images require the with-image or with-opencv feature")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn depth_image(encoding: &str, width: u32, height: u32, data: Vec<u8>) -> Image {
+        Image {
+            height,
+            width,
+            encoding: encoding.to_string(),
+            is_bigendian: cfg!(target_endian = "big") as u8,
+            step: data.len() as u32 / height,
+            data,
+            ..Default::default()
+        }
+    }
+
+    fn rvl_config() -> CompressedDepthConfig {
+        CompressedDepthConfig {
+            compression: DepthCompression::Rvl,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn header_round_trip() {
+        let header = CompressedDepthHeader {
+            format: CompressedDepthHeader::INV_DEPTH,
+            depth_param: [10100.0, -1009.0],
+        };
+        let bytes = header.to_bytes();
+
+        assert_eq!(bytes[0..4], [0, 0, 0, 0]);
+        assert_eq!(bytes[4..8], 10100f32.to_le_bytes());
+        assert_eq!(bytes[8..12], (-1009f32).to_le_bytes());
+        assert_eq!(CompressedDepthHeader::from_bytes(&bytes).unwrap(), header);
+        assert!(CompressedDepthHeader::from_bytes(&bytes[0..8]).is_err());
+    }
+
+    #[test]
+    fn format_round_trip() {
+        for text in ["16UC1; compressedDepth png", "32FC1; compressedDepth rvl"] {
+            let format: CompressedDepthFormat = text.parse().unwrap();
+            assert_eq!(format.to_string(), text);
+        }
+
+        let format: CompressedDepthFormat = "16UC1; compressedDepth".parse().unwrap();
+        assert_eq!(format.compression, DepthCompression::Png);
+    }
+
+    #[test]
+    fn u16_rvl_round_trip() {
+        let depths: [u16; 6] = [0, 500, 501, 0, 65535, 1200];
+        let data = depths
+            .iter()
+            .flat_map(|depth| depth.to_ne_bytes())
+            .collect();
+        let image = depth_image("16UC1", 3, 2, data);
+
+        let compressed = encode_compressed_depth(&image, &rvl_config()).unwrap();
+        assert_eq!(compressed.format, "16UC1; compressedDepth rvl");
+        assert_eq!(compressed.data[12..16], 3u32.to_le_bytes());
+        assert_eq!(compressed.data[16..20], 2u32.to_le_bytes());
+
+        let decoded = decode_compressed_depth(&compressed).unwrap();
+        assert_eq!(decoded.data, image.data);
+        assert_eq!(decoded.step, 6);
+    }
+
+    #[test]
+    fn f32_quantization_round_trip() {
+        let depths: [f32; 6] = [0.5, 1.0, 3.0, 9.5, 0.0, 12.0];
+        let data = depths
+            .iter()
+            .flat_map(|depth| depth.to_ne_bytes())
+            .collect();
+        let image = depth_image("32FC1", 3, 2, data);
+        let config = rvl_config();
+
+        let compressed = encode_compressed_depth(&image, &config).unwrap();
+        let header = CompressedDepthHeader::from_bytes(&compressed.data).unwrap();
+        let quant_a = config.depth_quantization * (config.depth_quantization + 1.0);
+        assert_eq!(
+            header.depth_param,
+            [quant_a, 1.0 - quant_a / config.depth_max]
+        );
+
+        let decoded = decode_compressed_depth(&compressed).unwrap();
+        let decoded: Vec<f32> = decoded
+            .data
+            .chunks_exact(4)
+            .map(|bytes| f32::from_ne_bytes(bytes.try_into().unwrap()))
+            .collect();
+
+        for (&depth, &value) in depths.iter().zip(&decoded) {
+            if depth > 0.0 && depth < config.depth_max {
+                // The quantization step is depth^2 / quant_a.
+                let tolerance = depth * depth / quant_a + 1e-6;
+                assert!((value - depth).abs() <= tolerance, "{depth} -> {value}");
+            } else {
+                assert!(value.is_nan(), "{depth} -> {value}");
+            }
+        }
+    }
+
+    #[test]
+    fn reject_oversized_rvl_image() {
+        let mut data = CompressedDepthHeader {
+            format: CompressedDepthHeader::INV_DEPTH,
+            depth_param: [0.0, 0.0],
+        }
+        .to_bytes()
+        .to_vec();
+        data.extend(u32::MAX.to_le_bytes());
+        data.extend(u32::MAX.to_le_bytes());
+        data.extend(rvl_encode(&[1, 2, 3]));
+
+        let compressed = CompressedImage {
+            format: "16UC1; compressedDepth rvl".to_string(),
+            data,
+            ..Default::default()
+        };
+        assert!(decode_compressed_depth(&compressed).is_err());
+    }
+}
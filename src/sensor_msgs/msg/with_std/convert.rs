@@ -0,0 +1,347 @@
+use super::{
+    check_image, image_demosaic, image_yuv_to_rgb8, DemosaicMethod, ImageEncoding, RgbOrder,
+};
+use anyhow::{bail, Result};
+use r2r::sensor_msgs::msg::Image;
+
+/// Converts an image to another encoding, the same way as cv_bridge
+/// does.
+///
+/// The target must be a mono, RGB or BGR encoding, with or without
+/// alpha, in 8 or 16 bits. The source may additionally be a YUV or
+/// a Bayer image, which is decoded to RGB first. Images already in
+/// the target encoding are returned as is.
+///
+/// Bayer images are demosaiced bilinearly. Use [ImageExt::demosaic]
+/// for other methods.
+pub fn image_convert_encoding(image: &Image, target: &str) -> Result<Image> {
+    let src_encoding = check_image(image)?;
+    let dst_encoding: ImageEncoding = target.parse()?;
+
+    if src_encoding == dst_encoding {
+        return Ok(image.clone());
+    }
+
+    let Some(dst_format) = ColorFormat::from_encoding(dst_encoding) else {
+        bail!("unsupported target image format '{target}'");
+    };
+
+    if src_encoding.yuv_format().is_some() {
+        let rgb = image_yuv_to_rgb8(image, RgbOrder::Rgb)?;
+        return convert_color(&rgb, dst_format, dst_encoding);
+    }
+
+    if src_encoding.is_bayer() {
+        let rgb = image_demosaic(image, DemosaicMethod::default(), RgbOrder::Rgb)?;
+        return convert_color(&rgb, dst_format, dst_encoding);
+    }
+
+    convert_color(image, dst_format, dst_encoding)
+}
+
+fn convert_color(
+    image: &Image,
+    dst_format: ColorFormat,
+    dst_encoding: ImageEncoding,
+) -> Result<Image> {
+    let Image {
+        ref header,
+        height,
+        width,
+        is_bigendian,
+        step: row_step,
+        ref data,
+        ..
+    } = *image;
+
+    let src_encoding = check_image(image)?;
+    let Some(src_format) = ColorFormat::from_encoding(src_encoding) else {
+        bail!("cannot convert image format '{src_encoding}' to '{dst_encoding}'");
+    };
+    let width = width as usize;
+    let height = height as usize;
+    let row_step = row_step as usize;
+    let src_pixel_step = src_format.pixel_step();
+    let dst_pixel_step = dst_format.pixel_step();
+
+    let is_bigendian = is_bigendian != 0;
+    let dst_step = width * dst_pixel_step;
+    let mut out = vec![0u8; dst_step * height];
+
+    if width > 0 {
+        out.chunks_exact_mut(dst_step)
+            .zip(data.chunks(row_step))
+            .for_each(|(dst_row, src_row)| {
+                let src_pixels = src_row[0..width * src_pixel_step].chunks_exact(src_pixel_step);
+                let dst_pixels = dst_row.chunks_exact_mut(dst_pixel_step);
+
+                src_pixels.zip(dst_pixels).for_each(|(src, dst)| {
+                    let rgba = src_format.read_rgba(src, is_bigendian);
+                    let rgba = rgba.map(|val| scale_depth(val, src_format.bits, dst_format.bits));
+                    dst_format.write_rgba(rgba, dst);
+                });
+            });
+    }
+
+    Ok(Image {
+        header: header.clone(),
+        height: height as u32,
+        width: width as u32,
+        encoding: dst_encoding.to_string(),
+        is_bigendian: cfg!(target_endian = "big") as u8,
+        step: dst_step as u32,
+        data: out,
+    })
+}
+
+/// Rescales a sample to another bit depth.
+///
+/// 16-bit samples are divided by 256 and rounded half to even, the
+/// same as OpenCV's `convertTo()` with a scale of 1/256, so that the
+/// OpenCV compression path gives the same 8-bit values.
+fn scale_depth(val: u32, src_bits: u32, dst_bits: u32) -> u32 {
+    match (src_bits, dst_bits) {
+        (8, 16) => val * 257,
+        (16, 8) => {
+            let (quot, rem) = (val >> 8, val & 0xff);
+            let round_up = rem > 128 || (rem == 128 && quot & 1 == 1);
+            (quot + round_up as u32).min(255)
+        }
+        _ => val,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ChannelLayout {
+    Mono,
+    Rgb,
+    Bgr,
+    Rgba,
+    Bgra,
+}
+
+impl ChannelLayout {
+    fn channels(&self) -> usize {
+        match self {
+            Self::Mono => 1,
+            Self::Rgb | Self::Bgr => 3,
+            Self::Rgba | Self::Bgra => 4,
+        }
+    }
+}
+
+/// The channel layout and bit depth of a color or mono encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ColorFormat {
+    layout: ChannelLayout,
+    bits: u32,
+}
+
+impl ColorFormat {
+    fn from_encoding(encoding: ImageEncoding) -> Option<Self> {
+        use ChannelLayout as L;
+        use ImageEncoding as E;
+
+        let layout = match encoding {
+            E::Mono8 | E::Mono16 => L::Mono,
+            E::Rgb8 | E::Rgb16 => L::Rgb,
+            E::Bgr8 | E::Bgr16 => L::Bgr,
+            E::Rgba8 | E::Rgba16 => L::Rgba,
+            E::Bgra8 | E::Bgra16 => L::Bgra,
+            _ => return None,
+        };
+        Some(Self {
+            layout,
+            bits: encoding.bit_depth(),
+        })
+    }
+
+    fn elem_size(&self) -> usize {
+        self.bits as usize / 8
+    }
+
+    fn pixel_step(&self) -> usize {
+        self.layout.channels() * self.elem_size()
+    }
+
+    fn max_value(&self) -> u32 {
+        (1 << self.bits) - 1
+    }
+
+    /// Reads a pixel as RGBA samples. The alpha is set to the maximum
+    /// value if the layout has no alpha channel.
+    fn read_rgba(&self, bytes: &[u8], is_bigendian: bool) -> [u32; 4] {
+        let elem_size = self.elem_size();
+        let sample = |idx: usize| -> u32 {
+            let bytes = &bytes[idx * elem_size..(idx + 1) * elem_size];
+            match *bytes {
+                [val] => val as u32,
+                [b0, b1] if is_bigendian => u16::from_be_bytes([b0, b1]) as u32,
+                [b0, b1] => u16::from_le_bytes([b0, b1]) as u32,
+                _ => unreachable!(),
+            }
+        };
+        let max = self.max_value();
+
+        match self.layout {
+            ChannelLayout::Mono => {
+                let val = sample(0);
+                [val, val, val, max]
+            }
+            ChannelLayout::Rgb => [sample(0), sample(1), sample(2), max],
+            ChannelLayout::Bgr => [sample(2), sample(1), sample(0), max],
+            ChannelLayout::Rgba => [sample(0), sample(1), sample(2), sample(3)],
+            ChannelLayout::Bgra => [sample(2), sample(1), sample(0), sample(3)],
+        }
+    }
+
+    /// Writes RGBA samples as a pixel in the native byte order.
+    fn write_rgba(&self, [r, g, b, a]: [u32; 4], bytes: &mut [u8]) {
+        let elem_size = self.elem_size();
+        let mut put = |idx: usize, val: u32| {
+            let bytes = &mut bytes[idx * elem_size..(idx + 1) * elem_size];
+            if elem_size == 1 {
+                bytes[0] = val as u8;
+            } else {
+                bytes.copy_from_slice(&(val as u16).to_ne_bytes());
+            }
+        };
+
+        match self.layout {
+            ChannelLayout::Mono => {
+                // BT.601 luma in 14-bit fixed-point, the same weights
+                // as OpenCV's RGB to gray conversion.
+                let gray = (r * 4899 + g * 9617 + b * 1868 + 8192) >> 14;
+                put(0, gray);
+            }
+            ChannelLayout::Rgb => {
+                put(0, r);
+                put(1, g);
+                put(2, b);
+            }
+            ChannelLayout::Bgr => {
+                put(0, b);
+                put(1, g);
+                put(2, r);
+            }
+            ChannelLayout::Rgba => {
+                put(0, r);
+                put(1, g);
+                put(2, b);
+                put(3, a);
+            }
+            ChannelLayout::Bgra => {
+                put(0, b);
+                put(1, g);
+                put(2, r);
+                put(3, a);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates a 2x1 image.
+    fn image(encoding: &str, data: Vec<u8>, is_bigendian: bool) -> Image {
+        Image {
+            height: 1,
+            width: 2,
+            encoding: encoding.to_string(),
+            is_bigendian: is_bigendian as u8,
+            step: data.len() as u32,
+            data,
+            ..Default::default()
+        }
+    }
+
+    fn u16_bytes(samples: &[u16], is_bigendian: bool) -> Vec<u8> {
+        samples
+            .iter()
+            .flat_map(|val| {
+                if is_bigendian {
+                    val.to_be_bytes()
+                } else {
+                    val.to_le_bytes()
+                }
+            })
+            .collect()
+    }
+
+    fn convert(src: &Image, target: &str) -> Vec<u8> {
+        let dst = image_convert_encoding(src, target).unwrap();
+        assert_eq!(dst.encoding, target);
+        assert_eq!((dst.width, dst.height), (2, 1));
+        assert_eq!(dst.step as usize, dst.data.len());
+        dst.data
+    }
+
+    #[test]
+    fn convert_channel_order() {
+        let rgb8 = image("rgb8", vec![10, 20, 30, 40, 50, 60], false);
+        assert_eq!(convert(&rgb8, "bgr8"), [30, 20, 10, 60, 50, 40]);
+        assert_eq!(convert(&rgb8, "rgba8"), [10, 20, 30, 255, 40, 50, 60, 255]);
+        assert_eq!(convert(&rgb8, "bgra8"), [30, 20, 10, 255, 60, 50, 40, 255]);
+
+        let bgra8 = image("bgra8", vec![30, 20, 10, 7, 60, 50, 40, 8], false);
+        assert_eq!(convert(&bgra8, "rgb8"), [10, 20, 30, 40, 50, 60]);
+        assert_eq!(convert(&bgra8, "rgba8"), [10, 20, 30, 7, 40, 50, 60, 8]);
+    }
+
+    #[test]
+    fn convert_mono() {
+        let rgb8 = image("rgb8", vec![255, 255, 255, 255, 0, 0], false);
+        assert_eq!(convert(&rgb8, "mono8"), [255, 76]);
+
+        let mono8 = image("mono8", vec![0, 200], false);
+        assert_eq!(convert(&mono8, "bgr8"), [0, 0, 0, 200, 200, 200]);
+        assert_eq!(convert(&mono8, "rgba8"), [0, 0, 0, 255, 200, 200, 200, 255]);
+    }
+
+    #[test]
+    fn convert_bit_depth() {
+        let mono8 = image("mono8", vec![1, 255], false);
+        assert_eq!(
+            convert(&mono8, "mono16"),
+            u16_bytes(&[257, 65535], cfg!(target_endian = "big"))
+        );
+
+        let rgb8 = image("rgb8", vec![0, 1, 2, 3, 4, 255], false);
+        assert_eq!(
+            convert(&rgb8, "bgra16"),
+            u16_bytes(
+                &[514, 257, 0, 65535, 65535, 1028, 771, 65535],
+                cfg!(target_endian = "big")
+            )
+        );
+
+        // Divided by 256 and rounded half to even, then saturated.
+        for is_bigendian in [false, true] {
+            let rgb16 = image(
+                "rgb16",
+                u16_bytes(&[127, 128, 384, 385, 65407, 65535], is_bigendian),
+                is_bigendian,
+            );
+            assert_eq!(convert(&rgb16, "rgb8"), [0, 0, 2, 2, 255, 255]);
+            assert_eq!(convert(&rgb16, "bgr8"), [2, 0, 0, 255, 255, 2]);
+        }
+    }
+
+    #[test]
+    fn convert_same_encoding() {
+        let rgb8 = image("rgb8", vec![10, 20, 30, 40, 50, 60], false);
+        assert_eq!(image_convert_encoding(&rgb8, "rgb8").unwrap(), rgb8);
+    }
+
+    #[test]
+    fn convert_unsupported_encoding() {
+        let rgb8 = image("rgb8", vec![10, 20, 30, 40, 50, 60], false);
+        assert!(image_convert_encoding(&rgb8, "32FC1").is_err());
+        assert!(image_convert_encoding(&rgb8, "yuv422").is_err());
+
+        let depth = image("32FC1", vec![0; 8], false);
+        assert!(image_convert_encoding(&depth, "mono8").is_err());
+    }
+}
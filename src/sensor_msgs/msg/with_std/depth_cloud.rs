@@ -0,0 +1,335 @@
+use super::{
+    check_image, ChannelType, ImageEncoding, ImageExt, PinholeCamera, PointFieldLayout,
+    PointFieldType,
+};
+use anyhow::{bail, ensure, Result};
+use r2r::sensor_msgs::msg::{CameraInfo, Image, PointCloud2};
+use std::borrow::Cow;
+
+pub trait DepthImageExt {
+    /// Converts a depth image to an organized point cloud. See
+    /// [depth_image_to_pointcloud2].
+    fn to_pointcloud2(&self, camera_info: &CameraInfo, rgb: Option<&Image>) -> Result<PointCloud2>;
+}
+
+impl DepthImageExt for Image {
+    fn to_pointcloud2(&self, camera_info: &CameraInfo, rgb: Option<&Image>) -> Result<PointCloud2> {
+        depth_image_to_pointcloud2(self, camera_info, rgb)
+    }
+}
+
+/// Converts a rectified depth image to an organized point cloud with
+/// the same size, the same way as depth_image_proc.
+///
+/// The depth is in millimeters for 16UC1 and mono16 images, and in
+/// meters for 32FC1 images. Pixels with zero or non-finite depth
+/// become points with NaN coordinates and clear `is_dense`.
+///
+/// The point cloud has the float32 fields "x", "y" and "z" in the
+/// camera optical frame. If a registered color image is given, a
+/// packed "rgb" field is appended. The color image is converted to
+/// rgb8 if it has another encoding.
+pub fn depth_image_to_pointcloud2(
+    depth: &Image,
+    camera_info: &CameraInfo,
+    rgb: Option<&Image>,
+) -> Result<PointCloud2> {
+    use ImageEncoding as E;
+
+    let camera = PinholeCamera::from_camera_info(camera_info)?;
+    let Image {
+        height,
+        width,
+        step: row_step,
+        ref data,
+        is_bigendian,
+        ..
+    } = *depth;
+    ensure!(
+        width == camera.width && height == camera.height,
+        "The camera info is for {}x{} images, but the depth image is {width}x{height}",
+        camera.width,
+        camera.height
+    );
+
+    let encoding = check_image(depth)?;
+    let (channel_type, scale) = match encoding {
+        E::Mono16
+        | E::Generic {
+            channel_type: ChannelType::U16,
+            channels: 1,
+        } => (ChannelType::U16, 0.001),
+        E::Generic {
+            channel_type: ChannelType::F32,
+            channels: 1,
+        } => (ChannelType::F32, 1.0),
+        _ => bail!("Expect a 16UC1 or 32FC1 depth image, but get '{encoding}'"),
+    };
+
+    let rgb = match rgb {
+        Some(rgb) => {
+            ensure!(
+                rgb.width == width && rgb.height == height,
+                "The color image is {}x{}, but the depth image is {width}x{height}",
+                rgb.width,
+                rgb.height
+            );
+            let rgb = if check_image(rgb)? == E::Rgb8 {
+                Cow::Borrowed(rgb)
+            } else {
+                Cow::Owned(rgb.convert_encoding("rgb8")?)
+            };
+            Some(rgb)
+        }
+        None => None,
+    };
+
+    let mut layout = PointFieldLayout::new();
+    layout.push("x", PointFieldType::F32, 1);
+    layout.push("y", PointFieldType::F32, 1);
+    layout.push("z", PointFieldType::F32, 1);
+    let rgb_offset = rgb
+        .as_ref()
+        .map(|_| layout.push("rgb", PointFieldType::F32, 1) as usize);
+    let point_step = layout.point_step() as usize;
+
+    let width = width as usize;
+    let height = height as usize;
+    let is_bigendian = is_bigendian != 0;
+    let elem_size = channel_type.size();
+    let [fx, _, cx, _, _, fy, cy, ..] = camera.p;
+
+    let depths = data
+        .chunks(row_step as usize)
+        .take(height)
+        .flat_map(|row| row[0..width * elem_size].chunks_exact(elem_size))
+        .map(|bytes| {
+            let value = match (channel_type, is_bigendian) {
+                (ChannelType::U16, true) => u16::from_be_bytes(bytes.try_into().unwrap()) as f64,
+                (ChannelType::U16, false) => u16::from_le_bytes(bytes.try_into().unwrap()) as f64,
+                (_, true) => f32::from_be_bytes(bytes.try_into().unwrap()) as f64,
+                (_, false) => f32::from_le_bytes(bytes.try_into().unwrap()) as f64,
+            };
+            value * scale
+        });
+
+    let mut points = vec![0u8; point_step * width * height];
+    let mut is_dense = true;
+
+    points
+        .chunks_exact_mut(point_step)
+        .zip(depths)
+        .enumerate()
+        .for_each(|(idx, (point, z))| {
+            let u = (idx % width) as f64;
+            let v = (idx / width) as f64;
+
+            let xyz = if z.is_finite() && z > 0.0 {
+                [(u - cx) * z / fx, (v - cy) * z / fy, z]
+            } else {
+                is_dense = false;
+                [f64::NAN; 3]
+            };
+
+            point[0..12]
+                .chunks_exact_mut(4)
+                .zip(xyz)
+                .for_each(|(bytes, value)| bytes.copy_from_slice(&(value as f32).to_ne_bytes()));
+        });
+
+    if let (Some(rgb), Some(offset)) = (&rgb, rgb_offset) {
+        let colors = rgb
+            .data
+            .chunks(rgb.step as usize)
+            .take(height)
+            .flat_map(|row| row[0..width * 3].chunks_exact(3));
+
+        points
+            .chunks_exact_mut(point_step)
+            .zip(colors)
+            .for_each(|(point, color)| {
+                let [r, g, b] = [color[0], color[1], color[2]].map(u32::from);
+                let packed = f32::from_bits((r << 16) | (g << 8) | b);
+                point[offset..offset + 4].copy_from_slice(&packed.to_ne_bytes());
+            });
+    }
+
+    Ok(PointCloud2 {
+        header: depth.header.clone(),
+        height: height as u32,
+        width: width as u32,
+        fields: layout.into_fields(),
+        is_bigendian: cfg!(target_endian = "big"),
+        point_step: point_step as u32,
+        row_step: (point_step * width) as u32,
+        data: points,
+        is_dense,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: u32 = 3;
+    const HEIGHT: u32 = 2;
+
+    fn camera_info() -> CameraInfo {
+        CameraInfo {
+            width: WIDTH,
+            height: HEIGHT,
+            k: vec![2.0, 0.0, 1.0, 0.0, 4.0, 0.5, 0.0, 0.0, 1.0],
+            r: vec![1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0],
+            p: vec![2.0, 0.0, 1.0, 0.0, 0.0, 4.0, 0.5, 0.0, 0.0, 0.0, 1.0, 0.0],
+            ..Default::default()
+        }
+    }
+
+    fn image(encoding: &str, is_bigendian: bool, pixel_size: u32, data: Vec<u8>) -> Image {
+        Image {
+            height: HEIGHT,
+            width: WIDTH,
+            encoding: encoding.to_string(),
+            is_bigendian: is_bigendian as u8,
+            step: WIDTH * pixel_size,
+            data,
+            ..Default::default()
+        }
+    }
+
+    /// Reads the float32 values of a field in each point.
+    fn read_field(cloud: &PointCloud2, name: &str) -> Vec<f32> {
+        let offset = cloud
+            .fields
+            .iter()
+            .find(|field| field.name == name)
+            .unwrap()
+            .offset as usize;
+        cloud
+            .data
+            .chunks_exact(cloud.point_step as usize)
+            .map(|point| f32::from_ne_bytes(point[offset..offset + 4].try_into().unwrap()))
+            .collect()
+    }
+
+    /// Checks the points against the depths in meters. Invalid depths
+    /// must give NaN points.
+    fn assert_points(cloud: &PointCloud2, depths: &[f32]) {
+        assert_eq!((cloud.width, cloud.height), (WIDTH, HEIGHT));
+        assert_eq!(cloud.row_step, cloud.point_step * WIDTH);
+
+        let [xs, ys, zs] = ["x", "y", "z"].map(|name| read_field(cloud, name));
+        for (idx, &depth) in depths.iter().enumerate() {
+            let point = [xs[idx], ys[idx], zs[idx]];
+
+            if depth.is_finite() && depth > 0.0 {
+                let u = (idx % WIDTH as usize) as f32;
+                let v = (idx / WIDTH as usize) as f32;
+                let expect = [(u - 1.0) * depth / 2.0, (v - 0.5) * depth / 4.0, depth];
+                assert_eq!(point, expect, "point {idx}");
+            } else {
+                assert!(point.iter().all(|value| value.is_nan()), "point {idx}");
+            }
+        }
+    }
+
+    #[test]
+    fn depth_16uc1() {
+        let millimeters: [u16; 6] = [1000, 0, 2000, 500, 1500, 3000];
+        let depths = millimeters.map(|depth| depth as f32 / 1000.0);
+
+        for (encoding, is_bigendian) in [("16UC1", false), ("16UC1", true), ("mono16", false)] {
+            let data = millimeters
+                .iter()
+                .flat_map(|depth| {
+                    if is_bigendian {
+                        depth.to_be_bytes()
+                    } else {
+                        depth.to_le_bytes()
+                    }
+                })
+                .collect();
+            let depth = image(encoding, is_bigendian, 2, data);
+
+            let cloud = depth_image_to_pointcloud2(&depth, &camera_info(), None).unwrap();
+            let names: Vec<_> = cloud.fields.iter().map(|field| &field.name).collect();
+            assert_eq!(names, ["x", "y", "z"]);
+            assert_eq!(cloud.point_step, 12);
+            assert_points(&cloud, &depths);
+            assert!(!cloud.is_dense);
+        }
+    }
+
+    #[test]
+    fn depth_32fc1() {
+        let to_image = |depths: &[f32]| {
+            let data = depths
+                .iter()
+                .flat_map(|depth| depth.to_le_bytes())
+                .collect();
+            image("32FC1", false, 4, data)
+        };
+
+        let depths = [0.5, 1.0, 1.5, 2.0, 2.5, 3.0];
+        let cloud = to_image(&depths)
+            .to_pointcloud2(&camera_info(), None)
+            .unwrap();
+        assert_points(&cloud, &depths);
+        assert!(cloud.is_dense);
+
+        let depths = [0.5, f32::NAN, 0.0, -1.0, f32::INFINITY, 3.0];
+        let cloud = to_image(&depths)
+            .to_pointcloud2(&camera_info(), None)
+            .unwrap();
+        assert_points(&cloud, &depths);
+        assert!(!cloud.is_dense);
+    }
+
+    #[test]
+    fn depth_with_color() {
+        let depth = image("16UC1", false, 2, [1000u16.to_le_bytes(); 6].concat());
+        let colors: Vec<[u8; 3]> = (0..6).map(|idx| [idx, idx * 10, idx * 20]).collect();
+        let packed: Vec<_> = colors
+            .iter()
+            .map(|&[r, g, b]| f32::from_bits(u32::from_be_bytes([0, r, g, b])))
+            .collect();
+
+        let rgb = image("rgb8", false, 3, colors.concat());
+        let cloud = depth_image_to_pointcloud2(&depth, &camera_info(), Some(&rgb)).unwrap();
+        assert_eq!(cloud.point_step, 16);
+        assert_points(&cloud, &[1.0; 6]);
+        let rgbs = read_field(&cloud, "rgb");
+        assert!(rgbs
+            .iter()
+            .zip(&packed)
+            .all(|(lhs, rhs)| lhs.to_bits() == rhs.to_bits()));
+
+        // Other color encodings are converted to rgb8.
+        let bgr_colors: Vec<_> = colors.iter().map(|&[r, g, b]| [b, g, r]).collect();
+        let bgr = image("bgr8", false, 3, bgr_colors.concat());
+        let cloud = depth_image_to_pointcloud2(&depth, &camera_info(), Some(&bgr)).unwrap();
+        let rgbs = read_field(&cloud, "rgb");
+        assert!(rgbs
+            .iter()
+            .zip(&packed)
+            .all(|(lhs, rhs)| lhs.to_bits() == rhs.to_bits()));
+    }
+
+    #[test]
+    fn reject_invalid_images() {
+        let info = camera_info();
+        let depth = image("16UC1", false, 2, vec![0; 12]);
+
+        let mono8 = image("mono8", false, 1, vec![0; 6]);
+        assert!(depth_image_to_pointcloud2(&mono8, &info, None).is_err());
+
+        let mut small_info = camera_info();
+        small_info.width -= 1;
+        assert!(depth_image_to_pointcloud2(&depth, &small_info, None).is_err());
+
+        let mut rgb = image("rgb8", false, 3, vec![0; 18]);
+        rgb.height = 1;
+        rgb.data.truncate(9);
+        assert!(depth_image_to_pointcloud2(&depth, &info, Some(&rgb)).is_err());
+    }
+}
@@ -0,0 +1,457 @@
+use super::{BayerPattern, YuvFormat};
+use anyhow::{anyhow, ensure, Error, Result};
+use r2r::sensor_msgs::msg::Image;
+use std::{fmt, str::FromStr};
+
+/// The type of a channel in generic OpenCV type encodings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChannelType {
+    U8,
+    I8,
+    U16,
+    I16,
+    I32,
+    F32,
+    F64,
+}
+
+impl ChannelType {
+    /// Returns the size of a channel in bytes.
+    pub fn size(&self) -> usize {
+        match self {
+            ChannelType::U8 => 1,
+            ChannelType::I8 => 1,
+            ChannelType::U16 => 2,
+            ChannelType::I16 => 2,
+            ChannelType::I32 => 4,
+            ChannelType::F32 => 4,
+            ChannelType::F64 => 8,
+        }
+    }
+
+    /// Returns the type prefix in generic encodings, such as "32F".
+    fn prefix(&self) -> &'static str {
+        match self {
+            ChannelType::U8 => "8U",
+            ChannelType::I8 => "8S",
+            ChannelType::U16 => "16U",
+            ChannelType::I16 => "16S",
+            ChannelType::I32 => "32S",
+            ChannelType::F32 => "32F",
+            ChannelType::F64 => "64F",
+        }
+    }
+
+    fn from_prefix(text: &str) -> Option<Self> {
+        let ty = match text {
+            "8U" => ChannelType::U8,
+            "8S" => ChannelType::I8,
+            "16U" => ChannelType::U16,
+            "16S" => ChannelType::I16,
+            "32S" => ChannelType::I32,
+            "32F" => ChannelType::F32,
+            "64F" => ChannelType::F64,
+            _ => return None,
+        };
+        Some(ty)
+    }
+}
+
+/// The maximum number of channels of generic encodings, which is
+/// `CV_CN_MAX` of OpenCV.
+pub const MAX_CHANNELS: u32 = 512;
+
+/// The pixel encodings of `sensor_msgs/Image` defined in ROS
+/// `sensor_msgs/image_encodings.hpp`.
+///
+/// It is displayed as the canonical encoding strings, so that parsing
+/// and displaying a canonical string gives back the same string. The
+/// legacy upper-case names "RGB8", "BGR8" and "UYVY" are also parsed,
+/// and are displayed as "rgb8", "bgr8" and "yuv422". Besides the ROS
+/// encodings, "nv12" and "i420" are accepted for cameras publishing
+/// these YUV 4:2:0 layouts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ImageEncoding {
+    Rgb8,
+    Rgba8,
+    Rgb16,
+    Rgba16,
+    Bgr8,
+    Bgra8,
+    Bgr16,
+    Bgra16,
+    Mono8,
+    Mono16,
+    /// Generic OpenCV type encodings, such as "8UC3" and "32FC1", with
+    /// 1 to [MAX_CHANNELS] channels.
+    Generic {
+        channel_type: ChannelType,
+        channels: u32,
+    },
+    BayerRggb8,
+    BayerBggr8,
+    BayerGbrg8,
+    BayerGrbg8,
+    BayerRggb16,
+    BayerBggr16,
+    BayerGbrg16,
+    BayerGrbg16,
+    /// Packed YUV 4:2:2 in UYVY order, named "yuv422" in ROS 1.
+    Yuv422,
+    /// Packed YUV 4:2:2 in YUYV order, named "yuv422_yuy2" in ROS 1.
+    Yuv422Yuy2,
+    /// Packed YUV 4:2:2 in UYVY order, named "uyvy" in ROS 2.
+    Uyvy,
+    /// Packed YUV 4:2:2 in YUYV order, named "yuyv" in ROS 2.
+    Yuyv,
+    Nv12,
+    Nv21,
+    Nv24,
+    I420,
+}
+
+impl ImageEncoding {
+    /// Returns the number of interleaved channels in a pixel. Planar
+    /// YUV encodings have a single channel in each plane.
+    pub fn channels(&self) -> u32 {
+        use ImageEncoding as E;
+
+        match *self {
+            E::Mono8 | E::Mono16 => 1,
+            E::Rgb8 | E::Rgb16 | E::Bgr8 | E::Bgr16 => 3,
+            E::Rgba8 | E::Rgba16 | E::Bgra8 | E::Bgra16 => 4,
+            E::Generic { channels, .. } => channels,
+            E::BayerRggb8
+            | E::BayerBggr8
+            | E::BayerGbrg8
+            | E::BayerGrbg8
+            | E::BayerRggb16
+            | E::BayerBggr16
+            | E::BayerGbrg16
+            | E::BayerGrbg16 => 1,
+            E::Yuv422 | E::Yuv422Yuy2 | E::Uyvy | E::Yuyv => 2,
+            E::Nv12 | E::Nv21 | E::Nv24 | E::I420 => 1,
+        }
+    }
+
+    /// Returns the type of a channel.
+    pub fn channel_type(&self) -> ChannelType {
+        use ImageEncoding as E;
+
+        match *self {
+            E::Rgb16
+            | E::Rgba16
+            | E::Bgr16
+            | E::Bgra16
+            | E::Mono16
+            | E::BayerRggb16
+            | E::BayerBggr16
+            | E::BayerGbrg16
+            | E::BayerGrbg16 => ChannelType::U16,
+            E::Generic { channel_type, .. } => channel_type,
+            _ => ChannelType::U8,
+        }
+    }
+
+    /// Returns the number of bits of a channel.
+    pub fn bit_depth(&self) -> u32 {
+        self.channel_type().size() as u32 * 8
+    }
+
+    /// Returns the number of bytes of a pixel. For planar YUV
+    /// encodings, it is the size of a luma sample.
+    pub fn bytes_per_pixel(&self) -> usize {
+        self.channels() as usize * self.channel_type().size()
+    }
+
+    /// Returns the expected `step` of an image with the given width
+    /// without row padding. It fails if the step does not fit in u32.
+    pub fn step(&self, width: u32) -> Result<u32> {
+        let step = (self.bytes_per_pixel() as u32).checked_mul(width);
+        step.ok_or_else(|| anyhow!("The row of a {width} pixels wide '{self}' image is too large"))
+    }
+
+    /// Returns the minimum number of data bytes of an image, including
+    /// the chroma planes of planar YUV encodings.
+    pub fn data_size(&self, step: usize, height: usize) -> usize {
+        match self {
+            ImageEncoding::Nv24 => step * height * 3,
+            _ => match self.yuv_format() {
+                Some(format) => format.data_size(step, height),
+                None => step * height,
+            },
+        }
+    }
+
+    /// Checks whether it is an RGB or BGR encoding, with or without
+    /// alpha.
+    pub fn is_color(&self) -> bool {
+        use ImageEncoding as E;
+
+        matches!(
+            self,
+            E::Rgb8 | E::Rgba8 | E::Rgb16 | E::Rgba16 | E::Bgr8 | E::Bgra8 | E::Bgr16 | E::Bgra16
+        )
+    }
+
+    pub fn is_mono(&self) -> bool {
+        matches!(self, ImageEncoding::Mono8 | ImageEncoding::Mono16)
+    }
+
+    pub fn has_alpha(&self) -> bool {
+        use ImageEncoding as E;
+
+        matches!(self, E::Rgba8 | E::Rgba16 | E::Bgra8 | E::Bgra16)
+    }
+
+    pub fn is_bayer(&self) -> bool {
+        self.bayer_pattern().is_some()
+    }
+
+    pub fn is_yuv(&self) -> bool {
+        use ImageEncoding as E;
+
+        matches!(
+            self,
+            E::Yuv422 | E::Yuv422Yuy2 | E::Uyvy | E::Yuyv | E::Nv12 | E::Nv21 | E::Nv24 | E::I420
+        )
+    }
+
+    /// Returns the color filter arrangement of Bayer encodings.
+    pub fn bayer_pattern(&self) -> Option<BayerPattern> {
+        use ImageEncoding as E;
+
+        let pattern = match self {
+            E::BayerRggb8 | E::BayerRggb16 => BayerPattern::Rggb,
+            E::BayerBggr8 | E::BayerBggr16 => BayerPattern::Bggr,
+            E::BayerGbrg8 | E::BayerGbrg16 => BayerPattern::Gbrg,
+            E::BayerGrbg8 | E::BayerGrbg16 => BayerPattern::Grbg,
+            _ => return None,
+        };
+        Some(pattern)
+    }
+
+    /// Returns the layout of YUV encodings supported by the pure-Rust
+    /// color converter.
+    pub fn yuv_format(&self) -> Option<YuvFormat> {
+        let format = match self {
+            ImageEncoding::Yuv422 | ImageEncoding::Uyvy => YuvFormat::Uyvy,
+            ImageEncoding::Yuv422Yuy2 | ImageEncoding::Yuyv => YuvFormat::Yuyv,
+            ImageEncoding::Nv12 => YuvFormat::Nv12,
+            ImageEncoding::Nv21 => YuvFormat::Nv21,
+            ImageEncoding::I420 => YuvFormat::I420,
+            _ => return None,
+        };
+        Some(format)
+    }
+}
+
+impl FromStr for ImageEncoding {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        use ImageEncoding as E;
+
+        let encoding = match text {
+            "rgb8" | "RGB8" => E::Rgb8,
+            "rgba8" => E::Rgba8,
+            "rgb16" => E::Rgb16,
+            "rgba16" => E::Rgba16,
+            "bgr8" | "BGR8" => E::Bgr8,
+            "bgra8" => E::Bgra8,
+            "bgr16" => E::Bgr16,
+            "bgra16" => E::Bgra16,
+            "mono8" => E::Mono8,
+            "mono16" => E::Mono16,
+            "bayer_rggb8" => E::BayerRggb8,
+            "bayer_bggr8" => E::BayerBggr8,
+            "bayer_gbrg8" => E::BayerGbrg8,
+            "bayer_grbg8" => E::BayerGrbg8,
+            "bayer_rggb16" => E::BayerRggb16,
+            "bayer_bggr16" => E::BayerBggr16,
+            "bayer_gbrg16" => E::BayerGbrg16,
+            "bayer_grbg16" => E::BayerGrbg16,
+            "yuv422" | "UYVY" => E::Yuv422,
+            "yuv422_yuy2" => E::Yuv422Yuy2,
+            "uyvy" => E::Uyvy,
+            "yuyv" => E::Yuyv,
+            "nv12" => E::Nv12,
+            "nv21" => E::Nv21,
+            "nv24" => E::Nv24,
+            "i420" => E::I420,
+            _ => {
+                let generic = text.split_once('C').and_then(|(ty, channels)| {
+                    let channel_type = ChannelType::from_prefix(ty)?;
+                    let channels: u32 = channels.parse().ok()?;
+                    (1..=MAX_CHANNELS)
+                        .contains(&channels)
+                        .then_some(E::Generic {
+                            channel_type,
+                            channels,
+                        })
+                });
+                generic.ok_or_else(|| anyhow!("unsupported image format '{text}'"))?
+            }
+        };
+
+        Ok(encoding)
+    }
+}
+
+impl fmt::Display for ImageEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use ImageEncoding as E;
+
+        let text = match *self {
+            E::Rgb8 => "rgb8",
+            E::Rgba8 => "rgba8",
+            E::Rgb16 => "rgb16",
+            E::Rgba16 => "rgba16",
+            E::Bgr8 => "bgr8",
+            E::Bgra8 => "bgra8",
+            E::Bgr16 => "bgr16",
+            E::Bgra16 => "bgra16",
+            E::Mono8 => "mono8",
+            E::Mono16 => "mono16",
+            E::Generic {
+                channel_type,
+                channels,
+            } => {
+                return write!(f, "{}C{channels}", channel_type.prefix());
+            }
+            E::BayerRggb8 => "bayer_rggb8",
+            E::BayerBggr8 => "bayer_bggr8",
+            E::BayerGbrg8 => "bayer_gbrg8",
+            E::BayerGrbg8 => "bayer_grbg8",
+            E::BayerRggb16 => "bayer_rggb16",
+            E::BayerBggr16 => "bayer_bggr16",
+            E::BayerGbrg16 => "bayer_gbrg16",
+            E::BayerGrbg16 => "bayer_grbg16",
+            E::Yuv422 => "yuv422",
+            E::Yuv422Yuy2 => "yuv422_yuy2",
+            E::Uyvy => "uyvy",
+            E::Yuyv => "yuyv",
+            E::Nv12 => "nv12",
+            E::Nv21 => "nv21",
+            E::Nv24 => "nv24",
+            E::I420 => "i420",
+        };
+        f.write_str(text)
+    }
+}
+
+/// Parses the encoding of an image and checks the step and the data
+/// size against it.
+pub fn check_image(image: &Image) -> Result<ImageEncoding> {
+    let Image {
+        height,
+        width,
+        ref encoding,
+        step,
+        ref data,
+        ..
+    } = *image;
+
+    let encoding: ImageEncoding = encoding.parse()?;
+    let min_step = encoding.step(width)?;
+    let data_size = encoding.data_size(step as usize, height as usize);
+
+    ensure!(
+        step >= min_step,
+        "Invalid step {step} for a {width} pixels wide '{encoding}' image. \
+         Expect at least {min_step} bytes."
+    );
+    ensure!(
+        data.len() >= data_size,
+        "Invalid data size. Expect {data_size} bytes, but get {} bytes.",
+        data.len()
+    );
+
+    Ok(encoding)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_display_round_trip() {
+        let texts = [
+            "rgb8",
+            "rgba8",
+            "rgb16",
+            "rgba16",
+            "bgr8",
+            "bgra8",
+            "bgr16",
+            "bgra16",
+            "mono8",
+            "mono16",
+            "bayer_rggb8",
+            "bayer_bggr8",
+            "bayer_gbrg8",
+            "bayer_grbg8",
+            "bayer_rggb16",
+            "bayer_bggr16",
+            "bayer_gbrg16",
+            "bayer_grbg16",
+            "yuv422",
+            "yuv422_yuy2",
+            "uyvy",
+            "yuyv",
+            "nv12",
+            "nv21",
+            "nv24",
+            "i420",
+            "8UC1",
+            "8SC2",
+            "16UC3",
+            "16SC4",
+            "32SC1",
+            "32FC1",
+            "64FC3",
+        ];
+
+        for text in texts {
+            let encoding: ImageEncoding = text.parse().unwrap();
+            assert_eq!(encoding.to_string(), text);
+        }
+    }
+
+    #[test]
+    fn parse_legacy_aliases() {
+        use ImageEncoding as E;
+
+        for (text, expect) in [("RGB8", E::Rgb8), ("BGR8", E::Bgr8), ("UYVY", E::Yuv422)] {
+            let encoding: ImageEncoding = text.parse().unwrap();
+            assert_eq!(encoding, expect, "{text}");
+            assert_eq!(
+                encoding.to_string().parse::<ImageEncoding>().unwrap(),
+                expect
+            );
+        }
+    }
+
+    #[test]
+    fn reject_unknown_encodings() {
+        for text in [
+            "",
+            "Rgb8",
+            "rgb",
+            "8UC0",
+            "8UC513",
+            "8UC4294967296",
+            "8XC1",
+            "32FC",
+        ] {
+            assert!(text.parse::<ImageEncoding>().is_err(), "{text}");
+        }
+        assert!("8UC512".parse::<ImageEncoding>().is_ok());
+    }
+
+    #[test]
+    fn step_overflow() {
+        assert_eq!(ImageEncoding::Rgb8.step(640).unwrap(), 1920);
+        assert!(ImageEncoding::Rgba16.step(u32::MAX / 4).is_err());
+    }
+}
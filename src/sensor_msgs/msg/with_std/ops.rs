@@ -0,0 +1,729 @@
+use super::{check_image, ChannelType, ImageEncoding};
+use anyhow::{bail, ensure, Result};
+use r2r::sensor_msgs::msg::{CameraInfo, Image, RegionOfInterest};
+
+/// Clockwise image rotations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Rotation {
+    Rotate90,
+    Rotate180,
+    Rotate270,
+}
+
+/// The interpolation methods of [ImageOpsExt::resize].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Interpolation {
+    Nearest,
+    #[default]
+    Bilinear,
+}
+
+/// Geometric image operations that keep the encoding and the byte
+/// order of the image.
+///
+/// Bayer and YUV images are not supported since the operations would
+/// break their color filter or chroma layout.
+pub trait ImageOpsExt
+where
+    Self: Sized,
+{
+    /// Crops the image to the region. A region with zero width and
+    /// height selects the full image like in `CameraInfo`.
+    fn crop(&self, roi: &RegionOfInterest) -> Result<Self>;
+
+    /// Crops the image and returns the camera info of the cropped
+    /// image.
+    fn crop_with_camera_info(
+        &self,
+        roi: &RegionOfInterest,
+        camera_info: &CameraInfo,
+    ) -> Result<(Self, CameraInfo)>;
+
+    fn flip_horizontal(&self) -> Result<Self>;
+    fn flip_vertical(&self) -> Result<Self>;
+    fn rotate(&self, rotation: Rotation) -> Result<Self>;
+    fn resize(&self, width: u32, height: u32, interpolation: Interpolation) -> Result<Self>;
+
+    /// Resizes the image and returns the camera info of the resized
+    /// image.
+    fn resize_with_camera_info(
+        &self,
+        width: u32,
+        height: u32,
+        interpolation: Interpolation,
+        camera_info: &CameraInfo,
+    ) -> Result<(Self, CameraInfo)>;
+}
+
+impl ImageOpsExt for Image {
+    fn crop(&self, roi: &RegionOfInterest) -> Result<Self> {
+        image_crop(self, roi)
+    }
+
+    fn crop_with_camera_info(
+        &self,
+        roi: &RegionOfInterest,
+        camera_info: &CameraInfo,
+    ) -> Result<(Self, CameraInfo)> {
+        let mut camera_info = camera_info_at_full_resolution(camera_info)?;
+        check_camera_info_size(&camera_info, self)?;
+
+        let image = image_crop(self, roi)?;
+        translate_camera_info(&mut camera_info, roi.x_offset as f64, roi.y_offset as f64);
+        camera_info.width = image.width;
+        camera_info.height = image.height;
+        Ok((image, camera_info))
+    }
+
+    fn flip_horizontal(&self) -> Result<Self> {
+        image_flip_horizontal(self)
+    }
+
+    fn flip_vertical(&self) -> Result<Self> {
+        image_flip_vertical(self)
+    }
+
+    fn rotate(&self, rotation: Rotation) -> Result<Self> {
+        image_rotate(self, rotation)
+    }
+
+    fn resize(&self, width: u32, height: u32, interpolation: Interpolation) -> Result<Self> {
+        image_resize(self, width, height, interpolation)
+    }
+
+    fn resize_with_camera_info(
+        &self,
+        width: u32,
+        height: u32,
+        interpolation: Interpolation,
+        camera_info: &CameraInfo,
+    ) -> Result<(Self, CameraInfo)> {
+        let mut camera_info = camera_info_at_full_resolution(camera_info)?;
+        check_camera_info_size(&camera_info, self)?;
+
+        let image = image_resize(self, width, height, interpolation)?;
+        scale_camera_info(
+            &mut camera_info,
+            width as f64 / self.width as f64,
+            height as f64 / self.height as f64,
+        );
+        camera_info.width = width;
+        camera_info.height = height;
+        Ok((image, camera_info))
+    }
+}
+
+/// Crops an image to the region of interest.
+pub fn image_crop(image: &Image, roi: &RegionOfInterest) -> Result<Image> {
+    let pixel_size = pixel_size(image)?;
+    let Image {
+        height,
+        width,
+        step: row_step,
+        ref data,
+        ..
+    } = *image;
+
+    let RegionOfInterest {
+        x_offset,
+        y_offset,
+        width: roi_width,
+        height: roi_height,
+        ..
+    } = *roi;
+    let (roi_width, roi_height) = if roi_width == 0 && roi_height == 0 {
+        (width - x_offset.min(width), height - y_offset.min(height))
+    } else {
+        (roi_width, roi_height)
+    };
+
+    ensure!(
+        x_offset as u64 + roi_width as u64 <= width as u64
+            && y_offset as u64 + roi_height as u64 <= height as u64,
+        "The region {roi_width}x{roi_height}+{x_offset}+{y_offset} is out of the \
+         {width}x{height} image"
+    );
+
+    let begin = x_offset as usize * pixel_size;
+    let end = begin + roi_width as usize * pixel_size;
+    let data: Vec<u8> = data
+        .chunks((row_step as usize).max(1))
+        .skip(y_offset as usize)
+        .take(roi_height as usize)
+        .flat_map(|row| &row[begin..end])
+        .copied()
+        .collect();
+
+    new_image(image, roi_width, roi_height, data)
+}
+
+/// Mirrors an image about the vertical axis.
+pub fn image_flip_horizontal(image: &Image) -> Result<Image> {
+    let pixel_size = pixel_size(image)?;
+    let data: Vec<u8> = rows(image, pixel_size)
+        .flat_map(|row| row.chunks_exact(pixel_size).rev().flatten())
+        .copied()
+        .collect();
+    new_image(image, image.width, image.height, data)
+}
+
+/// Mirrors an image about the horizontal axis.
+pub fn image_flip_vertical(image: &Image) -> Result<Image> {
+    let pixel_size = pixel_size(image)?;
+    let rows: Vec<&[u8]> = rows(image, pixel_size).collect();
+    let data: Vec<u8> = rows.into_iter().rev().flatten().copied().collect();
+    new_image(image, image.width, image.height, data)
+}
+
+/// Rotates an image clockwise.
+pub fn image_rotate(image: &Image, rotation: Rotation) -> Result<Image> {
+    let pixel_size = pixel_size(image)?;
+    let width = image.width as usize;
+    let height = image.height as usize;
+    let row_step = image.step as usize;
+    let src = &image.data;
+    let pixel = |x: usize, y: usize| {
+        let offset = y * row_step + x * pixel_size;
+        &src[offset..offset + pixel_size]
+    };
+
+    let (dst_width, dst_height) = match rotation {
+        Rotation::Rotate180 => (width, height),
+        Rotation::Rotate90 | Rotation::Rotate270 => (height, width),
+    };
+    let mut data = vec![0u8; dst_width * dst_height * pixel_size];
+
+    data.chunks_exact_mut(pixel_size)
+        .enumerate()
+        .for_each(|(idx, dst)| {
+            let x = idx % dst_width;
+            let y = idx / dst_width;
+            let src = match rotation {
+                Rotation::Rotate90 => pixel(y, height - 1 - x),
+                Rotation::Rotate180 => pixel(width - 1 - x, height - 1 - y),
+                Rotation::Rotate270 => pixel(width - 1 - y, x),
+            };
+            dst.copy_from_slice(src);
+        });
+
+    new_image(image, dst_width as u32, dst_height as u32, data)
+}
+
+/// Resizes an image. Pixel centers are aligned the same way as
+/// OpenCV's resize.
+pub fn image_resize(
+    image: &Image,
+    width: u32,
+    height: u32,
+    interpolation: Interpolation,
+) -> Result<Image> {
+    let pixel_size = pixel_size(image)?;
+    let encoding = check_image(image)?;
+    let src_width = image.width as usize;
+    let src_height = image.height as usize;
+    let row_step = image.step as usize;
+    let dst_width = width as usize;
+    let dst_height = height as usize;
+
+    ensure!(
+        (src_width > 0 && src_height > 0) || dst_width * dst_height == 0,
+        "Cannot resize an empty image to {width}x{height}"
+    );
+
+    let src = &image.data;
+    let pixel = |x: usize, y: usize| {
+        let offset = y * row_step + x * pixel_size;
+        &src[offset..offset + pixel_size]
+    };
+    let scale_x = src_width as f64 / dst_width as f64;
+    let scale_y = src_height as f64 / dst_height as f64;
+    let mut data = vec![0u8; dst_width * dst_height * pixel_size];
+
+    match interpolation {
+        Interpolation::Nearest => {
+            data.chunks_exact_mut(pixel_size)
+                .enumerate()
+                .for_each(|(idx, dst)| {
+                    let x = ((idx % dst_width) as f64 * scale_x) as usize;
+                    let y = ((idx / dst_width) as f64 * scale_y) as usize;
+                    dst.copy_from_slice(pixel(x.min(src_width - 1), y.min(src_height - 1)));
+                });
+        }
+        Interpolation::Bilinear => {
+            let channel_type = encoding.channel_type();
+            let elem_size = channel_type.size();
+            let is_bigendian = image.is_bigendian != 0;
+
+            // Returns the two neighbors and the weight of the second
+            // one along an axis.
+            let neighbors = |dst: usize, scale: f64, len: usize| {
+                let pos = ((dst as f64 + 0.5) * scale - 0.5).clamp(0.0, (len - 1) as f64);
+                let lower = pos.floor() as usize;
+                let upper = (lower + 1).min(len - 1);
+                (lower, upper, pos - lower as f64)
+            };
+
+            data.chunks_exact_mut(pixel_size)
+                .enumerate()
+                .for_each(|(idx, dst)| {
+                    let (x0, x1, wx) = neighbors(idx % dst_width, scale_x, src_width);
+                    let (y0, y1, wy) = neighbors(idx / dst_width, scale_y, src_height);
+                    let corners = [
+                        (pixel(x0, y0), (1.0 - wx) * (1.0 - wy)),
+                        (pixel(x1, y0), wx * (1.0 - wy)),
+                        (pixel(x0, y1), (1.0 - wx) * wy),
+                        (pixel(x1, y1), wx * wy),
+                    ];
+
+                    for offset in (0..pixel_size).step_by(elem_size) {
+                        let value: f64 = corners
+                            .iter()
+                            .map(|(pixel, weight)| {
+                                let bytes = &pixel[offset..offset + elem_size];
+                                read_channel(bytes, channel_type, is_bigendian) * weight
+                            })
+                            .sum();
+                        write_channel(
+                            value,
+                            channel_type,
+                            is_bigendian,
+                            &mut dst[offset..offset + elem_size],
+                        );
+                    }
+                });
+        }
+    }
+
+    new_image(image, width, height, data)
+}
+
+/// Returns the size of a pixel after rejecting encodings that cannot
+/// be manipulated pixel by pixel.
+pub(crate) fn pixel_size(image: &Image) -> Result<usize> {
+    let encoding = check_image(image)?;
+    if encoding.is_bayer() || encoding.is_yuv() {
+        bail!("image format '{encoding}' is not supported by geometric operations");
+    }
+    Ok(encoding.bytes_per_pixel())
+}
+
+/// Iterates over the rows of an image without padding. Images with
+/// zero width have no rows.
+fn rows(image: &Image, pixel_size: usize) -> impl DoubleEndedIterator<Item = &[u8]> {
+    let row_size = image.width as usize * pixel_size;
+    let height = image.height as usize;
+    let row_step = image.step as usize;
+
+    let data = if row_size == 0 || row_step == 0 {
+        &[][..]
+    } else {
+        &image.data[0..row_step * height]
+    };
+    data.chunks(row_step.max(1))
+        .map(move |row| &row[0..row_size])
+}
+
+/// Creates an image with the same header, encoding and byte order as
+/// the source from packed data.
+pub(crate) fn new_image(src: &Image, width: u32, height: u32, data: Vec<u8>) -> Result<Image> {
+    let encoding: ImageEncoding = src.encoding.parse()?;
+
+    Ok(Image {
+        header: src.header.clone(),
+        height,
+        width,
+        encoding: src.encoding.clone(),
+        is_bigendian: src.is_bigendian,
+        step: encoding.step(width)?,
+        data,
+    })
+}
+
+pub(crate) fn read_channel(bytes: &[u8], channel_type: ChannelType, is_bigendian: bool) -> f64 {
+    macro_rules! read {
+        ($ty:ty) => {{
+            let array = bytes.try_into().unwrap();
+            let value = if is_bigendian {
+                <$ty>::from_be_bytes(array)
+            } else {
+                <$ty>::from_le_bytes(array)
+            };
+            value as f64
+        }};
+    }
+
+    match channel_type {
+        ChannelType::U8 => read!(u8),
+        ChannelType::I8 => read!(i8),
+        ChannelType::U16 => read!(u16),
+        ChannelType::I16 => read!(i16),
+        ChannelType::I32 => read!(i32),
+        ChannelType::F32 => read!(f32),
+        ChannelType::F64 => read!(f64),
+    }
+}
+
+pub(crate) fn write_channel(
+    value: f64,
+    channel_type: ChannelType,
+    is_bigendian: bool,
+    bytes: &mut [u8],
+) {
+    macro_rules! write {
+        ($ty:ty, $value:expr) => {{
+            let value = $value as $ty;
+            let array = if is_bigendian {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            };
+            bytes.copy_from_slice(&array);
+        }};
+    }
+
+    // Integer conversions saturate at the bounds of the type.
+    match channel_type {
+        ChannelType::U8 => write!(u8, value.round()),
+        ChannelType::I8 => write!(i8, value.round()),
+        ChannelType::U16 => write!(u16, value.round()),
+        ChannelType::I16 => write!(i16, value.round()),
+        ChannelType::I32 => write!(i32, value.round()),
+        ChannelType::F32 => write!(f32, value),
+        ChannelType::F64 => write!(f64, value),
+    }
+}
+
+/// Folds the binning and the region of interest of a camera info into
+/// its intrinsics, the same way as image_geometry. The returned
+/// camera info describes the published images at full resolution.
+pub(crate) fn camera_info_at_full_resolution(camera_info: &CameraInfo) -> Result<CameraInfo> {
+    ensure!(
+        camera_info.k.len() == 9 && camera_info.p.len() == 12,
+        "Invalid camera info. Expect 9 elements in K and 12 in P, but get {} and {}.",
+        camera_info.k.len(),
+        camera_info.p.len()
+    );
+
+    let mut info = camera_info.clone();
+    let RegionOfInterest {
+        x_offset,
+        y_offset,
+        width: roi_width,
+        height: roi_height,
+        ..
+    } = camera_info.roi;
+    let binning_x = camera_info.binning_x.max(1);
+    let binning_y = camera_info.binning_y.max(1);
+
+    translate_camera_info(&mut info, x_offset as f64, y_offset as f64);
+    scale_camera_info(&mut info, 1.0 / binning_x as f64, 1.0 / binning_y as f64);
+
+    if roi_width != 0 && roi_height != 0 {
+        info.width = roi_width;
+        info.height = roi_height;
+    }
+    info.width /= binning_x;
+    info.height /= binning_y;
+    info.binning_x = 0;
+    info.binning_y = 0;
+    info.roi = RegionOfInterest::default();
+
+    Ok(info)
+}
+
+fn check_camera_info_size(camera_info: &CameraInfo, image: &Image) -> Result<()> {
+    ensure!(
+        camera_info.width == image.width && camera_info.height == image.height,
+        "The camera info is for {}x{} images, but the image is {}x{}",
+        camera_info.width,
+        camera_info.height,
+        image.width,
+        image.height
+    );
+    Ok(())
+}
+
+/// Moves the principal point after cropping at the offset.
+fn translate_camera_info(info: &mut CameraInfo, x_offset: f64, y_offset: f64) {
+    info.k[2] -= x_offset;
+    info.k[5] -= y_offset;
+    info.p[2] -= x_offset;
+    info.p[6] -= y_offset;
+}
+
+/// Scales the intrinsics after resizing, the same way as image_proc.
+fn scale_camera_info(info: &mut CameraInfo, scale_x: f64, scale_y: f64) {
+    info.k[0] *= scale_x;
+    info.k[2] *= scale_x;
+    info.k[4] *= scale_y;
+    info.k[5] *= scale_y;
+    info.p[0] *= scale_x;
+    info.p[2] *= scale_x;
+    info.p[3] *= scale_x;
+    info.p[5] *= scale_y;
+    info.p[6] *= scale_y;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 3x2 rgb8 image whose rows are padded by 2 bytes. The pixel at
+    /// (x, y) has the values [10 * y + x; 3].
+    fn rgb8() -> Image {
+        let data = (0..2u8)
+            .flat_map(|y| {
+                (0..3u8)
+                    .flat_map(move |x| [10 * y + x; 3])
+                    .chain([0xee, 0xee])
+            })
+            .collect();
+
+        Image {
+            height: 2,
+            width: 3,
+            encoding: "rgb8".to_string(),
+            step: 11,
+            data,
+            ..Default::default()
+        }
+    }
+
+    fn mono16(width: u32, height: u32, samples: &[u16], is_bigendian: bool) -> Image {
+        Image {
+            height,
+            width,
+            encoding: "mono16".to_string(),
+            is_bigendian: is_bigendian as u8,
+            step: width * 2,
+            data: samples
+                .iter()
+                .flat_map(|val| {
+                    if is_bigendian {
+                        val.to_be_bytes()
+                    } else {
+                        val.to_le_bytes()
+                    }
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Returns the first channel of each pixel of a packed rgb8 image.
+    fn pixels(image: &Image) -> Vec<u8> {
+        assert_eq!(image.step, image.width * 3);
+        assert_eq!(image.data.len(), (image.step * image.height) as usize);
+        image.data.chunks_exact(3).map(|pixel| pixel[0]).collect()
+    }
+
+    fn samples(image: &Image) -> Vec<u16> {
+        image
+            .data
+            .chunks_exact(2)
+            .map(|bytes| {
+                let bytes = [bytes[0], bytes[1]];
+                if image.is_bigendian != 0 {
+                    u16::from_be_bytes(bytes)
+                } else {
+                    u16::from_le_bytes(bytes)
+                }
+            })
+            .collect()
+    }
+
+    fn roi(x_offset: u32, y_offset: u32, width: u32, height: u32) -> RegionOfInterest {
+        RegionOfInterest {
+            x_offset,
+            y_offset,
+            width,
+            height,
+            ..Default::default()
+        }
+    }
+
+    fn camera_info() -> CameraInfo {
+        CameraInfo {
+            width: 3,
+            height: 2,
+            k: vec![100.0, 0.0, 1.5, 0.0, 110.0, 1.0, 0.0, 0.0, 1.0],
+            p: vec![
+                100.0, 0.0, 1.5, 5.0, 0.0, 110.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0,
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn crop() {
+        let image = rgb8();
+
+        let cropped = image.crop(&roi(1, 1, 2, 1)).unwrap();
+        assert_eq!((cropped.width, cropped.height), (2, 1));
+        assert_eq!(pixels(&cropped), [11, 12]);
+
+        // A zero-sized region selects the rest of the image.
+        let cropped = image.crop(&roi(1, 0, 0, 0)).unwrap();
+        assert_eq!(pixels(&cropped), [1, 2, 11, 12]);
+
+        assert!(image.crop(&roi(2, 0, 2, 1)).is_err());
+        assert!(image.crop(&roi(0, 1, 1, 2)).is_err());
+    }
+
+    #[test]
+    fn flip() {
+        let image = rgb8();
+        assert_eq!(
+            pixels(&image.flip_horizontal().unwrap()),
+            [2, 1, 0, 12, 11, 10]
+        );
+        assert_eq!(
+            pixels(&image.flip_vertical().unwrap()),
+            [10, 11, 12, 0, 1, 2]
+        );
+    }
+
+    #[test]
+    fn rotate() {
+        let image = rgb8();
+
+        let rotated = image.rotate(Rotation::Rotate90).unwrap();
+        assert_eq!((rotated.width, rotated.height), (2, 3));
+        assert_eq!(pixels(&rotated), [10, 0, 11, 1, 12, 2]);
+
+        let rotated = image.rotate(Rotation::Rotate180).unwrap();
+        assert_eq!((rotated.width, rotated.height), (3, 2));
+        assert_eq!(pixels(&rotated), [12, 11, 10, 2, 1, 0]);
+
+        let rotated = image.rotate(Rotation::Rotate270).unwrap();
+        assert_eq!((rotated.width, rotated.height), (2, 3));
+        assert_eq!(pixels(&rotated), [2, 12, 1, 11, 0, 10]);
+    }
+
+    #[test]
+    fn resize() {
+        let image = rgb8();
+
+        let resized = image.resize(6, 4, Interpolation::Nearest).unwrap();
+        assert_eq!(
+            pixels(&resized),
+            [0, 0, 1, 1, 2, 2, 0, 0, 1, 1, 2, 2, 10, 10, 11, 11, 12, 12, 10, 10, 11, 11, 12, 12]
+        );
+
+        // Pixel centers are aligned, so halving averages pairs.
+        let image = mono16(4, 1, &[0, 100, 200, 300], false);
+        let resized = image.resize(2, 1, Interpolation::Bilinear).unwrap();
+        assert_eq!(samples(&resized), [50, 250]);
+
+        let resized = image.resize(0, 0, Interpolation::Bilinear).unwrap();
+        assert_eq!(
+            (resized.width, resized.height, resized.data.len()),
+            (0, 0, 0)
+        );
+
+        let empty = mono16(0, 0, &[], false);
+        assert!(empty.resize(2, 2, Interpolation::Nearest).is_err());
+    }
+
+    #[test]
+    fn keep_byte_order() {
+        let image = mono16(2, 2, &[1, 0x0102, 0x0304, 0xffff], true);
+
+        let flipped = image.flip_horizontal().unwrap();
+        assert_eq!(flipped.is_bigendian, 1);
+        assert_eq!(samples(&flipped), [0x0102, 1, 0xffff, 0x0304]);
+
+        let resized = image.resize(1, 1, Interpolation::Bilinear).unwrap();
+        assert_eq!(resized.is_bigendian, 1);
+        // (1 + 0x0102 + 0x0304 + 0xffff) / 4 = 16641.5 is rounded up.
+        assert_eq!(samples(&resized), [16642]);
+    }
+
+    #[test]
+    fn zero_width_image() {
+        let image = Image {
+            height: 2,
+            width: 0,
+            encoding: "rgb8".to_string(),
+            ..Default::default()
+        };
+
+        for image in [
+            image.flip_horizontal().unwrap(),
+            image.flip_vertical().unwrap(),
+            image.rotate(Rotation::Rotate90).unwrap(),
+            image.crop(&roi(0, 0, 0, 0)).unwrap(),
+        ] {
+            assert!(image.data.is_empty());
+        }
+    }
+
+    #[test]
+    fn reject_bayer_and_yuv() {
+        let image = Image {
+            height: 2,
+            width: 2,
+            step: 2,
+            data: vec![0; 4],
+            encoding: "bayer_rggb8".to_string(),
+            ..Default::default()
+        };
+        assert!(image.flip_horizontal().is_err());
+
+        let image = Image {
+            encoding: "yuv422".to_string(),
+            step: 4,
+            data: vec![0; 8],
+            ..image
+        };
+        assert!(image.resize(4, 4, Interpolation::Nearest).is_err());
+    }
+
+    #[test]
+    fn crop_camera_info() {
+        let (image, info) = rgb8()
+            .crop_with_camera_info(&roi(1, 1, 2, 1), &camera_info())
+            .unwrap();
+        assert_eq!((info.width, info.height), (image.width, image.height));
+        assert_eq!((info.k[2], info.k[5]), (0.5, 0.0));
+        assert_eq!((info.p[2], info.p[6], info.p[3]), (0.5, 0.0, 5.0));
+
+        let mut other = camera_info();
+        other.width = 4;
+        assert!(rgb8()
+            .crop_with_camera_info(&roi(0, 0, 1, 1), &other)
+            .is_err());
+    }
+
+    #[test]
+    fn resize_camera_info() {
+        let (image, info) = rgb8()
+            .resize_with_camera_info(6, 1, Interpolation::Nearest, &camera_info())
+            .unwrap();
+        assert_eq!((image.width, image.height), (6, 1));
+        assert_eq!((info.width, info.height), (6, 1));
+        assert_eq!(info.k[0..6], [200.0, 0.0, 3.0, 0.0, 55.0, 0.5]);
+        assert_eq!(info.p[0..7], [200.0, 0.0, 3.0, 10.0, 0.0, 55.0, 0.5]);
+    }
+
+    #[test]
+    fn full_resolution_camera_info() {
+        let mut info = camera_info();
+        info.width = 8;
+        info.height = 6;
+        info.binning_x = 2;
+        info.binning_y = 2;
+        info.roi = roi(2, 2, 6, 4);
+
+        let info = camera_info_at_full_resolution(&info).unwrap();
+        assert_eq!((info.width, info.height), (3, 2));
+        assert_eq!((info.binning_x, info.binning_y), (0, 0));
+        assert_eq!(info.roi, RegionOfInterest::default());
+        assert_eq!(info.k[0..6], [50.0, 0.0, -0.25, 0.0, 55.0, -0.5]);
+        assert_eq!(info.p[3], 2.5);
+
+        let mut info = camera_info();
+        info.k.pop();
+        assert!(camera_info_at_full_resolution(&info).is_err());
+    }
+}
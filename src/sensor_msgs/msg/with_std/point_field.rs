@@ -0,0 +1,185 @@
+use anyhow::{bail, ensure, Result};
+use r2r::sensor_msgs::msg::{PointCloud2, PointField};
+
+/// The datatypes of `sensor_msgs/PointField`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum PointFieldType {
+    I8 = 1,
+    U8 = 2,
+    I16 = 3,
+    U16 = 4,
+    I32 = 5,
+    U32 = 6,
+    F32 = 7,
+    F64 = 8,
+}
+
+impl PointFieldType {
+    pub fn from_u8(datatype: u8) -> Option<Self> {
+        let datatype = match datatype {
+            1 => Self::I8,
+            2 => Self::U8,
+            3 => Self::I16,
+            4 => Self::U16,
+            5 => Self::I32,
+            6 => Self::U32,
+            7 => Self::F32,
+            8 => Self::F64,
+            _ => return None,
+        };
+        Some(datatype)
+    }
+
+    /// Returns the size of a value in bytes.
+    pub fn size(&self) -> usize {
+        match self {
+            PointFieldType::I8 => 1,
+            PointFieldType::U8 => 1,
+            PointFieldType::I16 => 2,
+            PointFieldType::U16 => 2,
+            PointFieldType::I32 => 4,
+            PointFieldType::U32 => 4,
+            PointFieldType::F32 => 4,
+            PointFieldType::F64 => 8,
+        }
+    }
+}
+
+/// Packs point fields back to back, without padding, in the order
+/// they are pushed.
+#[derive(Debug, Clone, Default)]
+pub struct PointFieldLayout {
+    fields: Vec<PointField>,
+    point_step: u32,
+}
+
+impl PointFieldLayout {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Continues the layout of existing fields. New fields are placed
+    /// after `point_step`.
+    pub fn from_fields(fields: Vec<PointField>, point_step: u32) -> Self {
+        Self { fields, point_step }
+    }
+
+    /// Appends a field and returns its offset in a point.
+    pub fn push(&mut self, name: &str, datatype: PointFieldType, count: u32) -> u32 {
+        let offset = self.point_step;
+        self.fields.push(PointField {
+            name: name.to_string(),
+            offset,
+            datatype: datatype as u8,
+            count,
+        });
+        self.point_step += datatype.size() as u32 * count;
+        offset
+    }
+
+    pub fn fields(&self) -> &[PointField] {
+        &self.fields
+    }
+
+    /// Returns the total size of the fields in bytes.
+    pub fn point_step(&self) -> u32 {
+        self.point_step
+    }
+
+    pub fn into_fields(self) -> Vec<PointField> {
+        self.fields
+    }
+}
+
+/// Reads a single-valued numeric field of points as f64.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct FieldReader {
+    offset: usize,
+    datatype: PointFieldType,
+    is_bigendian: bool,
+}
+
+impl FieldReader {
+    pub(crate) fn new(pcd: &PointCloud2, name: &str) -> Result<Self> {
+        let Some(field) = pcd.fields.iter().find(|field| field.name == name) else {
+            bail!("The point cloud has no field named '{name}'");
+        };
+        let Some(datatype) = PointFieldType::from_u8(field.datatype) else {
+            bail!("Unsupported datatype {} of field '{name}'", field.datatype);
+        };
+        let offset = field.offset as usize;
+        ensure!(
+            offset + datatype.size() <= pcd.point_step as usize,
+            "The field '{name}' exceeds the point step {}",
+            pcd.point_step
+        );
+
+        Ok(Self {
+            offset,
+            datatype,
+            is_bigendian: pcd.is_bigendian,
+        })
+    }
+
+    pub(crate) fn read(&self, point: &[u8]) -> f64 {
+        macro_rules! read {
+            ($ty:ty) => {{
+                let bytes = &point[self.offset..self.offset + std::mem::size_of::<$ty>()];
+                let array = bytes.try_into().unwrap();
+                let value = if self.is_bigendian {
+                    <$ty>::from_be_bytes(array)
+                } else {
+                    <$ty>::from_le_bytes(array)
+                };
+                value as f64
+            }};
+        }
+
+        match self.datatype {
+            PointFieldType::I8 => read!(i8),
+            PointFieldType::U8 => read!(u8),
+            PointFieldType::I16 => read!(i16),
+            PointFieldType::U16 => read!(u16),
+            PointFieldType::I32 => read!(i32),
+            PointFieldType::U32 => read!(u32),
+            PointFieldType::F32 => read!(f32),
+            PointFieldType::F64 => read!(f64),
+        }
+    }
+}
+
+/// Iterates over the bytes of points in row-major order, skipping the
+/// padding at the end of rows.
+pub(crate) fn point_chunks(pcd: &PointCloud2) -> Result<impl Iterator<Item = &[u8]> + '_> {
+    let PointCloud2 {
+        height,
+        width,
+        point_step,
+        row_step,
+        ref data,
+        ..
+    } = *pcd;
+    let height = height as usize;
+    let width = width as usize;
+    let point_step = point_step as usize;
+    let row_step = row_step as usize;
+    let row_size = width * point_step;
+
+    ensure!(
+        row_size <= row_step,
+        "Invalid row step {row_step}. Expect at least {row_size} bytes."
+    );
+    ensure!(
+        data.len() >= row_step * height,
+        "Invalid data size. Expect {} bytes, but get {} bytes.",
+        row_step * height,
+        data.len()
+    );
+
+    let iter = data
+        .chunks(row_step.max(1))
+        .take(height)
+        .flat_map(move |row| row[0..row_size].chunks(point_step.max(1)));
+    Ok(iter)
+}
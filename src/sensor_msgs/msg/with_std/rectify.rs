@@ -0,0 +1,326 @@
+use super::{
+    check_image, new_image, pixel_size, read_channel, write_channel, Interpolation, PinholeCamera,
+};
+use anyhow::{ensure, Result};
+use r2r::sensor_msgs::msg::{CameraInfo, Image};
+
+/// Undistorts or rectifies raw images of a camera, the same way as
+/// image_proc.
+///
+/// The remap table is computed once on creation, so the rectifier
+/// should be reused for images of the same camera. Pixels outside the
+/// raw image are filled with zeros. Bayer and YUV images must be
+/// converted before rectification.
+#[derive(Debug, Clone)]
+pub struct Rectifier {
+    width: u32,
+    height: u32,
+    interpolation: Interpolation,
+    map: Vec<[f32; 2]>,
+}
+
+impl Rectifier {
+    /// Creates a rectifier that produces images for the projection
+    /// matrix P, applying the rectification rotation R.
+    pub fn new(camera_info: &CameraInfo, interpolation: Interpolation) -> Result<Self> {
+        let camera = PinholeCamera::from_camera_info(camera_info)?;
+        let p = camera.p;
+        let new_k = [p[0], p[1], p[2], p[4], p[5], p[6], p[8], p[9], p[10]];
+        Ok(Self::with_camera(&camera, new_k, camera.r, interpolation))
+    }
+
+    /// Creates a rectifier that only removes the distortion, keeping
+    /// the intrinsic matrix K.
+    pub fn new_undistort(camera_info: &CameraInfo, interpolation: Interpolation) -> Result<Self> {
+        let camera = PinholeCamera::from_camera_info(camera_info)?;
+        let identity = [1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0];
+        Ok(Self::with_camera(
+            &camera,
+            camera.k,
+            identity,
+            interpolation,
+        ))
+    }
+
+    fn with_camera(
+        camera: &PinholeCamera,
+        new_k: [f64; 9],
+        r: [f64; 9],
+        interpolation: Interpolation,
+    ) -> Self {
+        let PinholeCamera { width, height, .. } = *camera;
+        let [fx, skew, cx, _, fy, cy, ..] = new_k;
+
+        let map = (0..height)
+            .flat_map(|v| (0..width).map(move |u| (u as f64, v as f64)))
+            .map(|(u, v)| {
+                let y = (v - cy) / fy;
+                let x = (u - cx - skew * y) / fx;
+
+                // Rotate the ray back to the camera frame by R^T.
+                let ray = [
+                    r[0] * x + r[3] * y + r[6],
+                    r[1] * x + r[4] * y + r[7],
+                    r[2] * x + r[5] * y + r[8],
+                ];
+
+                match camera.project(ray) {
+                    Some([u, v]) => [u as f32, v as f32],
+                    None => [f32::NAN; 2],
+                }
+            })
+            .collect();
+
+        Self {
+            width,
+            height,
+            interpolation,
+            map,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Returns the position on the raw image for each pixel of the
+    /// output image in row-major order. Positions are NaN if the pixel
+    /// has no source.
+    pub fn map(&self) -> &[[f32; 2]] {
+        &self.map
+    }
+
+    pub fn rectify(&self, image: &Image) -> Result<Image> {
+        ensure!(
+            image.width == self.width && image.height == self.height,
+            "The rectifier is for {}x{} images, but the image is {}x{}",
+            self.width,
+            self.height,
+            image.width,
+            image.height
+        );
+        image_remap(
+            image,
+            &self.map,
+            self.width,
+            self.height,
+            self.interpolation,
+        )
+    }
+}
+
+/// Samples an image at the positions of the map to create an image of
+/// the given size, like OpenCV's remap() with a constant zero border.
+pub fn image_remap(
+    image: &Image,
+    map: &[[f32; 2]],
+    width: u32,
+    height: u32,
+    interpolation: Interpolation,
+) -> Result<Image> {
+    let pixel_size = pixel_size(image)?;
+    let encoding = check_image(image)?;
+    let dst_width = width as usize;
+    let dst_height = height as usize;
+    ensure!(
+        map.len() == dst_width * dst_height,
+        "Expect {} map entries for {width}x{height} images, but get {}",
+        dst_width * dst_height,
+        map.len()
+    );
+
+    let src_width = image.width as i64;
+    let src_height = image.height as i64;
+    let row_step = image.step as usize;
+    let src = &image.data;
+    let pixel = |x: i64, y: i64| {
+        let inside = (0..src_width).contains(&x) && (0..src_height).contains(&y);
+        inside.then(|| {
+            let offset = y as usize * row_step + x as usize * pixel_size;
+            &src[offset..offset + pixel_size]
+        })
+    };
+    let mut data = vec![0u8; dst_width * dst_height * pixel_size];
+
+    match interpolation {
+        Interpolation::Nearest => {
+            data.chunks_exact_mut(pixel_size)
+                .zip(map)
+                .for_each(|(dst, &[x, y])| {
+                    if !x.is_finite() || !y.is_finite() {
+                        return;
+                    }
+                    if let Some(src) = pixel(x.round() as i64, y.round() as i64) {
+                        dst.copy_from_slice(src);
+                    }
+                });
+        }
+        Interpolation::Bilinear => {
+            let channel_type = encoding.channel_type();
+            let elem_size = channel_type.size();
+            let is_bigendian = image.is_bigendian != 0;
+
+            data.chunks_exact_mut(pixel_size)
+                .zip(map)
+                .for_each(|(dst, &[x, y])| {
+                    if !x.is_finite() || !y.is_finite() {
+                        return;
+                    }
+
+                    let x0 = x.floor() as i64;
+                    let y0 = y.floor() as i64;
+                    let wx = (x - x0 as f32) as f64;
+                    let wy = (y - y0 as f32) as f64;
+                    let corners = [
+                        (pixel(x0, y0), (1.0 - wx) * (1.0 - wy)),
+                        (pixel(x0 + 1, y0), wx * (1.0 - wy)),
+                        (pixel(x0, y0 + 1), (1.0 - wx) * wy),
+                        (pixel(x0 + 1, y0 + 1), wx * wy),
+                    ];
+                    if corners.iter().all(|(pixel, _)| pixel.is_none()) {
+                        return;
+                    }
+
+                    for offset in (0..pixel_size).step_by(elem_size) {
+                        let value: f64 = corners
+                            .iter()
+                            .filter_map(|(pixel, weight)| Some((pixel.as_ref()?, weight)))
+                            .map(|(pixel, weight)| {
+                                let bytes = &pixel[offset..offset + elem_size];
+                                read_channel(bytes, channel_type, is_bigendian) * weight
+                            })
+                            .sum();
+                        write_channel(
+                            value,
+                            channel_type,
+                            is_bigendian,
+                            &mut dst[offset..offset + elem_size],
+                        );
+                    }
+                });
+        }
+    }
+
+    new_image(image, width, height, data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: u32 = 8;
+    const HEIGHT: u32 = 6;
+
+    fn camera_info(distortion_model: &str, d: Vec<f64>) -> CameraInfo {
+        CameraInfo {
+            width: WIDTH,
+            height: HEIGHT,
+            distortion_model: distortion_model.to_string(),
+            d,
+            k: vec![10.0, 0.0, 3.5, 0.0, 12.0, 2.5, 0.0, 0.0, 1.0],
+            r: vec![1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0],
+            p: vec![10.0, 0.0, 3.5, 0.0, 0.0, 12.0, 2.5, 0.0, 0.0, 0.0, 1.0, 0.0],
+            ..Default::default()
+        }
+    }
+
+    /// A mono8 image whose pixels have distinct values.
+    fn mono8() -> Image {
+        Image {
+            height: HEIGHT,
+            width: WIDTH,
+            encoding: "mono8".to_string(),
+            step: WIDTH,
+            data: (0..WIDTH * HEIGHT).map(|idx| (idx * 5) as u8).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn identity_rectification() {
+        let info = camera_info("plumb_bob", vec![0.0; 5]);
+        let image = mono8();
+
+        for interpolation in [Interpolation::Nearest, Interpolation::Bilinear] {
+            for rectifier in [
+                Rectifier::new(&info, interpolation).unwrap(),
+                Rectifier::new_undistort(&info, interpolation).unwrap(),
+            ] {
+                assert_eq!((rectifier.width(), rectifier.height()), (WIDTH, HEIGHT));
+                assert_eq!(rectifier.rectify(&image).unwrap(), image);
+            }
+        }
+    }
+
+    #[test]
+    fn remap_matches_projection() {
+        let mut info = camera_info("plumb_bob", vec![-0.2, 0.05, 0.001, -0.002, 0.0]);
+        // A small rotation about the y axis, and a new focal length.
+        let (sin, cos) = 0.05f64.sin_cos();
+        info.r = vec![cos, 0.0, sin, 0.0, 1.0, 0.0, -sin, 0.0, cos];
+        info.p[0] = 9.0;
+        info.p[5] = 11.0;
+
+        let camera = PinholeCamera::from_camera_info(&info).unwrap();
+        let rectifier = Rectifier::new(&info, Interpolation::Nearest).unwrap();
+        let r = &info.r;
+
+        for (idx, &[mx, my]) in rectifier.map().iter().enumerate() {
+            let u = (idx % WIDTH as usize) as f64;
+            let v = (idx / WIDTH as usize) as f64;
+            let x = (u - 3.5) / 9.0;
+            let y = (v - 2.5) / 11.0;
+            let ray = [
+                r[0] * x + r[3] * y + r[6],
+                r[1] * x + r[4] * y + r[7],
+                r[2] * x + r[5] * y + r[8],
+            ];
+            let [eu, ev] = camera.project(ray).unwrap();
+            assert!(
+                (mx as f64 - eu).abs() < 1e-4 && (my as f64 - ev).abs() < 1e-4,
+                "pixel ({u}, {v}) maps to ({mx}, {my}), but expect ({eu}, {ev})"
+            );
+        }
+
+        // Nearest sampling takes the raw pixel at the rounded position,
+        // or zero outside the raw image.
+        let image = mono8();
+        let rectified = rectifier.rectify(&image).unwrap();
+        for (&value, &[mx, my]) in rectified.data.iter().zip(rectifier.map()) {
+            let (x, y) = (mx.round() as i64, my.round() as i64);
+            let expect = if (0..WIDTH as i64).contains(&x) && (0..HEIGHT as i64).contains(&y) {
+                image.data[y as usize * WIDTH as usize + x as usize]
+            } else {
+                0
+            };
+            assert_eq!(value, expect);
+        }
+    }
+
+    #[test]
+    fn remap_borders() {
+        let image = mono8();
+        let map = [[0.5, 0.0], [-1.0, 0.0], [f32::NAN, 0.0], [7.5, 5.0]];
+
+        let remapped = image_remap(&image, &map, 4, 1, Interpolation::Bilinear).unwrap();
+        assert_eq!((remapped.width, remapped.height), (4, 1));
+        // The outside neighbor of the last pixel counts as zero.
+        assert_eq!(remapped.data, [3, 0, 0, 118]);
+
+        assert!(image_remap(&image, &map, 2, 1, Interpolation::Nearest).is_err());
+    }
+
+    #[test]
+    fn reject_wrong_image_size() {
+        let info = camera_info("", vec![]);
+        let rectifier = Rectifier::new(&info, Interpolation::Bilinear).unwrap();
+
+        let mut image = mono8();
+        image.width -= 1;
+        assert!(rectifier.rectify(&image).is_err());
+    }
+}
@@ -0,0 +1,210 @@
+use anyhow::{anyhow, ensure, Result};
+use std::slice::ChunksExact;
+
+/// Compresses 16-bit depth samples using the RVL codec (Wilson,
+/// "Fast Lossless Depth Image Compression", 2017).
+///
+/// Runs of zeros and non-zeros are stored as variable-length integers
+/// and non-zero samples as zigzag-coded deltas. The output is a
+/// sequence of 32-bit little-endian words, compatible with the RVL
+/// codec in image_transport's compressedDepth plugin.
+pub fn rvl_encode(samples: &[u16]) -> Vec<u8> {
+    let mut writer = NibbleWriter::default();
+    let mut previous = 0u16;
+    let mut rest = samples;
+
+    while !rest.is_empty() {
+        let zeros = rest.iter().take_while(|&&val| val == 0).count();
+        writer.write_vle(zeros as u32);
+        rest = &rest[zeros..];
+
+        let nonzeros = rest.iter().take_while(|&&val| val != 0).count();
+        writer.write_vle(nonzeros as u32);
+
+        for &current in &rest[0..nonzeros] {
+            let delta = current as i32 - previous as i32;
+            let positive = ((delta << 1) ^ (delta >> 31)) as u32;
+            writer.write_vle(positive);
+            previous = current;
+        }
+        rest = &rest[nonzeros..];
+    }
+
+    writer.finish()
+}
+
+/// Decompresses RVL data produced by [rvl_encode] to exactly
+/// `num_samples` 16-bit samples.
+///
+/// The output grows as runs are decoded instead of being allocated
+/// up front, because `num_samples` usually comes from an untrusted
+/// message.
+pub fn rvl_decode(data: &[u8], num_samples: usize) -> Result<Vec<u16>> {
+    let mut reader = NibbleReader {
+        words: data.chunks_exact(4),
+        word: 0,
+        nibbles: 0,
+    };
+    let mut samples = vec![];
+    let mut previous = 0u16;
+
+    while samples.len() < num_samples {
+        let zeros = reader.read_vle()? as usize;
+        ensure!(
+            zeros <= num_samples - samples.len(),
+            "RVL data has more than {num_samples} samples"
+        );
+        samples.resize(samples.len() + zeros, 0);
+
+        let nonzeros = reader.read_vle()? as usize;
+        ensure!(
+            nonzeros <= num_samples - samples.len(),
+            "RVL data has more than {num_samples} samples"
+        );
+
+        for _ in 0..nonzeros {
+            let positive = reader.read_vle()? as i32;
+            let delta = (positive >> 1) ^ -(positive & 1);
+            let current = (previous as i32 + delta) as u16;
+            samples.push(current);
+            previous = current;
+        }
+    }
+
+    Ok(samples)
+}
+
+/// Packs 4-bit nibbles into 32-bit words, the most significant nibble
+/// first.
+#[derive(Default)]
+struct NibbleWriter {
+    output: Vec<u8>,
+    word: u32,
+    nibbles: u32,
+}
+
+impl NibbleWriter {
+    /// Writes a value in chunks of 3 bits, the least significant chunk
+    /// first. The 4th bit of a nibble tells whether more chunks follow.
+    fn write_vle(&mut self, mut value: u32) {
+        loop {
+            let mut nibble = value & 0x7;
+            value >>= 3;
+            if value != 0 {
+                nibble |= 0x8;
+            }
+
+            self.word = (self.word << 4) | nibble;
+            self.nibbles += 1;
+
+            if self.nibbles == 8 {
+                self.output.extend(self.word.to_le_bytes());
+                self.word = 0;
+                self.nibbles = 0;
+            }
+
+            if value == 0 {
+                break;
+            }
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.nibbles > 0 {
+            let word = self.word << (4 * (8 - self.nibbles));
+            self.output.extend(word.to_le_bytes());
+        }
+        self.output
+    }
+}
+
+struct NibbleReader<'a> {
+    words: ChunksExact<'a, u8>,
+    word: u32,
+    nibbles: u32,
+}
+
+impl NibbleReader<'_> {
+    fn read_vle(&mut self) -> Result<u32> {
+        let mut value = 0u32;
+        let mut shift = 0;
+
+        loop {
+            if self.nibbles == 0 {
+                let bytes = self
+                    .words
+                    .next()
+                    .ok_or_else(|| anyhow!("RVL data is truncated"))?;
+                self.word = u32::from_le_bytes(bytes.try_into().unwrap());
+                self.nibbles = 8;
+            }
+
+            let nibble = self.word >> 28;
+            self.word <<= 4;
+            self.nibbles -= 1;
+
+            if shift < 32 {
+                value |= (nibble & 0x7) << shift;
+            }
+            shift += 3;
+
+            if nibble & 0x8 == 0 {
+                return Ok(value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_matches_image_transport() {
+        // Encoded by the reference CompressRVL() of image_transport.
+        let samples = [0, 0, 1000, 1001, 999, 0, 65535, 7];
+        let expect = [35, 243, 138, 35, 243, 140, 142, 18, 0, 243, 255, 253];
+
+        assert_eq!(rvl_encode(&samples), expect);
+        assert_eq!(rvl_decode(&expect, samples.len()).unwrap(), samples);
+    }
+
+    #[test]
+    fn encode_decode_round_trip() {
+        let cases: [Vec<u16>; 5] = [
+            vec![],
+            vec![0; 100],
+            vec![1, 2, 3, 4, 5, 6, 7, 8, 9],
+            vec![0, 65535, 0, 65535, 1, 0, 0, 32768],
+            (0..5000u32)
+                .map(|idx| {
+                    if idx % 7 < 2 {
+                        0
+                    } else {
+                        (idx.wrapping_mul(2654435761) >> 16) as u16
+                    }
+                })
+                .collect(),
+        ];
+
+        for samples in cases {
+            let data = rvl_encode(&samples);
+            assert_eq!(data.len() % 4, 0);
+            assert_eq!(rvl_decode(&data, samples.len()).unwrap(), samples);
+        }
+    }
+
+    #[test]
+    fn decode_invalid_data() {
+        let samples: Vec<u16> = (1..=20).collect();
+        let data = rvl_encode(&samples);
+
+        // Truncated data
+        assert!(rvl_decode(&data[0..data.len() - 4], 20).is_err());
+        // More samples than requested
+        assert!(rvl_decode(&data, 10).is_err());
+        // A huge sample count with little data fails without
+        // allocating the output.
+        assert!(rvl_decode(&data, usize::MAX).is_err());
+    }
+}
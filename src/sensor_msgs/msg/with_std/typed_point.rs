@@ -0,0 +1,312 @@
+use super::{point_chunks, PointFieldType};
+use anyhow::{bail, ensure, Result};
+use r2r::{
+    sensor_msgs::msg::{PointCloud2, PointField},
+    std_msgs::msg::Header,
+};
+
+#[cfg(feature = "derive")]
+pub use r2r_msg_ext_derive::PointCloud2Point;
+
+/// A point type with a fixed layout of point fields.
+///
+/// It is usually derived on a `#[repr(C)]` struct whose fields are
+/// numbers or arrays of numbers. The derived layout follows the
+/// memory layout of the struct, including the padding. It differs
+/// from PCL point types unless the struct adds the same padding, since
+/// PCL aligns xyz and other groups of fields to 16 bytes.
+///
+/// ```ignore
+/// #[derive(Clone, Copy, PointCloud2Point)]
+/// #[repr(C)]
+/// struct XyzIr {
+///     x: f32,
+///     y: f32,
+///     z: f32,
+///     intensity: f32,
+///     ring: u16,
+/// }
+/// ```
+pub trait PointCloud2Point: Sized {
+    /// The fields of the point with the offsets used by
+    /// [write_point](PointCloud2Point::write_point).
+    fn point_fields() -> Vec<PointField>;
+
+    /// The size of an encoded point in bytes.
+    fn point_step() -> u32;
+
+    /// Decodes a point. `offsets` are the offsets of the fields in
+    /// `point`, in the order of [point_fields](PointCloud2Point::point_fields).
+    fn read_point(point: &[u8], offsets: &[usize], is_bigendian: bool) -> Self;
+
+    /// Encodes the point at the offsets of
+    /// [point_fields](PointCloud2Point::point_fields).
+    fn write_point(&self, point: &mut [u8], is_bigendian: bool);
+}
+
+/// A value stored in a point field, which is a number or an array of
+/// numbers.
+pub trait PointFieldValue: Sized {
+    const DATATYPE: PointFieldType;
+    const COUNT: u32;
+
+    fn read_field(bytes: &[u8], is_bigendian: bool) -> Self;
+
+    fn write_field(&self, bytes: &mut [u8], is_bigendian: bool);
+
+    fn point_field(name: &str, offset: usize) -> PointField {
+        PointField {
+            name: name.to_string(),
+            offset: offset as u32,
+            datatype: Self::DATATYPE as u8,
+            count: Self::COUNT,
+        }
+    }
+}
+
+macro_rules! impl_point_field_value {
+    ($ty:ty, $datatype:ident) => {
+        impl PointFieldValue for $ty {
+            const DATATYPE: PointFieldType = PointFieldType::$datatype;
+            const COUNT: u32 = 1;
+
+            fn read_field(bytes: &[u8], is_bigendian: bool) -> Self {
+                let array = bytes[0..std::mem::size_of::<$ty>()].try_into().unwrap();
+                if is_bigendian {
+                    <$ty>::from_be_bytes(array)
+                } else {
+                    <$ty>::from_le_bytes(array)
+                }
+            }
+
+            fn write_field(&self, bytes: &mut [u8], is_bigendian: bool) {
+                let array = if is_bigendian {
+                    self.to_be_bytes()
+                } else {
+                    self.to_le_bytes()
+                };
+                bytes[0..std::mem::size_of::<$ty>()].copy_from_slice(&array);
+            }
+        }
+    };
+}
+
+impl_point_field_value!(i8, I8);
+impl_point_field_value!(u8, U8);
+impl_point_field_value!(i16, I16);
+impl_point_field_value!(u16, U16);
+impl_point_field_value!(i32, I32);
+impl_point_field_value!(u32, U32);
+impl_point_field_value!(f32, F32);
+impl_point_field_value!(f64, F64);
+
+impl<T, const N: usize> PointFieldValue for [T; N]
+where
+    T: PointFieldValue + Copy + Default,
+{
+    const DATATYPE: PointFieldType = T::DATATYPE;
+    const COUNT: u32 = T::COUNT * N as u32;
+
+    fn read_field(bytes: &[u8], is_bigendian: bool) -> Self {
+        let size = T::DATATYPE.size() * T::COUNT as usize;
+        let mut array = [T::default(); N];
+        array.iter_mut().enumerate().for_each(|(idx, value)| {
+            *value = T::read_field(&bytes[idx * size..], is_bigendian);
+        });
+        array
+    }
+
+    fn write_field(&self, bytes: &mut [u8], is_bigendian: bool) {
+        let size = T::DATATYPE.size() * T::COUNT as usize;
+        self.iter().enumerate().for_each(|(idx, value)| {
+            value.write_field(&mut bytes[idx * size..], is_bigendian);
+        });
+    }
+}
+
+/// Decodes a single field of a point cloud. It is obtained by
+/// [field](super::PointCloud2Ext::field).
+#[derive(Debug, Clone, Copy)]
+pub struct FieldAccessor<'a> {
+    pcd: &'a PointCloud2,
+    field: &'a PointField,
+}
+
+impl<'a> FieldAccessor<'a> {
+    /// Looks up a field by name.
+    pub fn new(pcd: &'a PointCloud2, name: &str) -> Option<Self> {
+        let field = pcd.fields.iter().find(|field| field.name == name)?;
+        Some(Self { pcd, field })
+    }
+
+    pub fn name(&self) -> &'a str {
+        &self.field.name
+    }
+
+    /// Returns the datatype, or `None` if the datatype is not valid.
+    pub fn datatype(&self) -> Option<PointFieldType> {
+        PointFieldType::from_u8(self.field.datatype)
+    }
+
+    pub fn count(&self) -> u32 {
+        self.field.count
+    }
+
+    pub fn offset(&self) -> u32 {
+        self.field.offset
+    }
+
+    /// Decodes the field of every point in row-major order. Fields
+    /// with `count > 1` are decoded to arrays, such as `[f32; 3]`.
+    ///
+    /// It returns an error if the datatype or count of `T` does not
+    /// match the field.
+    pub fn iter<T>(&self) -> Result<impl Iterator<Item = T> + Sync + Send + 'a>
+    where
+        T: PointFieldValue,
+    {
+        let PointField {
+            ref name,
+            offset,
+            datatype,
+            count,
+        } = *self.field;
+        ensure!(
+            datatype == T::DATATYPE as u8 && count == T::COUNT,
+            "The field '{name}' has datatype {datatype} and count {count}, but expect datatype {} and count {}",
+            T::DATATYPE as u8,
+            T::COUNT
+        );
+
+        let offset = offset as usize;
+        let end = offset + T::DATATYPE.size() * count as usize;
+        ensure!(
+            end <= self.pcd.point_step as usize,
+            "The field '{name}' exceeds the point step {}",
+            self.pcd.point_step
+        );
+
+        let is_bigendian = self.pcd.is_bigendian;
+        let iter =
+            point_chunks(self.pcd)?.map(move |point| T::read_field(&point[offset..], is_bigendian));
+        Ok(iter)
+    }
+
+    pub fn to_vec<T>(&self) -> Result<Vec<T>>
+    where
+        T: PointFieldValue,
+    {
+        Ok(self.iter()?.collect())
+    }
+}
+
+/// Decodes a named field across all points of a point cloud.
+pub fn pointcloud2_field_iter<'a, T>(
+    pcd: &'a PointCloud2,
+    name: &str,
+) -> Result<impl Iterator<Item = T> + Sync + Send + 'a>
+where
+    T: PointFieldValue,
+{
+    let Some(field) = FieldAccessor::new(pcd, name) else {
+        bail!("The point cloud has no field named '{name}'");
+    };
+    field.iter()
+}
+
+/// Decodes the points of a point cloud to a user-defined point type.
+pub fn pointcloud2_to_typed_iter<T>(
+    pcd: &PointCloud2,
+) -> Result<impl Iterator<Item = T> + Sync + Send + '_>
+where
+    T: PointCloud2Point,
+{
+    let offsets: Vec<usize> = T::point_fields()
+        .iter()
+        .map(|expect| {
+            let Some(field) = pcd.fields.iter().find(|field| field.name == expect.name) else {
+                bail!("The point cloud has no field named '{}'", expect.name);
+            };
+            ensure!(
+                field.datatype == expect.datatype && field.count == expect.count,
+                "The field '{}' has datatype {} and count {}, but the point type expects datatype {} and count {}",
+                field.name,
+                field.datatype,
+                field.count,
+                expect.datatype,
+                expect.count
+            );
+
+            let Some(datatype) = PointFieldType::from_u8(field.datatype) else {
+                bail!("Unsupported datatype {} of field '{}'", field.datatype, field.name);
+            };
+            let end = field.offset as usize + datatype.size() * field.count as usize;
+            ensure!(
+                end <= pcd.point_step as usize,
+                "The field '{}' exceeds the point step {}",
+                field.name,
+                pcd.point_step
+            );
+
+            Ok(field.offset as usize)
+        })
+        .collect::<Result<_>>()?;
+
+    let is_bigendian = pcd.is_bigendian;
+    let iter = point_chunks(pcd)?.map(move |point| T::read_point(point, &offsets, is_bigendian));
+    Ok(iter)
+}
+
+/// Builds an unorganized point cloud from points of a user-defined
+/// point type. The cloud is dense if the floating-point x, y and z
+/// fields of all points are finite.
+pub fn points_to_pointcloud2<T>(points: &[T], header: Header) -> PointCloud2
+where
+    T: PointCloud2Point,
+{
+    let point_step = T::point_step() as usize;
+    let is_bigendian = cfg!(target_endian = "big");
+    let mut data = vec![0u8; point_step * points.len()];
+
+    data.chunks_exact_mut(point_step.max(1))
+        .zip(points)
+        .for_each(|(bytes, point)| point.write_point(bytes, is_bigendian));
+
+    let fields = T::point_fields();
+    let xyz_fields: Vec<&PointField> = fields
+        .iter()
+        .filter(|field| matches!(field.name.as_str(), "x" | "y" | "z"))
+        .collect();
+    let is_dense = data.chunks_exact(point_step.max(1)).all(|point| {
+        xyz_fields
+            .iter()
+            .all(|field| is_finite_field(point, field, is_bigendian))
+    });
+
+    PointCloud2 {
+        header,
+        height: 1,
+        width: points.len() as u32,
+        fields,
+        is_bigendian,
+        point_step: point_step as u32,
+        row_step: (point_step * points.len()) as u32,
+        data,
+        is_dense,
+    }
+}
+
+/// Checks whether the values of a floating-point field are finite.
+/// Fields of other types are always finite.
+fn is_finite_field(point: &[u8], field: &PointField, is_bigendian: bool) -> bool {
+    let offset = field.offset as usize;
+    let count = field.count as usize;
+
+    match PointFieldType::from_u8(field.datatype) {
+        Some(PointFieldType::F32) => (0..count)
+            .all(|idx| f32::read_field(&point[offset + idx * 4..], is_bigendian).is_finite()),
+        Some(PointFieldType::F64) => (0..count)
+            .all(|idx| f64::read_field(&point[offset + idx * 8..], is_bigendian).is_finite()),
+        _ => true,
+    }
+}
@@ -0,0 +1,516 @@
+use super::{check_image, ChannelType, ImageEncoding};
+use anyhow::{ensure, Result};
+use r2r::sensor_msgs::msg::Image;
+use std::{any::type_name, marker::PhantomData, mem};
+
+/// Pixel types of [ImageView] and [ImageViewMut].
+pub trait ImagePixel: Copy + 'static {
+    /// The number of bytes of a pixel.
+    const SIZE: usize;
+
+    /// Checks whether images in the encoding can be viewed as pixels
+    /// of this type.
+    fn is_compatible(encoding: ImageEncoding) -> bool;
+
+    /// Decodes a pixel from bytes in the given byte order.
+    fn from_bytes(bytes: &[u8], is_bigendian: bool) -> Self;
+
+    /// Encodes the pixel to bytes in the given byte order.
+    fn write_bytes(&self, bytes: &mut [u8], is_bigendian: bool);
+}
+
+/// The channel values of pixel types.
+trait Element: Copy {
+    fn read(bytes: &[u8], is_bigendian: bool) -> Self;
+    fn write(&self, bytes: &mut [u8], is_bigendian: bool);
+}
+
+macro_rules! impl_element {
+    ($ty:ty) => {
+        impl Element for $ty {
+            fn read(bytes: &[u8], is_bigendian: bool) -> Self {
+                let array = bytes[0..mem::size_of::<$ty>()].try_into().unwrap();
+                if is_bigendian {
+                    <$ty>::from_be_bytes(array)
+                } else {
+                    <$ty>::from_le_bytes(array)
+                }
+            }
+
+            fn write(&self, bytes: &mut [u8], is_bigendian: bool) {
+                let array = if is_bigendian {
+                    self.to_be_bytes()
+                } else {
+                    self.to_le_bytes()
+                };
+                bytes[0..mem::size_of::<$ty>()].copy_from_slice(&array);
+            }
+        }
+    };
+}
+
+impl_element!(u8);
+impl_element!(u16);
+impl_element!(f32);
+impl_element!(f64);
+
+impl<T, const N: usize> Element for [T; N]
+where
+    T: Element,
+{
+    fn read(bytes: &[u8], is_bigendian: bool) -> Self {
+        let size = mem::size_of::<T>();
+        std::array::from_fn(|idx| T::read(&bytes[idx * size..], is_bigendian))
+    }
+
+    fn write(&self, bytes: &mut [u8], is_bigendian: bool) {
+        let size = mem::size_of::<T>();
+        self.iter()
+            .enumerate()
+            .for_each(|(idx, val)| val.write(&mut bytes[idx * size..], is_bigendian));
+    }
+}
+
+macro_rules! define_pixel {
+    ($(#[$attr:meta])* $name:ident($elem:ty), $($encoding:pat_param)|+) => {
+        $(#[$attr])*
+        #[derive(Debug, Clone, Copy, Default, PartialEq)]
+        pub struct $name(pub $elem);
+
+        impl ImagePixel for $name {
+            const SIZE: usize = mem::size_of::<$elem>();
+
+            fn is_compatible(encoding: ImageEncoding) -> bool {
+                use ImageEncoding as E;
+                matches!(encoding, $($encoding)|+)
+            }
+
+            fn from_bytes(bytes: &[u8], is_bigendian: bool) -> Self {
+                Self(<$elem>::read(bytes, is_bigendian))
+            }
+
+            fn write_bytes(&self, bytes: &mut [u8], is_bigendian: bool) {
+                self.0.write(bytes, is_bigendian)
+            }
+        }
+    };
+}
+
+define_pixel!(
+    /// An 8-bit single-channel pixel of mono8, 8UC1 and 8-bit Bayer
+    /// images.
+    Mono8(u8),
+    E::Mono8
+        | E::Generic {
+            channel_type: ChannelType::U8,
+            channels: 1
+        }
+        | E::BayerRggb8
+        | E::BayerBggr8
+        | E::BayerGbrg8
+        | E::BayerGrbg8
+);
+define_pixel!(
+    /// A 16-bit single-channel pixel of mono16, 16UC1 and 16-bit Bayer
+    /// images.
+    Mono16(u16),
+    E::Mono16
+        | E::Generic {
+            channel_type: ChannelType::U16,
+            channels: 1
+        }
+        | E::BayerRggb16
+        | E::BayerBggr16
+        | E::BayerGbrg16
+        | E::BayerGrbg16
+);
+define_pixel!(Rgb8([u8; 3]), E::Rgb8);
+define_pixel!(Bgr8([u8; 3]), E::Bgr8);
+define_pixel!(Rgba8([u8; 4]), E::Rgba8);
+define_pixel!(Bgra8([u8; 4]), E::Bgra8);
+define_pixel!(Rgb16([u16; 3]), E::Rgb16);
+define_pixel!(Bgr16([u16; 3]), E::Bgr16);
+define_pixel!(Rgba16([u16; 4]), E::Rgba16);
+define_pixel!(Bgra16([u16; 4]), E::Bgra16);
+define_pixel!(
+    /// A pixel of 32FC1 images, such as depth images in meters.
+    F32(f32),
+    E::Generic {
+        channel_type: ChannelType::F32,
+        channels: 1
+    }
+);
+define_pixel!(
+    /// A pixel of 64FC1 images.
+    F64(f64),
+    E::Generic {
+        channel_type: ChannelType::F64,
+        channels: 1
+    }
+);
+
+/// A typed read-only view over the pixels of an [Image] without
+/// copying the data.
+///
+/// Row padding is skipped and multi-byte values are decoded in the
+/// byte order of the image.
+#[derive(Debug, Clone, Copy)]
+pub struct ImageView<'a, P> {
+    data: &'a [u8],
+    width: usize,
+    height: usize,
+    row_step: usize,
+    is_bigendian: bool,
+    _phantom: PhantomData<P>,
+}
+
+impl<'a, P> ImageView<'a, P>
+where
+    P: ImagePixel,
+{
+    /// Creates a view after checking the encoding against `P`.
+    pub fn new(image: &'a Image) -> Result<Self> {
+        let encoding = check_image(image)?;
+        ensure!(
+            P::is_compatible(encoding),
+            "image format '{encoding}' cannot be viewed as {}",
+            type_name::<P>()
+        );
+
+        Ok(Self {
+            data: &image.data,
+            width: image.width as usize,
+            height: image.height as usize,
+            row_step: image.step as usize,
+            is_bigendian: image.is_bigendian != 0,
+            _phantom: PhantomData,
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the pixel at the column `x` and the row `y`, or `None`
+    /// if it is out of bounds.
+    pub fn get(&self, x: usize, y: usize) -> Option<P> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        let offset = y * self.row_step + x * P::SIZE;
+        Some(P::from_bytes(
+            &self.data[offset..offset + P::SIZE],
+            self.is_bigendian,
+        ))
+    }
+
+    /// Returns the bytes of a row without padding.
+    pub fn row_bytes(&self, y: usize) -> Option<&'a [u8]> {
+        if y >= self.height {
+            return None;
+        }
+        let offset = y * self.row_step;
+        Some(&self.data[offset..offset + self.width * P::SIZE])
+    }
+
+    /// Iterates over the pixels of a row.
+    pub fn row(&self, y: usize) -> Option<impl Iterator<Item = P> + 'a> {
+        let is_bigendian = self.is_bigendian;
+        let row = self.row_bytes(y)?;
+        Some(
+            row.chunks_exact(P::SIZE)
+                .map(move |bytes| P::from_bytes(bytes, is_bigendian)),
+        )
+    }
+
+    /// Iterates over the rows, each of which is an iterator of pixels.
+    pub fn rows(&self) -> impl Iterator<Item = impl Iterator<Item = P> + 'a> + 'a {
+        let view = *self;
+        (0..self.height).map(move |y| view.row(y).unwrap())
+    }
+
+    /// Iterates over the pixels in row-major order.
+    pub fn pixels(&self) -> impl Iterator<Item = P> + 'a {
+        self.rows().flatten()
+    }
+
+    /// Iterates over the pixels in row-major order with their `(x, y)`
+    /// coordinates.
+    pub fn enumerate_pixels(&self) -> impl Iterator<Item = (usize, usize, P)> + 'a {
+        self.rows()
+            .enumerate()
+            .flat_map(|(y, row)| row.enumerate().map(move |(x, pixel)| (x, y, pixel)))
+    }
+}
+
+/// A typed mutable view over the pixels of an [Image] without copying
+/// the data.
+///
+/// Pixels are written in the byte order of the image.
+#[derive(Debug)]
+pub struct ImageViewMut<'a, P> {
+    data: &'a mut [u8],
+    width: usize,
+    height: usize,
+    row_step: usize,
+    is_bigendian: bool,
+    _phantom: PhantomData<P>,
+}
+
+impl<'a, P> ImageViewMut<'a, P>
+where
+    P: ImagePixel,
+{
+    /// Creates a view after checking the encoding against `P`.
+    pub fn new(image: &'a mut Image) -> Result<Self> {
+        let encoding = check_image(image)?;
+        ensure!(
+            P::is_compatible(encoding),
+            "image format '{encoding}' cannot be viewed as {}",
+            type_name::<P>()
+        );
+
+        Ok(Self {
+            width: image.width as usize,
+            height: image.height as usize,
+            row_step: image.step as usize,
+            is_bigendian: image.is_bigendian != 0,
+            data: &mut image.data,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Borrows a read-only view.
+    pub fn as_view(&self) -> ImageView<'_, P> {
+        ImageView {
+            data: self.data,
+            width: self.width,
+            height: self.height,
+            row_step: self.row_step,
+            is_bigendian: self.is_bigendian,
+            _phantom: PhantomData,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the pixel at the column `x` and the row `y`, or `None`
+    /// if it is out of bounds.
+    pub fn get(&self, x: usize, y: usize) -> Option<P> {
+        self.as_view().get(x, y)
+    }
+
+    /// Writes the pixel at the column `x` and the row `y`.
+    pub fn set(&mut self, x: usize, y: usize, pixel: P) -> Result<()> {
+        let Self { width, height, .. } = *self;
+        ensure!(
+            x < width && y < height,
+            "Pixel ({x}, {y}) is out of the {width}x{height} image"
+        );
+
+        let offset = y * self.row_step + x * P::SIZE;
+        pixel.write_bytes(&mut self.data[offset..offset + P::SIZE], self.is_bigendian);
+        Ok(())
+    }
+
+    /// Returns the bytes of a row without padding.
+    pub fn row_bytes_mut(&mut self, y: usize) -> Option<&mut [u8]> {
+        if y >= self.height {
+            return None;
+        }
+        let offset = y * self.row_step;
+        Some(&mut self.data[offset..offset + self.width * P::SIZE])
+    }
+
+    /// Calls the closure on each pixel with its `(x, y)` coordinates
+    /// and writes back the modified pixel.
+    pub fn for_each_mut<F>(&mut self, mut f: F)
+    where
+        F: FnMut(usize, usize, &mut P),
+    {
+        let Self {
+            width,
+            height,
+            row_step,
+            is_bigendian,
+            ..
+        } = *self;
+
+        if width == 0 {
+            return;
+        }
+
+        self.data
+            .chunks_mut(row_step)
+            .take(height)
+            .enumerate()
+            .for_each(|(y, row)| {
+                row[0..width * P::SIZE]
+                    .chunks_exact_mut(P::SIZE)
+                    .enumerate()
+                    .for_each(|(x, bytes)| {
+                        let mut pixel = P::from_bytes(bytes, is_bigendian);
+                        f(x, y, &mut pixel);
+                        pixel.write_bytes(bytes, is_bigendian);
+                    });
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 2x2 rgb8 image whose rows are padded by 2 bytes of 0xee.
+    fn padded_rgb8() -> Image {
+        Image {
+            height: 2,
+            width: 2,
+            encoding: "rgb8".to_string(),
+            step: 8,
+            data: vec![
+                1, 2, 3, 4, 5, 6, 0xee, 0xee, 7, 8, 9, 10, 11, 12, 0xee, 0xee,
+            ],
+            ..Default::default()
+        }
+    }
+
+    fn mono16(is_bigendian: bool) -> Image {
+        let data = [0x0102u16, 0x0304]
+            .into_iter()
+            .flat_map(|val| {
+                if is_bigendian {
+                    val.to_be_bytes()
+                } else {
+                    val.to_le_bytes()
+                }
+            })
+            .collect();
+
+        Image {
+            height: 1,
+            width: 2,
+            encoding: "mono16".to_string(),
+            is_bigendian: is_bigendian as u8,
+            step: 4,
+            data,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn view_skips_row_padding() {
+        let image = padded_rgb8();
+        let view = ImageView::<Rgb8>::new(&image).unwrap();
+
+        assert_eq!((view.width(), view.height()), (2, 2));
+        assert_eq!(view.get(1, 0), Some(Rgb8([4, 5, 6])));
+        assert_eq!(view.get(0, 1), Some(Rgb8([7, 8, 9])));
+        assert_eq!(view.get(2, 0), None);
+        assert_eq!(view.get(0, 2), None);
+
+        assert_eq!(view.row_bytes(1), Some(&[7, 8, 9, 10, 11, 12][..]));
+        assert_eq!(view.row_bytes(2), None);
+        assert_eq!(
+            view.row(0).unwrap().collect::<Vec<_>>(),
+            [Rgb8([1, 2, 3]), Rgb8([4, 5, 6])]
+        );
+        assert_eq!(
+            view.pixels().collect::<Vec<_>>(),
+            [
+                Rgb8([1, 2, 3]),
+                Rgb8([4, 5, 6]),
+                Rgb8([7, 8, 9]),
+                Rgb8([10, 11, 12])
+            ]
+        );
+        assert_eq!(
+            view.enumerate_pixels().last(),
+            Some((1, 1, Rgb8([10, 11, 12])))
+        );
+    }
+
+    #[test]
+    fn view_byte_order() {
+        for is_bigendian in [false, true] {
+            let image = mono16(is_bigendian);
+            let view = ImageView::<Mono16>::new(&image).unwrap();
+            assert_eq!(
+                view.pixels().collect::<Vec<_>>(),
+                [Mono16(0x0102), Mono16(0x0304)]
+            );
+        }
+
+        let image = Image {
+            height: 1,
+            width: 1,
+            encoding: "32FC1".to_string(),
+            is_bigendian: 1,
+            step: 4,
+            data: 1.5f32.to_be_bytes().to_vec(),
+            ..Default::default()
+        };
+        assert_eq!(
+            ImageView::<F32>::new(&image).unwrap().get(0, 0),
+            Some(F32(1.5))
+        );
+    }
+
+    #[test]
+    fn view_wrong_pixel_type() {
+        let image = padded_rgb8();
+        assert!(ImageView::<Bgr8>::new(&image).is_err());
+        assert!(ImageView::<Rgb16>::new(&image).is_err());
+        assert!(ImageView::<Mono8>::new(&image).is_err());
+        assert!(ImageView::<Mono8>::new(&mono16(false)).is_err());
+        assert!(ImageView::<F32>::new(&mono16(false)).is_err());
+
+        let mut image = image;
+        image.step = 5;
+        assert!(ImageView::<Rgb8>::new(&image).is_err());
+    }
+
+    #[test]
+    fn view_mut_writes_pixels() {
+        let mut image = padded_rgb8();
+        let mut view = ImageViewMut::<Rgb8>::new(&mut image).unwrap();
+
+        view.set(1, 1, Rgb8([20, 21, 22])).unwrap();
+        assert!(view.set(2, 1, Rgb8([0; 3])).is_err());
+        assert_eq!(view.get(1, 1), Some(Rgb8([20, 21, 22])));
+
+        view.for_each_mut(|x, y, pixel| pixel.0[0] = (y * 2 + x) as u8);
+        view.row_bytes_mut(0).unwrap()[1] = 99;
+
+        assert_eq!(
+            image.data,
+            [0, 99, 3, 1, 5, 6, 0xee, 0xee, 2, 8, 9, 3, 21, 22, 0xee, 0xee]
+        );
+    }
+
+    #[test]
+    fn view_mut_byte_order() {
+        for is_bigendian in [false, true] {
+            let mut image = mono16(is_bigendian);
+            let mut view = ImageViewMut::<Mono16>::new(&mut image).unwrap();
+            view.set(0, 0, Mono16(0x0a0b)).unwrap();
+
+            let expect = if is_bigendian {
+                [0x0a, 0x0b]
+            } else {
+                [0x0b, 0x0a]
+            };
+            assert_eq!(image.data[0..2], expect);
+        }
+    }
+}
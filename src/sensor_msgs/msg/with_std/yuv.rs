@@ -0,0 +1,351 @@
+use super::check_image;
+use anyhow::{bail, ensure, Result};
+use r2r::sensor_msgs::msg::Image;
+
+/// The number of pixels converted at once by the row kernel. The
+/// fixed block size lets the compiler vectorize the arithmetic.
+const BLOCK_SIZE: usize = 16;
+
+/// YUV layouts supported by the pure-Rust color converter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum YuvFormat {
+    /// Packed 4:2:2 in U, Y0, V, Y1 order.
+    Uyvy,
+    /// Packed 4:2:2 in Y0, U, Y1, V order.
+    Yuyv,
+    /// Planar 4:2:0 with an interleaved UV plane.
+    Nv12,
+    /// Planar 4:2:0 with an interleaved VU plane.
+    Nv21,
+    /// Planar 4:2:0 with separate U and V planes.
+    I420,
+}
+
+impl YuvFormat {
+    /// Returns the minimum number of data bytes of an image with
+    /// `row_step` bytes per row of luma.
+    pub fn data_size(&self, row_step: usize, height: usize) -> usize {
+        match self {
+            Self::Uyvy | Self::Yuyv => row_step * height,
+            Self::Nv12 | Self::Nv21 => row_step * height + row_step * height / 2,
+            Self::I420 => row_step * height + row_step / 2 * height / 2 * 2,
+        }
+    }
+}
+
+/// Channel order of the converted RGB image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RgbOrder {
+    Rgb,
+    Bgr,
+}
+
+/// Converts a YUV image to an rgb8 or bgr8 image.
+pub fn image_yuv_to_rgb8(image: &Image, order: RgbOrder) -> Result<Image> {
+    let Image {
+        ref header,
+        height,
+        width,
+        step,
+        ref data,
+        ..
+    } = *image;
+
+    let encoding = check_image(image)?;
+    let Some(format) = encoding.yuv_format() else {
+        bail!("unsupported YUV image format '{encoding}'");
+    };
+    let width = width as usize;
+    let height = height as usize;
+    let out_step = width * 3;
+    let mut out = vec![0u8; out_step * height];
+    yuv_to_rgb8(format, data, width, height, step as usize, order, &mut out)?;
+
+    let encoding = match order {
+        RgbOrder::Rgb => "rgb8",
+        RgbOrder::Bgr => "bgr8",
+    };
+
+    Ok(Image {
+        header: header.clone(),
+        height: height as u32,
+        width: width as u32,
+        encoding: encoding.to_string(),
+        is_bigendian: 0,
+        step: out_step as u32,
+        data: out,
+    })
+}
+
+/// Converts YUV pixels to packed 8-bit RGB or BGR pixels.
+///
+/// The `src` buffer has `row_step` bytes per row of luma and `dst`
+/// receives `width * 3` bytes per row. The BT.601 limited range
+/// coefficients are used, which agree with OpenCV's conversions.
+pub fn yuv_to_rgb8(
+    format: YuvFormat,
+    src: &[u8],
+    width: usize,
+    height: usize,
+    row_step: usize,
+    order: RgbOrder,
+    dst: &mut [u8],
+) -> Result<()> {
+    let is_packed = matches!(format, YuvFormat::Uyvy | YuvFormat::Yuyv);
+    let min_step = if is_packed { width * 2 } else { width };
+
+    ensure!(
+        width.is_multiple_of(2),
+        "YUV image width must be even, but get {width}"
+    );
+    ensure!(
+        is_packed || height.is_multiple_of(2),
+        "{format:?} image height must be even, but get {height}"
+    );
+    ensure!(
+        row_step >= min_step,
+        "Invalid step {row_step}. Expect at least {min_step} bytes."
+    );
+    ensure!(
+        src.len() >= format.data_size(row_step, height),
+        "Invalid data size. Expect {} bytes, but get {} bytes.",
+        format.data_size(row_step, height),
+        src.len()
+    );
+    ensure!(
+        dst.len() >= width * height * 3,
+        "Output buffer is too small. Expect {} bytes, but get {} bytes.",
+        width * height * 3,
+        dst.len()
+    );
+
+    if width == 0 || height == 0 {
+        return Ok(());
+    }
+
+    let chroma_width = width / 2;
+    let mut y_row = vec![0u8; width];
+    let mut u_row = vec![0u8; chroma_width];
+    let mut v_row = vec![0u8; chroma_width];
+    let luma_plane = &src[0..row_step * height];
+    let chroma_plane = &src[row_step * height..];
+
+    dst.chunks_exact_mut(width * 3)
+        .take(height)
+        .enumerate()
+        .for_each(|(row, out)| {
+            let luma = &luma_plane[row * row_step..];
+
+            match format {
+                YuvFormat::Uyvy | YuvFormat::Yuyv => {
+                    let (y_idx, u_idx, v_idx) = match format {
+                        YuvFormat::Uyvy => (1, 0, 2),
+                        _ => (0, 1, 3),
+                    };
+
+                    luma[0..width * 2]
+                        .chunks_exact(4)
+                        .zip(y_row.chunks_exact_mut(2))
+                        .zip(u_row.iter_mut().zip(&mut v_row))
+                        .for_each(|((bytes, y), (u, v))| {
+                            y[0] = bytes[y_idx];
+                            y[1] = bytes[y_idx + 2];
+                            *u = bytes[u_idx];
+                            *v = bytes[v_idx];
+                        });
+                }
+                YuvFormat::Nv12 | YuvFormat::Nv21 => {
+                    let (u_idx, v_idx) = match format {
+                        YuvFormat::Nv12 => (0, 1),
+                        _ => (1, 0),
+                    };
+                    let chroma = &chroma_plane[row / 2 * row_step..];
+
+                    y_row.copy_from_slice(&luma[0..width]);
+                    chroma[0..width]
+                        .chunks_exact(2)
+                        .zip(u_row.iter_mut().zip(&mut v_row))
+                        .for_each(|(bytes, (u, v))| {
+                            *u = bytes[u_idx];
+                            *v = bytes[v_idx];
+                        });
+                }
+                YuvFormat::I420 => {
+                    let chroma_step = row_step / 2;
+                    let (u_plane, v_plane) = chroma_plane.split_at(chroma_step * height / 2);
+                    let offset = row / 2 * chroma_step;
+
+                    y_row.copy_from_slice(&luma[0..width]);
+                    u_row.copy_from_slice(&u_plane[offset..offset + chroma_width]);
+                    v_row.copy_from_slice(&v_plane[offset..offset + chroma_width]);
+                }
+            }
+
+            convert_row(&y_row, &u_row, &v_row, order, out);
+        });
+
+    Ok(())
+}
+
+/// Converts a row of luma samples and horizontally subsampled chroma
+/// samples to RGB.
+fn convert_row(y: &[u8], u: &[u8], v: &[u8], order: RgbOrder, out: &mut [u8]) {
+    let (r_idx, b_idx) = match order {
+        RgbOrder::Rgb => (0, 2),
+        RgbOrder::Bgr => (2, 0),
+    };
+
+    let mut y_blocks = y.chunks_exact(BLOCK_SIZE);
+    let mut u_blocks = u.chunks_exact(BLOCK_SIZE / 2);
+    let mut v_blocks = v.chunks_exact(BLOCK_SIZE / 2);
+    let mut out_blocks = out.chunks_exact_mut(BLOCK_SIZE * 3);
+
+    (&mut y_blocks)
+        .zip(&mut u_blocks)
+        .zip(&mut v_blocks)
+        .zip(&mut out_blocks)
+        .for_each(|(((y, u), v), out)| {
+            let y: &[u8; BLOCK_SIZE] = y.try_into().unwrap();
+            let mut r = [0u8; BLOCK_SIZE];
+            let mut g = [0u8; BLOCK_SIZE];
+            let mut b = [0u8; BLOCK_SIZE];
+
+            for idx in 0..BLOCK_SIZE {
+                [r[idx], g[idx], b[idx]] = yuv_to_rgb(y[idx], u[idx / 2], v[idx / 2]);
+            }
+
+            out.chunks_exact_mut(3)
+                .enumerate()
+                .for_each(|(idx, pixel)| {
+                    pixel[r_idx] = r[idx];
+                    pixel[1] = g[idx];
+                    pixel[b_idx] = b[idx];
+                });
+        });
+
+    // Convert the remaining pixels that do not fill a block.
+    let y = y_blocks.remainder();
+    let u = u_blocks.remainder();
+    let v = v_blocks.remainder();
+    let out = out_blocks.into_remainder();
+
+    out.chunks_exact_mut(3)
+        .zip(y)
+        .enumerate()
+        .for_each(|(idx, (pixel, &y))| {
+            let [r, g, b] = yuv_to_rgb(y, u[idx / 2], v[idx / 2]);
+            pixel[r_idx] = r;
+            pixel[1] = g;
+            pixel[b_idx] = b;
+        });
+}
+
+/// Converts a YUV sample to RGB using BT.601 limited range
+/// coefficients in 8-bit fixed-point arithmetic.
+#[inline(always)]
+fn yuv_to_rgb(y: u8, u: u8, v: u8) -> [u8; 3] {
+    let c = (y as i32 - 16) * 298;
+    let d = u as i32 - 128;
+    let e = v as i32 - 128;
+
+    let r = (c + 409 * e + 128) >> 8;
+    let g = (c - 100 * d - 208 * e + 128) >> 8;
+    let b = (c + 516 * d + 128) >> 8;
+
+    [
+        r.clamp(0, 255) as u8,
+        g.clamp(0, 255) as u8,
+        b.clamp(0, 255) as u8,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLACK: [u8; 3] = [16, 128, 128];
+    const WHITE: [u8; 3] = [235, 128, 128];
+    const RED: [u8; 3] = [81, 90, 240];
+
+    /// 18 pixels wide, so that a block of 16 and a tail of 2 pixels
+    /// are converted.
+    const WIDTH: usize = 18;
+    const HEIGHT: usize = 2;
+
+    /// Returns the YUV sample of a pair of pixels.
+    fn pair_sample(pair: usize) -> [u8; 3] {
+        [BLACK, WHITE, RED][pair % 3]
+    }
+
+    fn expected_rgb(pair: usize) -> [u8; 3] {
+        [[0, 0, 0], [255, 255, 255], [255, 0, 0]][pair % 3]
+    }
+
+    fn encode(format: YuvFormat) -> Vec<u8> {
+        let pairs = || (0..WIDTH / 2).map(pair_sample);
+        let luma_plane = || (0..HEIGHT).flat_map(move |_| pairs().flat_map(|[y, _, _]| [y, y]));
+
+        match format {
+            YuvFormat::Uyvy => (0..HEIGHT)
+                .flat_map(|_| pairs().flat_map(|[y, u, v]| [u, y, v, y]))
+                .collect(),
+            YuvFormat::Yuyv => (0..HEIGHT)
+                .flat_map(|_| pairs().flat_map(|[y, u, v]| [y, u, y, v]))
+                .collect(),
+            YuvFormat::Nv12 => luma_plane()
+                .chain((0..HEIGHT / 2).flat_map(|_| pairs().flat_map(|[_, u, v]| [u, v])))
+                .collect(),
+            YuvFormat::Nv21 => luma_plane()
+                .chain((0..HEIGHT / 2).flat_map(|_| pairs().flat_map(|[_, u, v]| [v, u])))
+                .collect(),
+            YuvFormat::I420 => luma_plane()
+                .chain((0..HEIGHT / 2).flat_map(|_| pairs().map(|[_, u, _]| u)))
+                .chain((0..HEIGHT / 2).flat_map(|_| pairs().map(|[_, _, v]| v)))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn yuv_to_rgb_known_values() {
+        assert_eq!(yuv_to_rgb(16, 128, 128), [0, 0, 0]);
+        assert_eq!(yuv_to_rgb(235, 128, 128), [255, 255, 255]);
+        assert_eq!(yuv_to_rgb(126, 128, 128), [128, 128, 128]);
+        assert_eq!(yuv_to_rgb(81, 90, 240), [255, 0, 0]);
+        // Rounding in the fixed-point arithmetic leaves 1 in blue.
+        assert_eq!(yuv_to_rgb(145, 54, 34), [0, 255, 1]);
+        assert_eq!(yuv_to_rgb(41, 240, 110), [0, 0, 255]);
+    }
+
+    #[test]
+    fn convert_all_formats() {
+        let formats = [
+            YuvFormat::Uyvy,
+            YuvFormat::Yuyv,
+            YuvFormat::Nv12,
+            YuvFormat::Nv21,
+            YuvFormat::I420,
+        ];
+
+        for format in formats {
+            let src = encode(format);
+            let row_step = match format {
+                YuvFormat::Uyvy | YuvFormat::Yuyv => WIDTH * 2,
+                _ => WIDTH,
+            };
+            assert_eq!(src.len(), format.data_size(row_step, HEIGHT));
+
+            for order in [RgbOrder::Rgb, RgbOrder::Bgr] {
+                let mut dst = vec![0u8; WIDTH * HEIGHT * 3];
+                yuv_to_rgb8(format, &src, WIDTH, HEIGHT, row_step, order, &mut dst).unwrap();
+
+                for (idx, pixel) in dst.chunks_exact(3).enumerate() {
+                    let mut expect = expected_rgb(idx % WIDTH / 2);
+                    if order == RgbOrder::Bgr {
+                        expect.reverse();
+                    }
+                    assert_eq!(pixel, expect, "{format:?} {order:?} pixel {idx}");
+                }
+            }
+        }
+    }
+}
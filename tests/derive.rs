@@ -0,0 +1,131 @@
+#![cfg(feature = "derive")]
+
+use r2r::{sensor_msgs::msg::PointCloud2, std_msgs::msg::Header};
+use r2r_msg_ext::sensor_msgs::msg::{PointCloud2Ext, PointCloud2Point, PointFieldType};
+
+#[derive(Debug, Clone, Copy, PartialEq, PointCloud2Point)]
+#[repr(C)]
+struct XyzIr {
+    x: f32,
+    y: f32,
+    z: f32,
+    intensity: f32,
+    ring: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, PointCloud2Point)]
+#[repr(C)]
+struct Xyz {
+    x: f32,
+    y: f32,
+    z: f32,
+}
+
+mod renamed {
+    pub use r2r_msg_ext as ext;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, PointCloud2Point)]
+#[point(crate = "renamed::ext")]
+#[repr(C)]
+struct Labeled {
+    label: u8,
+    #[point_field(name = "normal_x")]
+    normal: [f64; 3],
+}
+
+fn xyzir_points() -> Vec<XyzIr> {
+    (0..5)
+        .map(|idx| XyzIr {
+            x: idx as f32,
+            y: -(idx as f32),
+            z: 0.5 * idx as f32,
+            intensity: 10.0 * idx as f32,
+            ring: 1000 + idx,
+        })
+        .collect()
+}
+
+#[test]
+fn derived_point_fields() {
+    let fields: Vec<_> = XyzIr::point_fields()
+        .into_iter()
+        .map(|field| (field.name, field.offset, field.datatype, field.count))
+        .collect();
+    let f32_type = PointFieldType::F32 as u8;
+    assert_eq!(
+        fields,
+        [
+            ("x".to_string(), 0, f32_type, 1),
+            ("y".to_string(), 4, f32_type, 1),
+            ("z".to_string(), 8, f32_type, 1),
+            ("intensity".to_string(), 12, f32_type, 1),
+            ("ring".to_string(), 16, PointFieldType::U16 as u8, 1),
+        ]
+    );
+    // The size includes the trailing padding.
+    assert_eq!(XyzIr::point_step(), 20);
+
+    let fields: Vec<_> = Labeled::point_fields()
+        .into_iter()
+        .map(|field| (field.name, field.offset, field.datatype, field.count))
+        .collect();
+    assert_eq!(
+        fields,
+        [
+            ("label".to_string(), 0, PointFieldType::U8 as u8, 1),
+            ("normal_x".to_string(), 8, PointFieldType::F64 as u8, 3),
+        ]
+    );
+    assert_eq!(Labeled::point_step(), 32);
+}
+
+#[test]
+fn derived_point_roundtrip() {
+    let points = xyzir_points();
+    let pcd = PointCloud2::from_points(&points, Header::default());
+    assert_eq!((pcd.width, pcd.height), (5, 1));
+    assert_eq!(pcd.point_step, 20);
+    assert_eq!(pcd.row_step, 100);
+    assert_eq!(pcd.data.len(), 100);
+    assert!(pcd.is_dense);
+
+    let decoded: Vec<XyzIr> = pcd.typed_iter().unwrap().collect();
+    assert_eq!(decoded, points);
+
+    // A point type with a subset of the fields.
+    let xyz: Vec<Xyz> = pcd.typed_iter().unwrap().collect();
+    let expect: Vec<_> = points
+        .iter()
+        .map(|&XyzIr { x, y, z, .. }| Xyz { x, y, z })
+        .collect();
+    assert_eq!(xyz, expect);
+
+    // The fields do not match.
+    assert!(pcd.typed_iter::<Labeled>().is_err());
+
+    let labeled = [Labeled {
+        label: 7,
+        normal: [0.0, 0.6, 0.8],
+    }];
+    let pcd = PointCloud2::from_points(&labeled, Header::default());
+    let decoded: Vec<Labeled> = pcd.typed_iter().unwrap().collect();
+    assert_eq!(decoded, labeled);
+}
+
+#[test]
+fn derived_point_is_dense() {
+    let mut points = xyzir_points();
+    points[2].y = f32::NAN;
+    let pcd = PointCloud2::from_points(&points, Header::default());
+    assert!(!pcd.is_dense);
+
+    let decoded: Vec<XyzIr> = pcd.typed_iter().unwrap().collect();
+    assert!(decoded[2].y.is_nan());
+    assert_eq!(decoded[3], points[3]);
+
+    // Only xyz fields are checked.
+    let mut points = xyzir_points();
+    points[2].intensity = f32::INFINITY;
+    assert!(PointCloud2::from_points(&points, Header::default()).is_dense);
+}
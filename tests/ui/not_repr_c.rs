@@ -0,0 +1,9 @@
+use r2r_msg_ext::sensor_msgs::msg::PointCloud2Point;
+
+#[derive(Clone, Copy, PointCloud2Point)]
+struct NotReprC {
+    x: f32,
+    ring: u16,
+}
+
+fn main() {}
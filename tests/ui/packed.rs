@@ -0,0 +1,10 @@
+use r2r_msg_ext::sensor_msgs::msg::PointCloud2Point;
+
+#[derive(Clone, Copy, PointCloud2Point)]
+#[repr(C, packed)]
+struct Packed {
+    x: f32,
+    ring: u16,
+}
+
+fn main() {}